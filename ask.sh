@@ -0,0 +1 @@
+cargo run --example ask
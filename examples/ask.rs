@@ -0,0 +1,11 @@
+use arrows::ask;
+use arrows::Msg;
+
+fn main() {
+    //`another_actor` replies back to the sender - the reply comes back to us
+    let m = Msg::from_text("Message to another_actor");
+    match ask!("another_actor", m) {
+        Ok(reply) => println!("Reply: {}", reply),
+        Err(err) => eprintln!("No reply: {}", err),
+    }
+}
@@ -3,15 +3,15 @@ use arrows::define_actor;
 use arrows::{AnotherProducer, DemoActorProducer};
 
 fn main() {
-    let producer = DemoActorProducer::default();
+    let producer = DemoActorProducer;
     //Define actor instance with a producer instance
     define_actor!("demo_actor", producer);
 
     //Another actor producer combination
-    let another_producer = AnotherProducer::default();
+    let another_producer = AnotherProducer;
     define_actor!("another_actor", another_producer);
 
     //Create another actor instance from same producer defintion
-    let producer = AnotherProducer::default();
+    let producer = AnotherProducer;
     define_actor!("yet_another_actor", producer);
 }
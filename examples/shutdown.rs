@@ -3,5 +3,5 @@ use arrows::Addr;
 use arrows::Msg;
 
 fn main() {
-    let _rs = send!(Addr::listen_addr(), Msg::shutdown());
+    send!(Addr::listen_addr(), Msg::shutdown());
 }
@@ -33,6 +33,12 @@ struct Opt {
         required_if("hostport", "user")
     )]
     addr: Option<SocketAddr>,
+
+    #[structopt(
+        long,
+        help = "Delete processed messages as the configured retention allows and exit"
+    )]
+    compact: bool,
 }
 //cargo run --bin arrows -- -i user --addr 127.0.0.1:8181
 //cargo run --bin arrows -- -i user --addr 127.0.0.1:8181 -d /tmp
@@ -41,6 +47,9 @@ struct Opt {
 fn main() {
     let opts = Opt::from_args();
     let mut config = Config::from_env();
+    if opts.compact {
+        return compact(config, opts.db);
+    }
     match opts.hostport {
         None => return MessageListener::start(),
         Some(ref hostport) if hostport == "user" => match opts.addr {
@@ -71,3 +80,15 @@ fn main() {
     Config::re_init(config);
     MessageListener::start();
 }
+
+//Compact the backing store - which a listener may be running against
+fn compact(mut config: Config, db: Option<String>) {
+    if let Some(dbpath) = db {
+        config.set_db_path(&dbpath);
+    }
+    Config::re_init(config);
+    match arrows::catalog::compact() {
+        Ok(deleted) => println!("Compacted backing store - {} messages deleted", deleted),
+        Err(err) => eprintln!("Error compacting backing store {}", err),
+    }
+}
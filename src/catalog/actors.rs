@@ -1,19 +1,58 @@
-use crate::catalog::PanicWatch;
+use crate::catalog::{Asks, PanicWatch};
+use crate::common::context::{Detached, Effect, Resolved};
+use crate::common::executor;
 use crate::Error::{self, RegistrationError, RestorationError};
-use crate::{Actor, Addr, Config, Mail, Producer, ProducerDeserializer, RichMail};
+use crate::{
+    Actor, ActorContext, Addr, Config, Mail, Msg, Producer, ProducerDeserializer, RichMail,
+    Supervision,
+};
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
 use std::any::Any;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::panic::{catch_unwind, AssertUnwindSafe};
-use std::sync::mpsc::Sender;
+use std::sync::{mpsc::Sender, Arc};
+use std::thread;
+use std::time::{Duration, Instant};
 
 #[derive(Debug)]
 pub(super) struct Actors {
-    actor_cache: HashMap<Addr, CachedActor>,
+    actor_cache: HashMap<Addr, Entry>,
+    //Cached actors by their last use - least recently used first
+    recency: BTreeMap<u64, Addr>,
+    ticks: u64,
 }
 unsafe impl Send for Actors {}
 unsafe impl Sync for Actors {}
 type OutputChannel = Option<Sender<RichMail>>;
 
+//A cached actor behind a lock of its own - actors run side by side, each one processing a
+//message at a time. The slot is vacated if the actor gets removed while it is being held.
+//Actors handed out for a message are marked busy - their slots are not waited on while the
+//Context is held, since the actor itself might be waiting for the Context.
+pub(crate) type Slot = Arc<Mutex<Option<CachedActor>>>;
+
+//What comes out of an actor processing a message - the snapshot, if one is due, and the
+//requests made through the context. The supervision of the actor if it panics.
+pub(crate) type Outcome = Result<(Option<Snapshot>, Vec<Effect>), Supervision>;
+
+//A message an async actor is not done with yet - the future handling it along with the id
+//of the message if it was asked
+pub(crate) struct Pending(pub(crate) Detached, pub(crate) Option<u64>);
+
+pub(crate) enum Processed {
+    Done(Outcome),
+    Pending(Pending),
+}
+
+#[derive(Debug)]
+struct Entry {
+    slot: Slot,
+    tick: u64,
+    last_used: Instant,
+    busy: bool,
+}
+
 impl Actors {
     pub(super) fn new() -> Self {
         //Set panic handler for for the actors. We don't want to eject actors on the very
@@ -23,23 +62,130 @@ impl Actors {
         let _panic_watch = PanicWatch::new();
         Self {
             actor_cache: HashMap::new(),
+            recency: BTreeMap::new(),
+            ticks: 0,
         }
     }
 
-    pub(super) fn get(&self, addr: &Addr) -> Option<&CachedActor> {
-        self.actor_cache.get(addr)
+    pub(super) fn contains(&self, addr: &Addr) -> bool {
+        self.actor_cache.contains_key(addr)
+    }
+
+    //Slot of an actor busy with a message - to wait on once the Context is released
+    pub(super) fn busy_slot(&self, addr: &Addr) -> Option<Slot> {
+        self.actor_cache
+            .get(addr)
+            .filter(|entry| entry.busy)
+            .map(|entry| Arc::clone(&entry.slot))
     }
 
-    pub(super) fn get_mut(&mut self, addr: &Addr) -> Option<&mut CachedActor> {
-        self.actor_cache.get_mut(addr)
+    //Hand out an actor for a message - it is busy until released
+    pub(super) fn engage(&mut self, addr: &Addr) -> Option<Slot> {
+        let entry = self.actor_cache.get_mut(addr)?;
+        entry.busy = true;
+        Some(Arc::clone(&entry.slot))
+    }
+
+    //Actor is done with a message. One removed meanwhile is shut down here - its slot is
+    //not cached any more.
+    pub(super) fn release(&mut self, addr: &Addr, slot: &Slot) {
+        match self.actor_cache.get_mut(addr) {
+            Some(entry) if Arc::ptr_eq(&entry.slot, slot) => entry.busy = false,
+            _ => {
+                let _removed = slot.lock().take().and_then(pre_shutdown);
+            }
+        }
+    }
+
+    //Work on a cached actor. Must not be busy with a message - its slot is only ever held
+    //for a moment otherwise.
+    pub(super) fn with<R>(&self, addr: &Addr, f: impl FnOnce(&mut CachedActor) -> R) -> Option<R> {
+        let entry = self.actor_cache.get(addr)?;
+        let mut cached = entry.slot.lock();
+        cached.as_mut().map(f)
+    }
+
+    //Work on a cached actor - unless it is busy with a message
+    pub(super) fn try_with<R>(
+        &self,
+        addr: &Addr,
+        f: impl FnOnce(&mut CachedActor) -> R,
+    ) -> Option<R> {
+        if self.is_busy(addr) {
+            return None;
+        }
+        self.with(addr, f)
+    }
+
+    pub(super) fn is_busy(&self, addr: &Addr) -> bool {
+        self.actor_cache.get(addr).is_some_and(|entry| entry.busy)
     }
 
     pub(super) fn add(&mut self, addr: Addr, actor: CachedActor) -> Option<CachedActor> {
-        self.actor_cache.insert(addr, actor)
+        let entry = Entry {
+            slot: Arc::new(Mutex::new(Some(actor))),
+            tick: self.next_tick(&addr),
+            last_used: Instant::now(),
+            busy: false,
+        };
+        let replaced = self.actor_cache.insert(addr, entry)?;
+        self.recency.remove(&replaced.tick);
+        Self::vacate(replaced)
     }
 
     pub(super) fn remove(actors: &mut Self, addr: &Addr) -> Option<CachedActor> {
-        actors.actor_cache.remove(addr).and_then(pre_shutdown)
+        let removed = actors.actor_cache.remove(addr)?;
+        actors.recency.remove(&removed.tick);
+        Self::vacate(removed).and_then(pre_shutdown)
+    }
+
+    //Take the actor out of an entry no longer cached - left to be released if it is busy
+    fn vacate(entry: Entry) -> Option<CachedActor> {
+        if entry.busy {
+            return None;
+        }
+        let vacated = entry.slot.lock().take();
+        vacated
+    }
+
+    pub(super) fn len(&self) -> usize {
+        self.actor_cache.len()
+    }
+
+    //Mark an actor as the most recently used one
+    pub(super) fn touch(&mut self, addr: &Addr) {
+        if let Some(previous) = self.actor_cache.get(addr).map(|entry| entry.tick) {
+            self.recency.remove(&previous);
+            let tick = self.next_tick(addr);
+            if let Some(entry) = self.actor_cache.get_mut(addr) {
+                entry.tick = tick;
+                entry.last_used = Instant::now();
+            }
+        }
+    }
+
+    //Actors not used for the given duration - least recently used first
+    pub(super) fn idle_for(&self, idle: Duration) -> Vec<Addr> {
+        self.recency
+            .values()
+            .take_while(|addr| {
+                self.actor_cache
+                    .get(addr)
+                    .is_some_and(|entry| entry.last_used.elapsed() >= idle)
+            })
+            .cloned()
+            .collect()
+    }
+
+    //The given number of least recently used actors
+    pub(super) fn least_recent(&self, count: usize) -> Vec<Addr> {
+        self.recency.values().take(count).cloned().collect()
+    }
+
+    fn next_tick(&mut self, addr: &Addr) -> u64 {
+        self.ticks += 1;
+        self.recency.insert(self.ticks, addr.clone());
+        self.ticks
     }
 
     pub(super) fn play_registration_acts(
@@ -67,6 +213,14 @@ impl Actors {
 }
 type Binary = Box<dyn Actor>;
 
+//Actor state as returned by `Actor::snapshot` along with the message sequence the actor
+//was at when the snapshot was taken. Gets stored in the `state` column of actors table.
+#[derive(Debug, Serialize, Deserialize)]
+pub(crate) struct Snapshot {
+    pub(crate) seq: i64,
+    pub(crate) state: Vec<u8>,
+}
+
 #[derive(Debug)]
 pub struct CachedActor {
     binary: Option<Binary>,
@@ -74,20 +228,47 @@ pub struct CachedActor {
     outputs: Vec<Option<Mail>>,
     channel: OutputChannel,
     addr: Addr,
+    since_snapshot: u64,
+    last_snapshot: Instant,
+    supervision: Supervision,
+    parent: Option<Addr>,
+    effects: Vec<Effect>,
 }
 
 impl CachedActor {
-    pub(crate) fn new(text: &str, addr: Addr, channel: OutputChannel) -> Option<Self> {
-        let producer = ProducerDeserializer::default().from_string(text.to_string());
+    //Produce an actor from its producer definition. State from the latest snapshot, if
+    //any, is restored before the actor is handed out.
+    pub(crate) fn new(
+        text: &str,
+        addr: Addr,
+        channel: OutputChannel,
+        snapshot: Option<&Snapshot>,
+        supervision: Supervision,
+        parent: Option<Addr>,
+    ) -> Option<Self> {
+        let producer = ProducerDeserializer.from_string(text.to_string());
         match producer {
             Ok(mut producer) => {
-                let actor: Binary = producer.produce();
+                let mut actor: Binary = producer.produce();
+                if let Some(snapshot) = snapshot {
+                    let restored =
+                        catch_unwind(AssertUnwindSafe(|| actor.restore_state(&snapshot.state)));
+                    if let Err(err) = restored {
+                        eprintln!("Error restoring actor state: {:?}", err);
+                        return None;
+                    }
+                }
                 Some(Self {
                     binary: Some(actor),
-                    sequence: 0,
+                    sequence: snapshot.map_or(0, |snapshot| snapshot.seq),
                     outputs: Vec::new(),
                     channel,
                     addr,
+                    since_snapshot: 0,
+                    last_snapshot: Instant::now(),
+                    supervision,
+                    parent,
+                    effects: Vec::new(),
                 })
             }
             Err(err) => {
@@ -105,6 +286,15 @@ impl CachedActor {
         &self.addr
     }
 
+    pub(crate) fn get_supervision(&self) -> &Supervision {
+        &self.supervision
+    }
+
+    //Spawn and stop requests the actor has made - to be carried out by the catalog
+    pub(crate) fn take_effects(actor: &mut CachedActor) -> Vec<Effect> {
+        std::mem::take(&mut actor.effects)
+    }
+
     pub(crate) fn get_sequence(actor: &CachedActor) -> i64 {
         actor.sequence
     }
@@ -146,34 +336,111 @@ impl CachedActor {
         this.channel = other.channel.clone();
     }
 
+    //Deliver a message and wait for the actor to be done with it - async or not
     pub(crate) fn receive(
         actor: &mut CachedActor,
-        mut mail: RichMail,
+        mail: RichMail,
     ) -> Result<(), Box<dyn Any + Send + 'static>> {
+        if let Some(pending) = Self::begin(actor, mail)? {
+            let resolved = catch_unwind(AssertUnwindSafe(|| executor::block_on(pending.0 .0)))?;
+            Self::wrap_up(actor, resolved, pending.1);
+        }
+        Ok(())
+    }
+
+    //Hand a message to the actor. An async actor leaves it to a future - the message is
+    //wrapped up once the future resolves.
+    fn begin(
+        actor: &mut CachedActor,
+        mut mail: RichMail,
+    ) -> Result<Option<Pending>, Box<dyn Any + Send + 'static>> {
         if !CachedActor::is_loaded(actor) || !CachedActor::should_handle_message(actor, &mail) {
-            return Ok(());
+            return Ok(None);
         }
+        let asked = Asks::asked(mail.mail());
+        let sender = match mail.mail() {
+            Mail::Trade(msg) => msg.get_from().clone(),
+            _ => None,
+        };
+        let mut ctx = ActorContext::new(
+            actor.addr.clone(),
+            actor.parent.clone(),
+            actor.sequence,
+            sender,
+        );
         if let Some(ref mut binary) = CachedActor::actor_binary(actor) {
-            let rs = Self::execute(binary, mail.mail_out());
-            match rs {
-                Ok(mut outcome) => {
-                    Mail::set_from(&mut outcome, CachedActor::get_addr(actor));
-                    CachedActor::push_outcome(CachedActor::output_buffer(actor), outcome);
-                    CachedActor::increment_sequence(CachedActor::get_sequence_mut(actor));
-                    /***println!(
-                        "CachedActor current message seq {:?}",
-                        CachedActor::get_sequence_mut(actor)
-                    );***/
-                    Self::flush_buffer(actor);
-                }
-                Err(err) => return Err(err),
+            let outcome = Self::execute(binary, mail.mail_out(), &mut ctx)?;
+            match ctx.take_detached() {
+                Some(detached) => return Ok(Some(Pending(detached, asked))),
+                None => Self::wrap_up(actor, (outcome, ctx), asked),
             }
         }
-        Ok(())
+        Ok(None)
+    }
+
+    //Account for a message the actor is done with - its outgoing mail is buffered and the
+    //sequence moves on
+    fn wrap_up(actor: &mut CachedActor, resolved: Resolved, asked: Option<u64>) {
+        let (mut outcome, mut ctx) = resolved;
+        actor.effects.extend(ctx.take_effects());
+        Mail::set_from(&mut outcome, CachedActor::get_addr(actor));
+        //Outcome of an asked message is the reply - goes back to the asker
+        if let Some(msg_id) = asked {
+            outcome = Asks::answer(msg_id, outcome);
+        }
+        CachedActor::push_outcome(CachedActor::output_buffer(actor), outcome);
+        let sent = ctx.take_sent();
+        if !sent.is_empty() {
+            let mut sent = Some(Mail::Bulk(sent));
+            Mail::set_from(&mut sent, CachedActor::get_addr(actor));
+            CachedActor::push_outcome(CachedActor::output_buffer(actor), sent);
+        }
+        CachedActor::increment_sequence(CachedActor::get_sequence_mut(actor));
+        actor.since_snapshot += 1;
+        Self::flush_buffer(actor);
+    }
+
+    //Deliver a message and gather what comes out of it - unless the actor is left pending
+    //on a future
+    pub(crate) fn process(actor: &mut CachedActor, mail: RichMail) -> Processed {
+        match Self::begin(actor, mail) {
+            Ok(Some(pending)) => Processed::Pending(pending),
+            Ok(None) => Processed::Done(Self::outcome(actor)),
+            Err(_err) => Processed::Done(Err(actor.get_supervision().clone())),
+        }
+    }
+
+    //Gather what comes out of a message once the future handling it resolves
+    pub(crate) fn resolve(
+        actor: &mut CachedActor,
+        resolved: thread::Result<Resolved>,
+        asked: Option<u64>,
+    ) -> Outcome {
+        match resolved {
+            Ok(resolved) => {
+                Self::wrap_up(actor, resolved, asked);
+                Self::outcome(actor)
+            }
+            Err(err) => {
+                eprintln!("{:?}", err);
+                Err(actor.get_supervision().clone())
+            }
+        }
+    }
+
+    fn outcome(actor: &mut CachedActor) -> Outcome {
+        Ok((Self::snapshot(actor), Self::take_effects(actor)))
     }
 
     fn flush_buffer(actor: &mut CachedActor) {
         if CachedActor::should_flush(CachedActor::buffer_size(actor)) {
+            Self::flush_outputs(actor);
+        }
+    }
+
+    //Hand over buffered outgoing mail - irrespective of the buffer size
+    pub(crate) fn flush_outputs(actor: &mut CachedActor) {
+        if !actor.outputs.is_empty() {
             let buffered = std::mem::take(CachedActor::output_buffer(actor));
             if let Some(ref channel) = actor.channel {
                 channel
@@ -192,8 +459,9 @@ impl CachedActor {
     fn execute(
         binary: &mut Binary,
         mail: Mail,
+        ctx: &mut ActorContext,
     ) -> Result<Option<Mail>, Box<dyn Any + Send + 'static>> {
-        match catch_unwind(AssertUnwindSafe(|| binary.receive(mail))) {
+        match catch_unwind(AssertUnwindSafe(|| binary.receive_with(mail, ctx))) {
             Ok(outcome) => Ok(outcome),
             Err(err) => {
                 eprintln!("{:?}", err);
@@ -202,6 +470,64 @@ impl CachedActor {
         }
     }
 
+    pub(crate) fn is_event_sourced(actor: &CachedActor) -> bool {
+        actor
+            .binary
+            .as_ref()
+            .is_some_and(|binary| binary.event_sourced())
+    }
+
+    //Feed already processed messages back to the actor to rebuild its state. Sequence
+    //stays where it is - outgoing mail is suppressed.
+    pub(crate) fn replay(
+        actor: &mut CachedActor,
+        msgs: Vec<Msg>,
+    ) -> Result<(), Box<dyn Any + Send + 'static>> {
+        if let Some(ref mut binary) = CachedActor::actor_binary(actor) {
+            for msg in msgs {
+                catch_unwind(AssertUnwindSafe(|| binary.replay(Mail::Trade(msg))))?;
+            }
+        }
+        Ok(())
+    }
+
+    //Capture actor state if a snapshot is due - based on messages processed or time
+    //elapsed since the last one
+    pub(crate) fn snapshot(actor: &mut CachedActor) -> Option<Snapshot> {
+        if !Self::snapshot_due(actor) {
+            return None;
+        }
+        Self::take_snapshot(actor)
+    }
+
+    //Capture actor state irrespective of whether a snapshot is due
+    pub(crate) fn take_snapshot(actor: &mut CachedActor) -> Option<Snapshot> {
+        actor.since_snapshot = 0;
+        actor.last_snapshot = Instant::now();
+        let binary = actor.binary.as_ref()?;
+        match catch_unwind(AssertUnwindSafe(|| binary.snapshot())) {
+            Ok(state) => state.map(|state| Snapshot {
+                seq: actor.sequence,
+                state,
+            }),
+            Err(err) => {
+                eprintln!("Error taking actor snapshot: {:?}", err);
+                None
+            }
+        }
+    }
+
+    fn snapshot_due(actor: &CachedActor) -> bool {
+        if actor.since_snapshot == 0 {
+            return false;
+        }
+        let config = Config::get_shared();
+        let every = config.snapshot_every();
+        let interval = config.snapshot_interval();
+        (every > 0 && actor.since_snapshot >= every)
+            || (interval > 0 && actor.last_snapshot.elapsed() >= Duration::from_secs(interval))
+    }
+
     pub(crate) fn push_outcome(output_buffer: &mut Vec<Option<Mail>>, mail: Option<Mail>) {
         if mail.is_some() {
             output_buffer.push(mail);
@@ -231,7 +557,10 @@ fn pre_shutdown(mut actor: CachedActor) -> Option<CachedActor> {
         &mut actor,
         RichMail::RichContent(Mail::Blank, true, 0, None, None),
     );
-    println!("Pre shutdown hook fired for actor ({})", actor.get_addr().get_name());
+    println!(
+        "Pre shutdown hook fired for actor ({})",
+        actor.get_addr().get_name()
+    );
     Some(actor)
 }
 //Post startup message
@@ -240,6 +569,9 @@ fn post_start(mut actor: CachedActor) -> Option<CachedActor> {
         &mut actor,
         RichMail::RichContent(Mail::Blank, true, 0, None, None),
     );
-    println!("Post start hook fired for actor ({})", actor.get_addr().get_name());
+    println!(
+        "Post start hook fired for actor ({})",
+        actor.get_addr().get_name()
+    );
     Some(actor)
 }
@@ -0,0 +1,47 @@
+use crate::Mail;
+use lazy_static::lazy_static;
+use parking_lot::Mutex;
+use std::collections::HashMap;
+use std::sync::mpsc::{channel, Receiver, Sender};
+
+//Callers waiting for replies - keyed by the id of the message they have asked with
+lazy_static! {
+    static ref ASKS: Mutex<HashMap<u64, Sender<Mail>>> = Mutex::new(HashMap::new());
+}
+
+pub(super) struct Asks;
+
+impl Asks {
+    //Register interest in the reply to a message - reply would arrive on the receiver
+    pub(super) fn register(msg_id: u64) -> Receiver<Mail> {
+        let (sender, receiver) = channel();
+        ASKS.lock().insert(msg_id, sender);
+        receiver
+    }
+
+    //Asker has given up waiting
+    pub(super) fn withdraw(msg_id: u64) {
+        ASKS.lock().remove(&msg_id);
+    }
+
+    //The id of the message inside a mail - if somebody is waiting for its reply
+    pub(super) fn asked(mail: &Mail) -> Option<u64> {
+        match mail {
+            Mail::Trade(msg) if ASKS.lock().contains_key(msg.get_id()) => Some(*msg.get_id()),
+            _ => None,
+        }
+    }
+
+    //Hand the actor outcome over to the waiting asker. Outcome is given back if the asker
+    //is no longer around - so that it gets routed as usual.
+    pub(super) fn answer(msg_id: u64, outcome: Option<Mail>) -> Option<Mail> {
+        let asker = ASKS.lock().remove(&msg_id);
+        match asker {
+            Some(asker) => match asker.send(Mail::from(outcome)) {
+                Ok(_) => None,
+                Err(err) => Some(err.0).filter(|mail| !Mail::is_blank(mail)),
+            },
+            None => outcome,
+        }
+    }
+}
@@ -0,0 +1,44 @@
+use crate::RichMail;
+use lazy_static::lazy_static;
+use parking_lot::Mutex;
+use std::collections::{HashMap, VecDeque};
+
+//Messages held back for actors waiting to be handed a failed message again - keyed by actor
+//id. Actors process their messages in order - those arriving meanwhile wait for the retry.
+lazy_static! {
+    static ref BACKOFFS: Mutex<HashMap<u64, VecDeque<RichMail>>> = Mutex::new(HashMap::new());
+}
+
+pub(super) struct Backoffs;
+
+impl Backoffs {
+    //Actor is backing off - messages to it are held back from now on
+    pub(super) fn begin(actor_id: u64) {
+        BACKOFFS.lock().entry(actor_id).or_default();
+    }
+
+    //Hold a message back if its recipient is backing off - it is handed back otherwise
+    pub(super) fn hold(mail: RichMail) -> Option<RichMail> {
+        let actor_id = match mail.to() {
+            Some(addr) => addr.get_id(),
+            None => return Some(mail),
+        };
+        match BACKOFFS.lock().get_mut(&actor_id) {
+            Some(held) => {
+                held.push_back(mail);
+                None
+            }
+            None => Some(mail),
+        }
+    }
+
+    //Next message held back for an actor - the actor is done backing off if there is none
+    pub(super) fn next(actor_id: u64) -> Option<RichMail> {
+        let mut backoffs = BACKOFFS.lock();
+        let next = backoffs.get_mut(&actor_id).and_then(VecDeque::pop_front);
+        if next.is_none() {
+            backoffs.remove(&actor_id);
+        }
+        next
+    }
+}
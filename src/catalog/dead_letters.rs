@@ -0,0 +1,70 @@
+use crate::{Addr, Msg};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+//Reasons messages end up in the dead letter store
+pub(crate) const ACTOR_NOT_FOUND: &str = "Actor definition not found";
+pub(crate) const ACTOR_EVICTED: &str = "Actor stopped after failing on the message";
+pub(crate) const MESSAGE_SKIPPED: &str = "Skipped by actor restarted after failing on it";
+pub(crate) const MESSAGE_EXPIRED: &str = "Time-to-live elapsed before delivery";
+
+///A message that could not be delivered - because the recipient actor was not defined in
+///the system, because its time-to-live elapsed before delivery or because the actor
+///panicked on it and its supervision decided to skip the message or stop the actor. Dead
+///letters are kept in the backing store until they are purged or re-injected.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DeadLetter {
+    id: i64,
+    addr: Addr,
+    msg: Msg,
+    reason: String,
+    recorded_at: SystemTime,
+}
+
+impl DeadLetter {
+    pub(crate) fn new(id: i64, addr: Addr, msg: Msg, reason: String, recorded_at: i64) -> Self {
+        Self {
+            id,
+            addr,
+            msg,
+            reason,
+            recorded_at: UNIX_EPOCH + Duration::from_secs(recorded_at as u64),
+        }
+    }
+    ///Identity of the dead letter in the backing store
+    pub fn get_id(&self) -> i64 {
+        self.id
+    }
+    ///Address of the actor the message was meant for
+    pub fn get_addr(&self) -> &Addr {
+        &self.addr
+    }
+    ///The undelivered message
+    pub fn get_msg(&self) -> &Msg {
+        &self.msg
+    }
+    ///Why the message could not be delivered
+    pub fn get_reason(&self) -> &str {
+        &self.reason
+    }
+    ///When the message was declared dead
+    pub fn recorded_at(&self) -> SystemTime {
+        self.recorded_at
+    }
+}
+
+impl std::fmt::Display for DeadLetter {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "DeadLetter({}), addr: {}, reason: {}, msg: {}",
+            self.id, self.addr, self.reason, self.msg
+        )
+    }
+}
+
+//Seconds since epoch - for recording when a message was declared dead
+pub(crate) fn now_secs() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs() as i64)
+}
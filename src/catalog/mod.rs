@@ -4,15 +4,30 @@
 //!Provides internal apis for activatation/restoration of actors, a handle to backend store.
 
 mod actors;
+mod asks;
+mod backoffs;
+mod dead_letters;
+mod outbox;
 mod panics;
 use crate::apis::Store;
-use crate::catalog::actors::{Actors, CachedActor};
+use crate::backend::{self, ActorDef, PoolDef};
+use crate::catalog::actors::{Actors, CachedActor, Outcome, Pending, Processed, Slot, Snapshot};
+use crate::catalog::asks::Asks;
+use crate::catalog::backoffs::Backoffs;
+use crate::catalog::dead_letters::{
+    now_secs, ACTOR_EVICTED, ACTOR_NOT_FOUND, MESSAGE_EXPIRED, MESSAGE_SKIPPED,
+};
+use crate::catalog::outbox::Outbox;
 use crate::catalog::panics::PanicWatch;
+use crate::common::context::Effect;
+use crate::common::executor;
+use crate::common::pool::{self, Distribution};
+use crate::common::supervision::Directive;
 use crate::common::{actor::Producer, mail::Mail};
 use crate::events::DBEvent;
-use crate::routing::messenger::Messenger;
 use crate::Error::{self, RegistrationError, RestorationError};
-use crate::{Addr, RichMail};
+use crate::{compute_hash, from_bytes, option_of_bytes, Addr, Config, Msg, RichMail, Supervision};
+pub use dead_letters::DeadLetter;
 use lazy_static::lazy_static;
 use parking_lot::{ReentrantMutex, ReentrantMutexGuard};
 use std::cell::RefCell;
@@ -21,8 +36,9 @@ use std::sync::{
     mpsc::{channel, Receiver, Sender},
     Arc,
 };
-use std::thread::JoinHandle;
-use std::time::Duration;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use uuid::Uuid;
 
 lazy_static! {
     pub(crate) static ref CTX: Arc<ReentrantMutex<RefCell<Context>>> =
@@ -38,12 +54,16 @@ pub(crate) struct Context {
     dispatcher: Dispatcher,
 }
 type Dispatcher = Sender<RichMail>;
+//A failed message to be delivered again after a delay
+pub(crate) type Retry = (Duration, RichMail);
 
 impl Context {
     pub(crate) fn init() -> RefCell<Self> {
         let actors = Actors::new();
         let mut store = Store::new();
         if let Err(err) = store.setup() {
+            //The panic hook does not report the message
+            eprintln!("Backing store could not be set up: {}", err);
             panic!("{}", err);
         }
         let ctx_init = Arc::new(AtomicBool::new(true));
@@ -64,7 +84,7 @@ impl Context {
                                 self::egress(rich_mail);
                             }
                             if !outs.is_empty() {
-                                if let Err(err) = Messenger::mail(Mail::Bulk(outs)) {
+                                if let Err(err) = self::send_out(outs) {
                                     eprintln!("{:?}", err);
                                 }
                             }
@@ -101,25 +121,31 @@ impl Context {
 
     //Numeric identity of the actor
     pub(crate) fn remove_actor_permanent(&mut self, identity: &str) -> Result<(), Error> {
-        self.store
-            .remove_actor_permanent(identity)
-            .map_err(|err| Error::Other(Box::new(err)))
+        self.store.remove_actor_permanent(identity)
     }
-    //Save an actor producer defintion in the backing store. Current active actor, if any, will
-    //not be disturbed
+    //Save an actor producer defintion along with the supervision and the parent, if any,
+    //in the backing store - messages of event sourced actors are kept for replay. Current
+    //active actor, if any, will not be disturbed
     pub(crate) fn save_producer(
         &mut self,
         identity: &str,
         addr: Addr,
-        producer: &impl Producer,
+        text: &str,
+        supervision: &Supervision,
+        parent: Option<&Addr>,
+        event_sourced: bool,
     ) -> Result<(), Error> {
-        let text = serde_json::to_string(producer as &dyn Producer)?;
-        self.store
-            .save_producer(identity, addr, &text)
-            .map_err(|err| Error::Other(Box::new(err)))
+        self.store.save_producer(
+            identity,
+            addr,
+            text,
+            &supervision.to_text(),
+            parent,
+            event_sourced,
+        )
     }
     //identity - numeric string of actor address(Addr)
-    pub(crate) fn retrieve_actor_def(&mut self, identity: &str) -> Option<(String, String, i64)> {
+    pub(crate) fn retrieve_actor_def(&mut self, identity: &str) -> Option<ActorDef> {
         let result = self.store.retrieve_actor_def(identity);
         match result {
             Ok(addr_text_seq) => addr_text_seq,
@@ -129,43 +155,155 @@ impl Context {
             }
         }
     }
-    //Defines an actor in the system. The producer instantiates actors.
+    //Defines an actor in the system. The producer instantiates actors. Supervision decides
+    //what happens when the actor panics.
     pub(crate) fn define_actor(
         &mut self,
         addr: Addr,
         producer: impl Producer,
+        supervision: Supervision,
     ) -> Result<Option<CachedActor>, Error> {
         let text = serde_json::to_string(&producer as &dyn Producer)?;
-        match CachedActor::new(&text, addr.clone(), Some(self.dispatcher.clone())) {
+        self.define(addr, &text, supervision, None)
+    }
+
+    //Define an actor from its serialized producer - as a child if a parent is given
+    fn define(
+        &mut self,
+        addr: Addr,
+        text: &str,
+        supervision: Supervision,
+        parent: Option<Addr>,
+    ) -> Result<Option<CachedActor>, Error> {
+        //Busy actors are replaced by callers waiting for them with the Context released
+        if self.actors.is_busy(&addr) {
+            return Err(RegistrationError);
+        }
+        match CachedActor::new(
+            text,
+            addr.clone(),
+            Some(self.dispatcher.clone()),
+            None,
+            supervision.clone(),
+            parent.clone(),
+        ) {
             Some(mut actor) => {
-                let previous = Actors::get(&self.actors, &addr);
+                let previous = self.actors.with(&addr, |previous| {
+                    CachedActor::take_over_from(&mut actor, previous)
+                });
                 let identity = addr.get_id().to_string();
-                if let Some(previous) = previous {
-                    CachedActor::take_over_from(&mut actor, previous);
+                if previous.is_some() {
                     let _rs = self.remove_actor_permanent(&identity);
                 }
-                self.save_producer(&identity.to_string(), addr.clone(), &producer)?;
-                Actors::play_registration_acts(&mut self.actors, addr, actor)
+                let event_sourced = CachedActor::is_event_sourced(&actor);
+                self.save_producer(
+                    &identity,
+                    addr.clone(),
+                    text,
+                    &supervision,
+                    parent.as_ref(),
+                    event_sourced,
+                )?;
+                let evicted =
+                    Actors::play_registration_acts(&mut self.actors, addr.clone(), actor)?;
+                self.apply_effects(&addr);
+                self.passivate_surplus();
+                Ok(evicted)
             }
             None => Err(RegistrationError),
         }
     }
 
+    //Carry out the requests an actor has made through its context
+    fn apply_effects(&mut self, addr: &Addr) {
+        let effects = self.actors.with(addr, CachedActor::take_effects);
+        self.carry_out(addr, effects.unwrap_or_default());
+    }
+
+    fn carry_out(&mut self, addr: &Addr, effects: Vec<Effect>) {
+        for effect in effects {
+            match effect {
+                Effect::Spawn(child, text, supervision) => {
+                    self.spawn(addr, child, &text, supervision)
+                }
+                Effect::Stop(child) if self.is_child_of(&child, addr) => self.stop(&child),
+                Effect::Stop(child) => eprintln!("Actor {} is not a child of {}", child, addr),
+                Effect::StopSelf => self.stop(addr),
+                Effect::Schedule(id, msg, after, every) => {
+                    if let Err(err) = self.schedule(id, &msg, after, every) {
+                        eprintln!("Error scheduling message {}: {:?}", msg, err);
+                    }
+                }
+                Effect::CancelSchedule(id) => {
+                    if let Err(err) = self.cancel_schedule(id) {
+                        eprintln!("Error cancelling schedule {}: {:?}", id, err);
+                    }
+                }
+                Effect::Publish(topic, msg) => {
+                    if let Err(err) = self.publish(&topic, vec![msg]) {
+                        eprintln!("Error publishing to topic {}: {:?}", topic, err);
+                    }
+                }
+            }
+        }
+    }
+
+    //Define a child actor. Existing actors are left undisturbed - a child that has already
+    //been spawned is not spawned again and actors are never adopted.
+    fn spawn(&mut self, parent: &Addr, child: Addr, text: &str, supervision: Supervision) {
+        if self
+            .retrieve_actor_def(&child.get_id().to_string())
+            .is_some()
+        {
+            if !self.is_child_of(&child, parent) {
+                eprintln!("Actor {} already exists - not spawned by {}", child, parent);
+            }
+            return;
+        }
+        if let Err(err) = self.define(child.clone(), text, supervision, Some(parent.clone())) {
+            eprintln!("Error spawning actor {}: {:?}", child, err);
+        }
+    }
+
+    fn is_child_of(&mut self, child: &Addr, parent: &Addr) -> bool {
+        self.retrieve_actor_def(&child.get_id().to_string())
+            .and_then(|def| def.parent)
+            .is_some_and(|(parent_id, _)| parent_id == parent.get_id().to_string())
+    }
+
     //Restore an actor from the backing storage. Active actor will be replaced on successful
     //retrieval. Left undisturbed if not found.
     pub(crate) fn restore(&mut self, addr: Addr) -> Result<Option<CachedActor>, Error> {
         let identity = addr.get_id().to_string();
         match self.retrieve_actor_def(&identity) {
-            Some(definition) => {
-                let text = definition.1;
-                let msg_seq = definition.2;
-                match CachedActor::new(&text, addr.clone(), Some(self.dispatcher.clone())) {
+            Some(def) => {
+                let snapshot = def
+                    .state
+                    .and_then(|state| from_bytes::<'_, Snapshot>(&state).ok());
+                match CachedActor::new(
+                    &def.producer,
+                    addr.clone(),
+                    Some(self.dispatcher.clone()),
+                    snapshot.as_ref(),
+                    Supervision::from_text(def.supervision.as_deref()),
+                    def.parent.map(|(_, parent)| parent),
+                ) {
                     Some(mut actor) => {
+                        //Snapshot might be ahead of the sequence recorded at egress
+                        let snapshot_seq = CachedActor::get_sequence(&actor);
+                        let msg_seq = def.msg_seq.max(snapshot_seq);
+                        if CachedActor::is_event_sourced(&actor) {
+                            self.replay(&identity, &mut actor, snapshot_seq, msg_seq)?;
+                        }
                         CachedActor::set_sequence(
                             CachedActor::get_sequence_mut(&mut actor),
                             msg_seq,
                         );
-                        Actors::play_restoration_acts(&mut self.actors, addr, actor)
+                        let evicted =
+                            Actors::play_restoration_acts(&mut self.actors, addr.clone(), actor)?;
+                        self.apply_effects(&addr);
+                        self.passivate_surplus();
+                        Ok(evicted)
                     }
                     None => Err(RestorationError),
                 }
@@ -174,48 +312,585 @@ impl Context {
         }
     }
 
-    fn is_actor_defined(&mut self, addr: &Addr) -> bool {
-        match Actors::get(&self.actors, addr) {
-            Some(_) => true,
-            None => {
-                let rs = self.restore(addr.clone());
-                rs.is_ok() && rs.ok().is_some()
+    //Replay messages an event sourced actor has already processed - from the snapshot
+    //sequence upto, but not including, the sequence it resumes live processing from
+    fn replay(
+        &mut self,
+        identity: &str,
+        actor: &mut CachedActor,
+        from_seq: i64,
+        until_seq: i64,
+    ) -> Result<(), Error> {
+        let mut from_seq = from_seq;
+        while from_seq < until_seq {
+            let batch = self.store.replay_messages(identity, from_seq, until_seq)?;
+            match batch.last() {
+                Some((last_seq, _)) => from_seq = last_seq + 1,
+                None => break,
+            }
+            let msgs = batch.into_iter().map(|(_, msg)| msg).collect();
+            if let Err(err) = CachedActor::replay(actor, msgs) {
+                eprintln!("Actor panicked during replay: {:?}", err);
+                return Err(RestorationError);
             }
         }
+        Ok(())
     }
 
-    pub(crate) fn handle_invocation(&mut self, rich_mail: RichMail) {
-        let addr = rich_mail.to();
-        if let Some(ref addr_inner) = addr {
-            let defined = self.is_actor_defined(addr_inner);
-            if !defined {
-                eprintln!(
-                    "Actor definition not found in the system for :{}!",
-                    addr_inner
-                );
-                return;
+    //Whether the actor is cached - restored from the backing store if not active
+    fn activate(&mut self, addr: &Addr) -> bool {
+        if !self.actors.contains(addr) {
+            let _rs = self.restore(addr.clone());
+        }
+        self.actors.contains(addr)
+    }
+
+    //Get the recipient actor ready for a message - restored if not active. Returns the actor
+    //along with the message kept aside in case the actor panics on it. None if there is
+    //nothing for the actor to process. The actor is busy until the message is concluded.
+    pub(crate) fn admit(&mut self, rich_mail: &RichMail) -> Option<(Slot, Option<RichMail>)> {
+        let addr_inner = rich_mail.to()?.clone();
+        if !self.actors.contains(&addr_inner) {
+            if let Some(pool) = self.pool_def(&addr_inner) {
+                self.distribute(&addr_inner, pool, rich_mail);
+                return None;
             }
-            let mut actor_addr = Addr::default();
-            let actor_id = addr_inner.get_id();
-            PanicWatch::set_watch(actor_id);
-            let mut panicked = false;
-            {
-                let actor = self.actors.get_mut(addr_inner);
-                if let Some(actor) = actor {
-                    if let Err(_err) = CachedActor::receive(actor, rich_mail) {
-                        panicked = PanicWatch::has_exceeded_tolerance(actor_id);
-                        actor_addr = CachedActor::get_addr(actor).clone();
+        }
+        if !self.activate(&addr_inner) {
+            eprintln!(
+                "Actor definition not found in the system for :{}!",
+                addr_inner
+            );
+            if let Mail::Trade(msg) = rich_mail.mail() {
+                Self::record_dead_letter(&mut self.store, msg, ACTOR_NOT_FOUND);
+                Self::keep_message(&mut self.store, msg);
+            }
+            return None;
+        }
+        self.actors.touch(&addr_inner);
+        if self.expired(&addr_inner, rich_mail) {
+            return None;
+        }
+        let poison = match rich_mail.mail() {
+            Mail::Trade(msg) => {
+                Self::keep_message(&mut self.store, msg);
+                Some(rich_mail.clone())
+            }
+            _ => None,
+        };
+        let slot = self.actors.engage(&addr_inner)?;
+        Some((slot, poison))
+    }
+
+    //Wrap up once the actor is done with a message. If the actor has panicked on it - its
+    //supervision decides what happens next. The message is handed back along with a delay
+    //if it is to be retried.
+    pub(crate) fn conclude(
+        &mut self,
+        addr_inner: &Addr,
+        slot: &Slot,
+        poison: Option<RichMail>,
+        outcome: Outcome,
+    ) -> Option<Retry> {
+        self.actors.release(addr_inner, slot);
+        let actor_id = addr_inner.get_id();
+        let supervision = match outcome {
+            Ok((snapshot, effects)) => {
+                if let Some(snapshot) = snapshot {
+                    Self::save_snapshot(&mut self.store, actor_id, &snapshot);
+                }
+                self.carry_out(addr_inner, effects);
+                return None;
+            }
+            Err(supervision) => supervision,
+        };
+        let failures = PanicWatch::record(actor_id, supervision.within());
+        match self.directive(addr_inner, &supervision, failures) {
+            Directive::Restart { retry, after } => match self.restart(addr_inner) {
+                Ok(_) if retry => return poison.map(|poison| (after, poison)),
+                Ok(_) => {
+                    Self::record_poison(&mut self.store, poison, MESSAGE_SKIPPED);
+                    return None;
+                }
+                Err(err) => eprintln!("Error restarting actor {}: {:?}", addr_inner, err),
+            },
+            Directive::Escalate | Directive::Stop => (),
+        }
+        println!(
+            "Actor failure count {}. Stopping actor {}.",
+            PanicWatch::count(actor_id),
+            addr_inner
+        );
+        self.stop(addr_inner);
+        Self::record_poison(&mut self.store, poison, ACTOR_EVICTED);
+        None
+    }
+
+    //Decide what happens to a failed actor. Escalation goes up the chain of parents - the
+    //first supervision that does not escalate decides. Actors without a parent to escalate
+    //to are stopped.
+    fn directive(&mut self, addr: &Addr, supervision: &Supervision, failures: u32) -> Directive {
+        let mut directive = supervision.directive(failures);
+        let mut identity = addr.get_id().to_string();
+        while directive == Directive::Escalate {
+            let parent = self
+                .retrieve_actor_def(&identity)
+                .and_then(|def| def.parent)
+                .and_then(|(parent_id, _)| {
+                    self.retrieve_actor_def(&parent_id)
+                        .map(|parent| (parent_id, parent))
+                });
+            match parent {
+                Some((parent_id, parent)) => {
+                    directive =
+                        Supervision::from_text(parent.supervision.as_deref()).directive(failures);
+                    identity = parent_id;
+                }
+                None => directive = Directive::Stop,
+            }
+        }
+        directive
+    }
+
+    //Replace a failed actor with a fresh instance - restored just like on system restart.
+    //The fresh instance does not go back on messages already processed.
+    fn restart(&mut self, addr: &Addr) -> Result<(), Error> {
+        let sequence = self
+            .actors
+            .with(addr, |actor| CachedActor::get_sequence(actor));
+        self.restore(addr.clone())?;
+        if let Some(sequence) = sequence {
+            self.actors.with(addr, |actor| {
+                let restored = CachedActor::get_sequence_mut(actor);
+                *restored = sequence.max(*restored);
+            });
+        }
+        Ok(())
+    }
+
+    //Stop an actor for good - its definition is removed from the backing store as well.
+    //Children are stopped first. Messages that arrive later end up in the dead letters.
+    fn stop(&mut self, addr: &Addr) {
+        match self.store.children_of(&addr.get_id().to_string()) {
+            Ok(children) => {
+                for child in children {
+                    self.stop(&child);
+                }
+            }
+            Err(err) => eprintln!("Error fetching children of {}: {}", addr, err),
+        }
+        Actors::remove(&mut self.actors, addr);
+        PanicWatch::remove_watch(&addr.get_id());
+        if let Err(err) = self.store.drop_subscriptions(&addr.get_id().to_string()) {
+            eprintln!("Error removing subscriptions of {}: {}", addr, err);
+        }
+        if let Err(err) = self.remove_actor_permanent(&addr.get_id().to_string()) {
+            eprintln!("Error removing actor definition {}: {:?}", addr, err);
+        }
+    }
+
+    //Messages past their time-to-live are not handed to the actor - they end up in the
+    //dead letters. Messages the actor has already processed - routed again on restart -
+    //are left for the actor to pass over.
+    fn expired(&mut self, addr: &Addr, rich_mail: &RichMail) -> bool {
+        let msg = match rich_mail.mail() {
+            Mail::Trade(msg) if msg.is_expired() => msg,
+            _ => return false,
+        };
+        let pending = self
+            .actors
+            .with(addr, |actor| {
+                CachedActor::should_handle_message(actor, rich_mail)
+            })
+            .unwrap_or(false);
+        if pending {
+            //Whoever removes the message records it - it may have been routed twice
+            match self.store.delete_message(msg) {
+                Ok(0) => (),
+                Ok(_) => Self::record_dead_letter(&mut self.store, msg, MESSAGE_EXPIRED),
+                Err(err) => eprintln!("Error removing expired message {}", err),
+            }
+        }
+        pending
+    }
+
+    //Persist incoming mail right away - returning the ids of the messages that had already
+    //been ingested
+    pub(crate) fn ingest(&mut self, mail: Mail) -> Result<Vec<u64>, Error> {
+        self.store.ingest(mail)
+    }
+
+    //A message with a time-to-live that has been accounted for - delivered or dead
+    //lettered - is kept out of the reach of the expiry sweeper
+    fn keep_message(store: &mut Store, msg: &Msg) {
+        if msg.get_ttl().is_some() {
+            if let Err(err) = store.clear_expiry(msg) {
+                eprintln!("Error clearing message expiry {}", err);
+            }
+        }
+    }
+
+    //Remove messages that expired before they could be dispatched - such as those to
+    //actors that are not active. They end up in the dead letters. Ids of messages ingested
+    //before the dedup window are forgotten too.
+    pub(crate) fn sweep_expired(&mut self) {
+        match self.store.take_expired_messages(now_millis()) {
+            Ok(expired) => {
+                for msg in &expired {
+                    Self::record_dead_letter(&mut self.store, msg, MESSAGE_EXPIRED);
+                }
+            }
+            Err(err) => eprintln!("Error sweeping expired messages {}", err),
+        }
+        let dedup_window = Config::get_shared().dedup_window() as i64;
+        if let Err(err) = self.store.forget_ingested(now_secs() - dedup_window) {
+            eprintln!("Error forgetting ingested messages {}", err);
+        }
+    }
+
+    //Delete processed messages as the configured retention allows
+    pub(crate) fn compact(&mut self) {
+        if let Err(err) = self.store.compact() {
+            eprintln!("Error compacting backing store {}", err);
+        }
+    }
+
+    fn record_poison(store: &mut Store, poison: Option<RichMail>, reason: &str) {
+        if let Some(Mail::Trade(ref msg)) = poison.as_ref().map(RichMail::mail) {
+            Self::record_dead_letter(store, msg, reason);
+        }
+    }
+
+    fn record_dead_letter(store: &mut Store, msg: &Msg, reason: &str) {
+        if let Err(err) = store.record_dead_letter(msg, reason, now_secs()) {
+            eprintln!("Error recording dead letter {}", err);
+        }
+    }
+
+    pub(crate) fn dead_letters(&mut self) -> Result<Vec<DeadLetter>, Error> {
+        self.store.dead_letters()
+    }
+
+    pub(crate) fn dead_letter(&mut self, id: i64) -> Result<Option<DeadLetter>, Error> {
+        self.store.dead_letter(id)
+    }
+
+    pub(crate) fn purge_dead_letters(&mut self, id: Option<i64>) -> Result<usize, Error> {
+        self.store.delete_dead_letters(id)
+    }
+
+    //Persist a message to be delivered after a delay - periodically if `every` is given
+    pub(crate) fn schedule(
+        &mut self,
+        id: u64,
+        msg: &Msg,
+        after: Duration,
+        every: Option<Duration>,
+    ) -> Result<(), Error> {
+        let due_at = now_millis() + after.as_millis() as i64;
+        let every = every.map_or(0, |every| every.as_millis() as i64);
+        self.store
+            .save_schedule(&id.to_string(), msg, due_at, every)
+    }
+
+    pub(crate) fn cancel_schedule(&mut self, id: u64) -> Result<bool, Error> {
+        self.store
+            .delete_schedule(&id.to_string())
+            .map(|deleted| deleted > 0)
+    }
+
+    //Move the messages of schedules that have fallen due into the backing store. Each run
+    //of a schedule is a new message - hence a new id. Messages to remote actors are handed
+    //back.
+    pub(crate) fn fire_schedules(&mut self) -> Vec<Msg> {
+        let due = match self.store.take_due_schedules(now_millis()) {
+            Ok(due) => due,
+            Err(err) => {
+                eprintln!("Error fetching due schedules {}", err);
+                return Vec::new();
+            }
+        };
+        let msgs = due
+            .into_iter()
+            .map(|mut msg| {
+                msg.renew_id();
+                msg
+            })
+            .collect();
+        match Mail::split(Mail::Bulk(msgs)) {
+            Some((ins, outs)) => {
+                if !ins.is_empty() {
+                    if let Err(err) = self
+                        .store
+                        .persist(Mail::Bulk(ins))
+                        .and_then(|_| self.store.persist(Mail::Blank))
+                    {
+                        eprintln!("Error persisting scheduled messages {}", err);
                     }
                 }
+                outs
+            }
+            None => Vec::new(),
+        }
+    }
+
+    //Subscriptions are kept in the backing store - they survive restarts
+    pub(crate) fn subscribe(&mut self, topic: &str, addr: &Addr) -> Result<bool, Error> {
+        self.store.subscribe(topic, addr).map(|count| count > 0)
+    }
+
+    pub(crate) fn unsubscribe(&mut self, topic: &str, addr: &Addr) -> Result<bool, Error> {
+        self.store
+            .unsubscribe(topic, &addr.get_id().to_string())
+            .map(|count| count > 0)
+    }
+
+    pub(crate) fn subscribers(&mut self, topic: &str) -> Result<Vec<Addr>, Error> {
+        self.store.subscribers(topic)
+    }
+
+    //Publish to the subscribers of a topic - returning the number of copies. Copies to local
+    //subscribers go straight into the backing store - the listener is not to be called back
+    //while the Context is held. Copies to remote subscribers are sent out.
+    fn publish(&mut self, topic: &str, msgs: Vec<Msg>) -> Result<usize, Error> {
+        let subscribers = self.subscribers(topic)?;
+        let copies = fan_out(&subscribers, msgs);
+        let count = copies.len();
+        if let Some((ins, outs)) = Mail::split(Mail::Bulk(copies)) {
+            if !ins.is_empty() {
+                self.store
+                    .persist(Mail::Bulk(ins))
+                    .and_then(|_| self.store.persist(Mail::Blank))?;
+            }
+            if !outs.is_empty() {
+                self.send_out(outs)?;
+            }
+        }
+        Ok(count)
+    }
+
+    //Record messages bound for other listeners in the outbox - they are sent out by the outbox
+    //delivery thread until acknowledged
+    fn send_out(&mut self, msgs: Vec<Msg>) -> Result<(), Error> {
+        self.store.save_outbound(&msgs, now_millis())?;
+        Outbox::wake();
+        Ok(())
+    }
+
+    //Define a pool of actors behind one address. Members are (re)defined as children of the
+    //pool - surplus members from an earlier definition are stopped.
+    pub(crate) fn define_pool(
+        &mut self,
+        addr: &Addr,
+        producer: impl Producer,
+        size: usize,
+        distribution: Distribution,
+    ) -> Result<Vec<Addr>, Error> {
+        let text = serde_json::to_string(&producer as &dyn Producer)?;
+        self.store.save_pool(
+            &addr.get_id().to_string(),
+            addr.get_name(),
+            &text,
+            &distribution.to_text(),
+            size,
+        )?;
+        let members = Self::members(addr, size);
+        for member in &members {
+            //Members defined by an earlier run are not necessarily active
+            let _rs = self.remove_actor_permanent(&member.get_id().to_string());
+            let supervision = Supervision::default();
+            self.define(member.clone(), &text, supervision, Some(addr.clone()))?;
+        }
+        self.stop_surplus_members(addr, &members)?;
+        Ok(members)
+    }
+
+    //Spawn missing members and stop the ones beyond the new size. Returns None if there is
+    //no such pool.
+    pub(crate) fn resize_pool(
+        &mut self,
+        addr: &Addr,
+        size: usize,
+    ) -> Result<Option<Vec<Addr>>, Error> {
+        let def = match self.pool_def(addr) {
+            Some(def) => def,
+            None => return Ok(None),
+        };
+        self.store.resize_pool(&addr.get_id().to_string(), size)?;
+        let members = Self::members(addr, size);
+        for member in &members {
+            if self
+                .retrieve_actor_def(&member.get_id().to_string())
+                .is_none()
+            {
+                let (producer, supervision) = (&def.producer, Supervision::default());
+                self.define(member.clone(), producer, supervision, Some(addr.clone()))?;
+            }
+        }
+        self.stop_surplus_members(addr, &members)?;
+        Ok(Some(members))
+    }
+
+    //Stop all the members and remove the pool definition. Returns false if there is no such
+    //pool.
+    pub(crate) fn remove_pool(&mut self, addr: &Addr) -> Result<bool, Error> {
+        if self.pool_def(addr).is_none() {
+            return Ok(false);
+        }
+        self.stop_surplus_members(addr, &[])?;
+        self.store
+            .delete_pool(&addr.get_id().to_string())
+            .map(|count| count > 0)
+    }
+
+    pub(crate) fn pool_members(&mut self, addr: &Addr) -> Option<Vec<Addr>> {
+        self.pool_def(addr).map(|def| Self::members(addr, def.size))
+    }
+
+    fn pool_def(&mut self, addr: &Addr) -> Option<PoolDef> {
+        match self.store.pool_def(&addr.get_id().to_string()) {
+            Ok(def) => def,
+            Err(err) => {
+                eprintln!("Error retrieving pool definition {}: {}", addr, err);
+                None
+            }
+        }
+    }
+
+    fn members(addr: &Addr, size: usize) -> Vec<Addr> {
+        (0..size).map(|index| pool::member(addr, index)).collect()
+    }
+
+    fn stop_surplus_members(&mut self, addr: &Addr, members: &[Addr]) -> Result<(), Error> {
+        let children = self.store.children_of(&addr.get_id().to_string())?;
+        for child in children {
+            if !members.contains(&child) {
+                self.stop(&child);
+            }
+        }
+        Ok(())
+    }
+
+    //Hand a message sent to a pool out to its members. Copies for the members are persisted
+    //just like any other message - each member processes its share in order. Messages
+    //already handed out - routed again on restart - are passed over.
+    fn distribute(&mut self, addr: &Addr, pool: PoolDef, rich_mail: &RichMail) {
+        let msg = match rich_mail.mail() {
+            Mail::Trade(msg) if rich_mail.seq() > pool.msg_seq => msg,
+            _ => return,
+        };
+        let members = Self::members(addr, pool.size);
+        let mut next = pool.next;
+        let targets = match Distribution::from_text(&pool.distribution) {
+            _ if members.is_empty() => Vec::new(),
+            Distribution::RoundRobin => {
+                next = (pool.next + 1) % members.len();
+                vec![members[pool.next % members.len()].clone()]
+            }
+            Distribution::SmallestInbox => members
+                .iter()
+                .min_by_key(|member| self.pending(member))
+                .cloned()
+                .into_iter()
+                .collect(),
+            Distribution::ConsistentHash => {
+                let key = msg
+                    .get_key()
+                    .map_or(*msg.get_id(), |key| compute_hash(&key));
+                vec![members[pool::jump_hash(key, members.len())].clone()]
+            }
+            Distribution::Broadcast => members,
+        };
+        if targets.is_empty() {
+            Self::record_dead_letter(&mut self.store, msg, ACTOR_NOT_FOUND);
+        } else {
+            let copies = targets.iter().map(|member| msg.copy_to(member)).collect();
+            if let Err(err) = self
+                .store
+                .persist(Mail::Bulk(copies))
+                .and_then(|_| self.store.persist(Mail::Blank))
+            {
+                eprintln!("Error handing out message to pool {}: {}", addr, err);
+                return;
+            }
+        }
+        Self::keep_message(&mut self.store, msg);
+        let pool_id = addr.get_id().to_string();
+        if let Err(err) = self.store.save_pool_cursor(&pool_id, next, rich_mail.seq()) {
+            eprintln!("Error saving pool progress {}: {}", addr, err);
+        }
+    }
+
+    //Messages persisted for an actor but not yet processed - a relative measure for
+    //comparing the members of a pool. Members busy with a message are not waited for - the
+    //sequence recorded in the backing store is good enough.
+    fn pending(&mut self, member: &Addr) -> i64 {
+        let identity = member.get_id().to_string();
+        let sequence = self
+            .actors
+            .try_with(member, |actor| CachedActor::get_sequence(actor));
+        let sequence = match sequence {
+            Some(sequence) => sequence,
+            None => self
+                .retrieve_actor_def(&identity)
+                .map_or(0, |def| def.msg_seq),
+        };
+        self.store
+            .max_msg_seq(&identity)
+            .map_or(0, |max_seq| max_seq - sequence)
+    }
+
+    //Drop an actor from memory - it gets reactivated from the backing store on its next
+    //message. Sequence and state are flushed so that it resumes where it left off.
+    fn passivate(&mut self, addr: &Addr) {
+        let mut actor = match Actors::remove(&mut self.actors, addr) {
+            Some(actor) => actor,
+            None => return,
+        };
+        CachedActor::flush_outputs(&mut actor);
+        let actor_id = addr.get_id();
+        if let Some(snapshot) = CachedActor::take_snapshot(&mut actor) {
+            Self::save_snapshot(&mut self.store, actor_id, &snapshot);
+        }
+        let sequence = CachedActor::get_sequence(&actor);
+        if let Err(err) =
+            Store::update_actor_event_seq(&mut self.store, sequence, &actor_id.to_string())
+        {
+            eprintln!(
+                "Error saving sequence of passivated actor {}: {}",
+                addr, err
+            );
+        }
+    }
+
+    //Passivate actors that have been idle for longer than configured
+    pub(crate) fn passivate_idle(&mut self) {
+        let idle = Config::get_shared().passivate_after();
+        if idle > 0 {
+            for addr in self.actors.idle_for(Duration::from_secs(idle)) {
+                if !self.actors.is_busy(&addr) {
+                    self.passivate(&addr);
+                }
             }
-            if panicked {
-                println!(
-                    "Actor panic count {}. Has exceeded tolerance ({:?}). Removing.",
-                    PanicWatch::count(actor_id),
-                    PanicWatch::tolerance()
-                );
-                Actors::remove(&mut self.actors, &actor_addr);
-                PanicWatch::remove_watch(&actor_id);
+        }
+    }
+
+    //Passivate the least recently used actors beyond the configured cache capacity
+    fn passivate_surplus(&mut self) {
+        let capacity = Config::get_shared().actor_cache_capacity();
+        if capacity > 0 && self.actors.len() > capacity {
+            for addr in self.actors.least_recent(self.actors.len() - capacity) {
+                if !self.actors.is_busy(&addr) {
+                    self.passivate(&addr);
+                }
+            }
+        }
+    }
+
+    fn save_snapshot(store: &mut Store, actor_id: u64, snapshot: &Snapshot) {
+        if let Some(bytes) = option_of_bytes(snapshot) {
+            if let Err(err) = store.save_state(&actor_id.to_string(), snapshot.seq, &bytes) {
+                eprintln!("Error saving actor snapshot {}", err);
             }
         }
     }
@@ -226,7 +901,8 @@ impl Context {
     }
 
     pub(crate) fn perist_buffered(&mut self, events: Vec<DBEvent>) -> Vec<i64> {
-        match self.store.persist_events(events.into_iter()) {
+        let rowids = events.into_iter().map(|DBEvent(row_id)| row_id).collect();
+        match self.store.persist_events(rowids) {
             Ok(events) => events,
             Err(err) => {
                 eprintln!("{}", err);
@@ -250,6 +926,11 @@ impl Drop for Context {
     }
 }
 
+//Initialize the Context - if not already done
+pub(crate) fn init() {
+    lazy_static::initialize(&CTX);
+}
+
 pub(crate) fn perist_buffered(events: Vec<DBEvent>) -> Vec<i64> {
     Context::handle().borrow_mut().perist_buffered(events)
 }
@@ -267,22 +948,1240 @@ pub(crate) fn past_events() -> Vec<RichMail> {
 ///pre-shutdown signal would be sent to the removed instance. New actor would be added to
 ///a pool of in memory actors and it would receive a startup signal. Supplied producer
 ///definition would be peristed in the backing store. On restart - actors will be restored
-///on demand to process pending or incoming messages. Actors will restart from where they
-///left off.
-
+///on demand to process pending or incoming messages. Actors will restart from where they left off.
 pub fn define_actor(addr: Addr, producer: impl Producer) -> Result<Option<CachedActor>, Error> {
-    Context::handle().borrow_mut().define_actor(addr, producer)
+    define_supervised_actor(addr, producer, Supervision::default())
+}
+
+///Define an actor in the system just like [define_actor] - with the given supervision
+///instead of the default one. Supervision decides what happens when the actor panics
+///while processing a message and gets persisted alongside the producer definition.
+pub fn define_supervised_actor(
+    addr: Addr,
+    producer: impl Producer,
+    supervision: Supervision,
+) -> Result<Option<CachedActor>, Error> {
+    when_idle(std::slice::from_ref(&addr), |ctx| {
+        ctx.define_actor(addr.clone(), producer, supervision)
+    })
+}
+
+//Work on the Context once none of the given actors is busy with a message - actors about to
+//be replaced are waited for with the Context released
+fn when_idle<R>(addrs: &[Addr], f: impl FnOnce(&mut Context) -> R) -> R {
+    loop {
+        let busy = {
+            let ctx = Context::handle();
+            let mut ctx = ctx.borrow_mut();
+            match addrs.iter().find_map(|addr| ctx.actors.busy_slot(addr)) {
+                Some(slot) => slot,
+                None => return f(&mut ctx),
+            }
+        };
+        //Not held while an async actor is pending on a future - looked at again shortly
+        if busy.try_lock().is_some() {
+            thread::sleep(Duration::from_millis(1));
+        } else {
+            drop(busy.lock());
+        }
+    }
 }
 
 pub(crate) fn ingress(mail: Mail) -> std::io::Result<Option<Mail>> {
     Context::handle().borrow_mut().ingress(mail)
 }
+
+pub(crate) fn ingest(mail: Mail) -> Result<Vec<u64>, Error> {
+    Context::handle().borrow_mut().ingest(mail)
+}
+//Ingress a message and wait for the reply that the recipient actor produces for it. The
+//buffered messages are flushed right away - we don't want the asked message to sit in the
+//buffer while the asker waits.
+pub(crate) fn ask(msg: Msg, timeout: Duration) -> Option<Mail> {
+    let msg_id = *msg.get_id();
+    let reply = Asks::register(msg_id);
+    if let Err(err) = ingress(Mail::Bulk(vec![msg])).and_then(|_| ingress(Mail::Blank)) {
+        eprintln!("Error ingressing asked message {}", err);
+    }
+    match reply.recv_timeout(timeout) {
+        Ok(reply) => Some(reply),
+        Err(_) => {
+            Asks::withdraw(msg_id);
+            None
+        }
+    }
+}
+//Fire schedules that have fallen due. Messages to remote actors are sent out once the
+//Context is released.
+pub(crate) fn fire_schedules() {
+    let outs = Context::handle().borrow_mut().fire_schedules();
+    if !outs.is_empty() {
+        if let Err(err) = send_out(outs) {
+            eprintln!("{:?}", err);
+        }
+    }
+}
+
+//Record messages bound for other listeners in the outbox - they are sent out by the outbox
+//delivery thread until acknowledged
+pub(crate) fn send_out(msgs: Vec<Msg>) -> Result<(), Error> {
+    Context::handle().borrow_mut().send_out(msgs)
+}
+
+//Record messages bound for other listeners in the outbox and send them out on the calling
+//thread. Those not acknowledged are left to the outbox delivery thread.
+pub(crate) fn send_out_now(msgs: Vec<Msg>) -> Result<(), Error> {
+    let due = {
+        let ctx = Context::handle();
+        ctx.borrow_mut().send_out(msgs)?;
+        Outbox::claim()
+    };
+    Outbox::deliver(due);
+    Ok(())
+}
+
+//Have the messages in the outbox that have fallen due sent out - those left over from
+//earlier runs included
+pub(crate) fn deliver_outbox() {
+    Outbox::wake();
+}
+
+pub(crate) fn passivate_idle() {
+    Context::handle().borrow_mut().passivate_idle();
+}
+
+pub(crate) fn sweep_expired() {
+    Context::handle().borrow_mut().sweep_expired();
+}
+
+pub(crate) fn compact_store() {
+    Context::handle().borrow_mut().compact();
+}
+
+//A copy of each message for each subscriber - each copy being a delivery of its own. Copies
+//are ordered message by message so that every subscriber sees the messages in the order
+//they were published.
+fn fan_out(subscribers: &[Addr], msgs: Vec<Msg>) -> Vec<Msg> {
+    let mut copies = Vec::with_capacity(subscribers.len() * msgs.len());
+    for msg in msgs {
+        for subscriber in subscribers {
+            let mut copy = msg.clone();
+            copy.renew_id();
+            copy.set_recipient_addr(subscriber);
+            copies.push(copy);
+        }
+    }
+    copies
+}
+
+//Milliseconds since epoch - schedules fall due in terms of these
+fn now_millis() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_millis() as i64)
+}
+
 pub(crate) fn egress(mail: RichMail) {
     Context::handle().borrow_mut().egress(mail);
 }
 /***pub(crate) fn restore(addr: Addr) -> Result<Option<CachedActor>, Error> {
     Context::handle().borrow_mut().restore(addr)
 }***/
+//Failed messages are retried outside the Context lock. Those to be retried after a delay
+//are retried on a thread of their own - the delegate moves on to other actors meanwhile,
+//while messages to the failed actor are held back to keep them in order. Likewise for
+//async actors pending on a future.
 pub(crate) fn handle_invocation(mail: RichMail) {
-    Context::handle().borrow_mut().handle_invocation(mail);
+    let actor_id = match mail.to() {
+        Some(addr) => addr.get_id(),
+        None => return,
+    };
+    if let Some(mail) = Backoffs::hold(mail) {
+        proceed(actor_id, invoke(mail));
+    }
+}
+
+//What is left to do once an actor is handed a message
+enum Invoked {
+    Done,
+    Retry(Box<Retry>),
+    Pending,
+}
+
+//Carry on with an actor - retrying a failed message, if so decided, followed by the
+//messages held back meanwhile. Left to the future once the actor is pending on one.
+fn proceed(actor_id: u64, mut invoked: Invoked) {
+    loop {
+        let mail = match invoked {
+            Invoked::Retry(retry) => match *retry {
+                (after, retry) if after.is_zero() => retry,
+                (after, retry) => return back_off(actor_id, after, retry),
+            },
+            Invoked::Done => match Backoffs::next(actor_id) {
+                Some(mail) => mail,
+                None => return,
+            },
+            Invoked::Pending => return,
+        };
+        invoked = invoke(mail);
+    }
+}
+
+//Hand a failed message to its recipient again once the delay is over - followed by the
+//messages held back meanwhile
+fn back_off(actor_id: u64, after: Duration, mail: RichMail) {
+    Backoffs::begin(actor_id);
+    thread::spawn(move || {
+        thread::sleep(after);
+        proceed(actor_id, invoke(mail));
+    });
+}
+
+//Actors run outside the Context lock - only the bookkeeping before and after takes the lock.
+//Messages to an actor are always handled by the same delegate - hence an actor processes its
+//messages one at a time and in order, while different actors run side by side. An async
+//actor stays busy until its future resolves - its messages are held back meanwhile.
+fn invoke(rich_mail: RichMail) -> Invoked {
+    let addr = match rich_mail.to() {
+        Some(addr) => addr.clone(),
+        None => return Invoked::Done,
+    };
+    loop {
+        let admitted = Context::handle().borrow_mut().admit(&rich_mail);
+        let (slot, poison) = match admitted {
+            Some(admitted) => admitted,
+            None => return Invoked::Done,
+        };
+        let mut cached = slot.lock();
+        //Passivated or replaced meanwhile - admit again
+        match cached.as_mut() {
+            Some(actor) => {
+                let processed = CachedActor::process(actor, rich_mail);
+                drop(cached);
+                let outcome = match processed {
+                    Processed::Done(outcome) => outcome,
+                    Processed::Pending(Pending(detached, asked)) => {
+                        Backoffs::begin(addr.get_id());
+                        executor::spawn(detached.0, move |resolved| {
+                            let outcome = slot
+                                .lock()
+                                .as_mut()
+                                .map(|actor| CachedActor::resolve(actor, resolved, asked));
+                            proceed(addr.get_id(), settle(&addr, &slot, poison, outcome));
+                        });
+                        return Invoked::Pending;
+                    }
+                };
+                return settle(&addr, &slot, poison, Some(outcome));
+            }
+            None => {
+                drop(cached);
+                Context::handle().borrow_mut().actors.release(&addr, &slot);
+            }
+        }
+    }
+}
+
+//Conclude the message once the actor is done with it - there is nothing to conclude if
+//the slot has been vacated meanwhile
+fn settle(addr: &Addr, slot: &Slot, poison: Option<RichMail>, outcome: Option<Outcome>) -> Invoked {
+    let ctx = Context::handle();
+    let mut ctx = ctx.borrow_mut();
+    match outcome {
+        Some(outcome) => match ctx.conclude(addr, slot, poison, outcome) {
+            Some(retry) => Invoked::Retry(Box::new(retry)),
+            None => Invoked::Done,
+        },
+        None => {
+            ctx.actors.release(addr, slot);
+            Invoked::Done
+        }
+    }
+}
+
+///List the dead letters - messages that could not be delivered because the recipient actor
+///was not defined or got evicted for panicking on them.
+pub fn dead_letters() -> Result<Vec<DeadLetter>, Error> {
+    Context::handle().borrow_mut().dead_letters()
+}
+
+///Inspect a dead letter by its id
+pub fn dead_letter(id: i64) -> Result<Option<DeadLetter>, Error> {
+    Context::handle().borrow_mut().dead_letter(id)
+}
+
+///Purge a dead letter by its id. Returns the number of dead letters purged.
+pub fn purge_dead_letter(id: i64) -> Result<usize, Error> {
+    Context::handle().borrow_mut().purge_dead_letters(Some(id))
+}
+
+///Purge all the dead letters. Returns the number of dead letters purged.
+pub fn purge_dead_letters() -> Result<usize, Error> {
+    Context::handle().borrow_mut().purge_dead_letters(None)
+}
+
+///Compact the backing store - deleting processed messages as the configured
+///[retention](Config::retention) allows. Works on a backend instance of its own, without
+///bringing up the system - so that it can be run against the sqlite store of a running
+///listener. Returns the number of messages deleted.
+pub fn compact() -> Result<usize, Error> {
+    let mut backend = backend::open();
+    backend.setup()?;
+    let config = Config::get_shared();
+    backend.compact(config.retention(), config.vacuum())
+}
+
+///Re-inject a dead letter into the normal ingress path - the message is sent again to the
+///listener fronting the actor it was meant for. The dead letter gets purged once the
+///message is in the outbox - it is sent until the listener acknowledges it. The message
+///goes out with a new id since it is a fresh delivery. Returns false if no such dead letter
+///exists.
+pub fn reinject_dead_letter(id: i64) -> Result<bool, Error> {
+    let dead_letter = dead_letter(id)?;
+    match dead_letter {
+        Some(dead_letter) => {
+            let mut msg = dead_letter.get_msg().clone();
+            msg.set_recipient_addr(dead_letter.get_addr());
+            //Original message is still around in the inbox - this is a new delivery
+            msg.renew_id();
+            send_out(vec![msg])?;
+            purge_dead_letter(id).map(|_| true)
+        }
+        None => Ok(false),
+    }
+}
+
+///Schedule a message for delivery to an actor after the given delay. Schedules are kept in
+///the backing store - they survive restarts. Returns the id of the schedule - which can be
+///used to cancel it.
+pub fn schedule_once(to: &Addr, msg: Msg, after: Duration) -> Result<u64, Error> {
+    schedule(to, msg, after, None)
+}
+
+///Schedule a message for delivery to an actor periodically - the first one after one
+///period. Returns the id of the schedule.
+pub fn schedule_every(to: &Addr, msg: Msg, every: Duration) -> Result<u64, Error> {
+    schedule(to, msg, every, Some(every))
+}
+
+fn schedule(
+    to: &Addr,
+    mut msg: Msg,
+    after: Duration,
+    every: Option<Duration>,
+) -> Result<u64, Error> {
+    msg.set_recipient_addr(to);
+    let id = compute_hash(&Uuid::new_v4());
+    Context::handle()
+        .borrow_mut()
+        .schedule(id, &msg, after, every)
+        .map(|_| id)
+}
+
+///Cancel a schedule. Returns false if no such schedule exists - a one-off schedule that has
+///already fired included.
+pub fn cancel_schedule(id: u64) -> Result<bool, Error> {
+    Context::handle().borrow_mut().cancel_schedule(id)
+}
+
+///Subscribe an actor to a topic. Messages published to the topic from then on are delivered
+///to the actor. Subscriptions are kept in the backing store - they survive restarts. Returns
+///false if the actor is already subscribed.
+pub fn subscribe(topic: &str, addr: &Addr) -> Result<bool, Error> {
+    Context::handle().borrow_mut().subscribe(topic, addr)
+}
+
+///Unsubscribe an actor from a topic. Returns false if the actor was not subscribed.
+pub fn unsubscribe(topic: &str, addr: &Addr) -> Result<bool, Error> {
+    Context::handle().borrow_mut().unsubscribe(topic, addr)
+}
+
+///Addresses of the actors subscribed to a topic
+pub fn subscribers(topic: &str) -> Result<Vec<Addr>, Error> {
+    Context::handle().borrow_mut().subscribers(topic)
+}
+
+///Publish a message to a topic - every subscriber gets its own copy of the message, which
+///is persisted and delivered just like a message sent to the subscriber directly. Returns
+///the number of copies sent out - zero if the topic has no subscribers.
+pub fn publish(topic: &str, msg: Msg) -> Result<usize, Error> {
+    publish_all(topic, vec![msg])
+}
+
+///Publish messages to a topic in one go. Each subscriber receives the messages in the given
+///order.
+pub fn publish_all(topic: &str, msgs: Vec<Msg>) -> Result<usize, Error> {
+    Context::handle().borrow_mut().publish(topic, msgs)
+}
+
+///Define a pool of `size` identical actors behind one address - all produced by the given
+///producer. Messages sent to the pool address are handed out to the members as per the
+///[Distribution]. Members are named `<pool name>#<index>` and are children of the pool. The
+///pool definition is persisted - redefining a pool redefines its members. Returns the
+///addresses of the members.
+pub fn define_pool(
+    addr: Addr,
+    producer: impl Producer,
+    size: usize,
+    distribution: Distribution,
+) -> Result<Vec<Addr>, Error> {
+    when_idle(&Context::members(&addr, size), |ctx| {
+        ctx.define_pool(&addr, producer, size, distribution)
+    })
+}
+
+///Resize a pool at runtime. Members are added or - highest indices first - stopped. Messages
+///pending for stopped members end up in the dead letters. Returns false if there is no such
+///pool.
+pub fn resize_pool(addr: &Addr, size: usize) -> Result<bool, Error> {
+    Context::handle()
+        .borrow_mut()
+        .resize_pool(addr, size)
+        .map(|members| members.is_some())
+}
+
+///Addresses of the members of a pool - None if there is no such pool
+pub fn pool_members(addr: &Addr) -> Option<Vec<Addr>> {
+    Context::handle().borrow_mut().pool_members(addr)
+}
+
+///Stop the members of a pool and remove the pool. Returns false if there is no such pool.
+pub fn remove_pool(addr: &Addr) -> Result<bool, Error> {
+    Context::handle().borrow_mut().remove_pool(addr)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Actor, ActorContext, AsyncActor, Asynchronous, Backend, MailFuture, Strategy};
+    use parking_lot::Mutex;
+    use serde::{Deserialize, Serialize};
+    use std::collections::HashSet;
+    use std::future::Future;
+    use std::pin::Pin;
+    use std::sync::Once;
+    use std::task::{self, Poll};
+    use std::time::Instant;
+
+    //Texts of the messages received - keyed by the receiving actor. Gates actors have
+    //entered and gates that have been opened.
+    lazy_static! {
+        static ref RECEIVED: Mutex<Vec<(String, String)>> = Mutex::new(Vec::new());
+        static ref ENTERED: Mutex<HashSet<String>> = Mutex::new(HashSet::new());
+        static ref OPENED: Mutex<HashSet<String>> = Mutex::new(HashSet::new());
+    }
+
+    //Bring up an actor system on the in-memory backend - shared by the tests
+    fn system() {
+        static SYSTEM: Once = Once::new();
+        SYSTEM.call_once(|| {
+            let mut config = Config::from_env();
+            config.set_backend(Backend::Memory);
+            Config::re_init(config);
+            init();
+        });
+    }
+
+    //Ingress messages as the listener would
+    fn deliver(msgs: Vec<Msg>) {
+        ingress(Mail::Bulk(msgs)).unwrap();
+        ingress(Mail::Blank).unwrap();
+    }
+
+    //Ingress a text message to an actor
+    fn tell(actor: &Addr, text: &str) {
+        deliver(vec![Msg::with_text(text, "from", actor.get_name())]);
+    }
+
+    //Texts received by an actor - waiting for as many as expected to arrive
+    fn received(actor: &str, expected: usize) -> Vec<String> {
+        let started = Instant::now();
+        loop {
+            let texts: Vec<_> = RECEIVED
+                .lock()
+                .iter()
+                .filter(|(name, _)| name == actor)
+                .map(|(_, text)| text.clone())
+                .collect();
+            if texts.len() >= expected || started.elapsed() > Duration::from_secs(5) {
+                return texts;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    //Dead letters of messages meant for an actor - waiting for as many as expected
+    fn dead_letters_of(actor: &str, expected: usize) -> Vec<DeadLetter> {
+        let started = Instant::now();
+        loop {
+            let dead_letters: Vec<_> = dead_letters()
+                .unwrap()
+                .into_iter()
+                .filter(|dead_letter| dead_letter.get_addr().get_name() == actor)
+                .collect();
+            if dead_letters.len() >= expected || started.elapsed() > Duration::from_secs(5) {
+                return dead_letters;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    //Whether the condition comes to hold within a while
+    fn eventually(condition: impl Fn() -> bool) -> bool {
+        let started = Instant::now();
+        while !condition() {
+            if started.elapsed() > Duration::from_secs(5) {
+                return false;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        true
+    }
+
+    //Whether an actor is defined in the system
+    fn defined(addr: &Addr) -> bool {
+        Context::handle()
+            .borrow_mut()
+            .retrieve_actor_def(&addr.get_id().to_string())
+            .is_some()
+    }
+
+    //Drop an actor from memory - once it is done with the message at hand
+    fn passivate(addr: &Addr) {
+        loop {
+            {
+                let ctx = Context::handle();
+                let mut ctx = ctx.borrow_mut();
+                if !ctx.actors.is_busy(addr) {
+                    ctx.passivate(addr);
+                    return;
+                }
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    #[derive(Debug, Default, Serialize, Deserialize)]
+    struct RecorderProducer;
+
+    #[typetag::serde]
+    impl Producer for RecorderProducer {
+        fn produce(&mut self) -> Box<dyn Actor> {
+            Box::new(Recorder)
+        }
+    }
+
+    struct Recorder;
+
+    impl Actor for Recorder {
+        fn receive(&mut self, mail: Mail) -> Option<Mail> {
+            if let Mail::Trade(ref msg) = mail {
+                if let (Some(to), Some(text)) = (msg.get_to(), msg.as_text()) {
+                    RECEIVED
+                        .lock()
+                        .push((to.get_name().to_string(), text.to_string()));
+                }
+            }
+            None
+        }
+    }
+
+    //Panics on the first message reading "boom" it is handed - across restarts
+    #[derive(Debug, Default, Serialize, Deserialize)]
+    struct FlakyProducer;
+
+    #[typetag::serde]
+    impl Producer for FlakyProducer {
+        fn produce(&mut self) -> Box<dyn Actor> {
+            Box::new(Flaky)
+        }
+    }
+
+    struct Flaky;
+
+    impl Actor for Flaky {
+        fn receive(&mut self, mail: Mail) -> Option<Mail> {
+            static FAILED: AtomicBool = AtomicBool::new(false);
+            if let Mail::Trade(ref msg) = mail {
+                if msg.as_text() == Some("boom") && !FAILED.swap(true, Ordering::AcqRel) {
+                    panic!("Failing on boom once");
+                }
+            }
+            Recorder.receive(mail)
+        }
+    }
+
+    //Calls into the catalog while handling a message - taking the Context meanwhile
+    #[derive(Debug, Default, Serialize, Deserialize)]
+    struct CallerProducer;
+
+    #[typetag::serde]
+    impl Producer for CallerProducer {
+        fn produce(&mut self) -> Box<dyn Actor> {
+            Box::new(Caller)
+        }
+    }
+
+    struct Caller;
+
+    impl Actor for Caller {
+        fn receive(&mut self, mail: Mail) -> Option<Mail> {
+            if let Mail::Trade(_) = mail {
+                thread::sleep(Duration::from_millis(20));
+                dead_letters().unwrap();
+            }
+            Recorder.receive(mail)
+        }
+    }
+
+    //Counts the messages it receives and records the count - the count is snapshotted
+    #[derive(Debug, Default, Serialize, Deserialize)]
+    struct TallyProducer;
+
+    #[typetag::serde]
+    impl Producer for TallyProducer {
+        fn produce(&mut self) -> Box<dyn Actor> {
+            Box::new(Tally(0))
+        }
+    }
+
+    struct Tally(u64);
+
+    impl Actor for Tally {
+        fn receive(&mut self, mail: Mail) -> Option<Mail> {
+            if let Mail::Trade(ref msg) = mail {
+                self.0 += 1;
+                if let Some(to) = msg.get_to() {
+                    RECEIVED
+                        .lock()
+                        .push((to.get_name().to_string(), self.0.to_string()));
+                }
+            }
+            None
+        }
+
+        fn snapshot(&self) -> Option<Vec<u8>> {
+            Some(self.0.to_be_bytes().to_vec())
+        }
+
+        fn restore_state(&mut self, state: &[u8]) {
+            self.0 = u64::from_be_bytes(state.try_into().expect("Tally state"));
+        }
+    }
+
+    //Counts the messages it receives like the tally - rebuilding the count by replay
+    #[derive(Debug, Default, Serialize, Deserialize)]
+    struct JournalProducer;
+
+    #[typetag::serde]
+    impl Producer for JournalProducer {
+        fn produce(&mut self) -> Box<dyn Actor> {
+            Box::new(Journal(Tally(0)))
+        }
+    }
+
+    struct Journal(Tally);
+
+    impl Actor for Journal {
+        fn receive(&mut self, mail: Mail) -> Option<Mail> {
+            self.0.receive(mail)
+        }
+
+        fn event_sourced(&self) -> bool {
+            true
+        }
+
+        fn replay(&mut self, _mail: Mail) {
+            (self.0).0 += 1;
+        }
+    }
+
+    //Panics on every message reading "boom"
+    #[derive(Debug, Default, Serialize, Deserialize)]
+    struct BombProducer;
+
+    #[typetag::serde]
+    impl Producer for BombProducer {
+        fn produce(&mut self) -> Box<dyn Actor> {
+            Box::new(Bomb)
+        }
+    }
+
+    struct Bomb;
+
+    impl Actor for Bomb {
+        fn receive(&mut self, mail: Mail) -> Option<Mail> {
+            if let Mail::Trade(ref msg) = mail {
+                if msg.as_text() == Some("boom") {
+                    panic!("Failing on boom");
+                }
+            }
+            Recorder.receive(mail)
+        }
+    }
+
+    //Spawns and stops children as told - "spawn:<name>", "stop:<name>" and "quit" to stop
+    //itself. Records the other messages.
+    #[derive(Debug, Default, Serialize, Deserialize)]
+    struct ParentProducer;
+
+    #[typetag::serde]
+    impl Producer for ParentProducer {
+        fn produce(&mut self) -> Box<dyn Actor> {
+            Box::new(Parent)
+        }
+    }
+
+    struct Parent;
+
+    impl Actor for Parent {
+        fn receive(&mut self, mail: Mail) -> Option<Mail> {
+            Recorder.receive(mail)
+        }
+
+        fn receive_with(&mut self, mail: Mail, ctx: &mut ActorContext) -> Option<Mail> {
+            let text = match mail {
+                Mail::Trade(ref msg) => msg.as_text().unwrap_or_default().to_string(),
+                _ => return None,
+            };
+            match text.split_once(':') {
+                Some(("spawn", child)) => {
+                    ctx.spawn(child, ParentProducer).unwrap();
+                }
+                Some(("stop", child)) => ctx.stop(&Addr::new(child)),
+                _ if text == "quit" => ctx.stop_self(),
+                _ => return self.receive(mail),
+            }
+            None
+        }
+    }
+
+    //Waits at the gate named in a "wait:<gate>" message until it is opened - calling into
+    //the catalog once let through. Records the messages.
+    #[derive(Debug, Default, Serialize, Deserialize)]
+    struct GateProducer;
+
+    #[typetag::serde]
+    impl Producer for GateProducer {
+        fn produce(&mut self) -> Box<dyn Actor> {
+            Box::new(Gate)
+        }
+    }
+
+    struct Gate;
+
+    impl Actor for Gate {
+        fn receive(&mut self, mail: Mail) -> Option<Mail> {
+            if let Mail::Trade(ref msg) = mail {
+                if let Some(("wait", gate)) = msg.as_text().and_then(|text| text.split_once(':')) {
+                    ENTERED.lock().insert(gate.to_string());
+                    while !OPENED.lock().contains(gate) {
+                        thread::sleep(Duration::from_millis(10));
+                    }
+                    dead_letters().unwrap();
+                }
+            }
+            Recorder.receive(mail)
+        }
+    }
+
+    //Like the gate - but waits asynchronously
+    #[derive(Debug, Default, Serialize, Deserialize)]
+    struct AsyncGateProducer;
+
+    #[typetag::serde]
+    impl Producer for AsyncGateProducer {
+        fn produce(&mut self) -> Box<dyn Actor> {
+            Box::new(Asynchronous::new(AsyncGate))
+        }
+    }
+
+    struct AsyncGate;
+
+    impl AsyncActor for AsyncGate {
+        fn receive(&mut self, mail: Mail) -> MailFuture<'_> {
+            Box::pin(async move {
+                if let Mail::Trade(ref msg) = mail {
+                    if let Some(("wait", gate)) =
+                        msg.as_text().and_then(|text| text.split_once(':'))
+                    {
+                        ENTERED.lock().insert(gate.to_string());
+                        Opened(gate.to_string()).await;
+                    }
+                }
+                Recorder.receive(mail)
+            })
+        }
+    }
+
+    //Ready once the gate is opened - checked on again shortly otherwise
+    struct Opened(String);
+
+    impl Future for Opened {
+        type Output = ();
+        fn poll(self: Pin<&mut Self>, cx: &mut task::Context<'_>) -> Poll<()> {
+            if OPENED.lock().contains(&self.0) {
+                return Poll::Ready(());
+            }
+            let waker = cx.waker().clone();
+            thread::spawn(move || {
+                thread::sleep(Duration::from_millis(10));
+                waker.wake();
+            });
+            Poll::Pending
+        }
+    }
+
+    #[test]
+    fn handle_invocation_async_test_1() {
+        system();
+        let waiter = Addr::new("async_waiter");
+        define_actor(waiter.clone(), AsyncGateProducer).unwrap();
+        //Handled by the same delegate as the async actor
+        let delegates = num_cpus::get() as u64;
+        let bystander = (0..)
+            .map(|index| Addr::new(&format!("async_bystander_{}", index)))
+            .find(|addr| addr.get_id() % delegates == waiter.get_id() % delegates)
+            .unwrap();
+        define_actor(bystander.clone(), RecorderProducer).unwrap();
+        tell(&waiter, "wait:async_gate");
+        assert!(eventually(|| ENTERED.lock().contains("async_gate")));
+        tell(&waiter, "after");
+        tell(&bystander, "meanwhile");
+        //The delegate moves on while the future is pending - the next message to the async
+        //actor is held back until it resolves
+        let progressed = received(bystander.get_name(), 1);
+        let held = received(waiter.get_name(), 0);
+        OPENED.lock().insert("async_gate".to_string());
+        assert_eq!(progressed, ["meanwhile"]);
+        assert!(held.is_empty());
+        assert_eq!(received(waiter.get_name(), 2), ["wait:async_gate", "after"]);
+    }
+
+    #[test]
+    fn handle_invocation_concurrency_test_1() {
+        system();
+        let actors: Vec<_> = (0..8)
+            .map(|index| Addr::new(&format!("concurrent_caller_{}", index)))
+            .collect();
+        for addr in &actors {
+            define_actor(addr.clone(), CallerProducer).unwrap();
+        }
+        let texts = ["one", "two", "three"];
+        deliver(
+            actors
+                .iter()
+                .flat_map(|addr| {
+                    texts
+                        .iter()
+                        .map(|text| Msg::with_text(text, "from", addr.get_name()))
+                })
+                .collect(),
+        );
+        //Actors get redefined while busy with their messages
+        let done = Arc::new(AtomicBool::new(false));
+        let redefined = (actors.clone(), Arc::clone(&done));
+        thread::spawn(move || {
+            let (actors, done) = redefined;
+            while !done.load(Ordering::Acquire) {
+                for addr in &actors {
+                    define_actor(addr.clone(), CallerProducer).unwrap();
+                }
+            }
+        });
+        for addr in &actors {
+            assert_eq!(received(addr.get_name(), texts.len()), texts);
+        }
+        done.store(true, Ordering::Release);
+    }
+
+    #[test]
+    fn handle_invocation_backoff_test_1() {
+        system();
+        let flaky = Addr::new("backoff_flaky");
+        let backoff = Strategy::Backoff {
+            initial: Duration::from_secs(2),
+            max: Duration::from_secs(2),
+        };
+        define_supervised_actor(flaky.clone(), FlakyProducer, Supervision::new(backoff)).unwrap();
+        //An actor handled by the same delegate as the one backing off
+        let delegates = num_cpus::get() as u64;
+        let neighbour = (0..)
+            .map(|index| Addr::new(&format!("backoff_neighbour_{}", index)))
+            .find(|addr| addr.get_id() % delegates == flaky.get_id() % delegates)
+            .unwrap();
+        define_actor(neighbour.clone(), RecorderProducer).unwrap();
+        let started = Instant::now();
+        deliver(vec![
+            Msg::with_text("boom", "from", flaky.get_name()),
+            Msg::with_text("after", "from", flaky.get_name()),
+        ]);
+        thread::sleep(Duration::from_millis(200));
+        deliver(vec![Msg::with_text("hello", "from", neighbour.get_name())]);
+        assert_eq!(received(neighbour.get_name(), 1), ["hello"]);
+        assert!(started.elapsed() < Duration::from_secs(2));
+        //The failed message goes again after the delay - ahead of the one held back
+        assert_eq!(received(flaky.get_name(), 2), ["boom", "after"]);
+        assert!(started.elapsed() >= Duration::from_secs(2));
+    }
+
+    #[test]
+    fn publish_all_test_1() {
+        system();
+        let local = [Addr::new("pubsub_local_1"), Addr::new("pubsub_local_2")];
+        let remote = Addr::remote("pubsub_remote", "192.0.2.10:7171");
+        for addr in &local {
+            define_actor(addr.clone(), RecorderProducer).unwrap();
+            assert!(subscribe("pubsub_news", addr).unwrap());
+        }
+        assert!(subscribe("pubsub_news", &remote).unwrap());
+        let msgs = vec![Msg::from_text("first"), Msg::from_text("second")];
+        assert_eq!(publish_all("pubsub_news", msgs).unwrap(), 6);
+        //Local copies go straight to the subscribers - in the order published
+        for addr in &local {
+            assert_eq!(received(addr.get_name(), 2), ["first", "second"]);
+        }
+        //Only remote copies are sent out
+        let outbound = Context::handle()
+            .borrow_mut()
+            .store
+            .take_due_outbound(now_millis() + 3_600_000)
+            .unwrap();
+        let outbound: Vec<_> = outbound
+            .iter()
+            .filter_map(|msg| Some((msg.get_to().as_ref()?.get_name().as_str(), msg.as_text()?)))
+            .filter(|(name, _)| name.starts_with("pubsub_"))
+            .collect();
+        assert_eq!(
+            outbound,
+            [("pubsub_remote", "first"), ("pubsub_remote", "second")]
+        );
+    }
+
+    #[test]
+    fn passivate_snapshot_test_1() {
+        system();
+        let tally = Addr::new("snapshot_tally");
+        define_actor(tally.clone(), TallyProducer).unwrap();
+        deliver(
+            ["a", "b", "c"]
+                .iter()
+                .map(|text| Msg::with_text(text, "from", tally.get_name()))
+                .collect(),
+        );
+        assert_eq!(received(tally.get_name(), 3), ["1", "2", "3"]);
+        //State is snapshotted on the way out of memory
+        passivate(&tally);
+        let def = {
+            let ctx = Context::handle();
+            let mut ctx = ctx.borrow_mut();
+            assert!(!ctx.actors.contains(&tally));
+            ctx.retrieve_actor_def(&tally.get_id().to_string()).unwrap()
+        };
+        let snapshot = from_bytes::<'_, Snapshot>(&def.state.unwrap()).unwrap();
+        assert_eq!(snapshot.state, 3u64.to_be_bytes());
+        //And restored along with the actor on its next message
+        deliver(vec![Msg::with_text("d", "from", tally.get_name())]);
+        assert_eq!(received(tally.get_name(), 4), ["1", "2", "3", "4"]);
+    }
+
+    #[test]
+    fn restore_replay_test_1() {
+        system();
+        let journal = Addr::new("replay_journal");
+        define_actor(journal.clone(), JournalProducer).unwrap();
+        deliver(
+            ["a", "b", "c"]
+                .iter()
+                .map(|text| Msg::with_text(text, "from", journal.get_name()))
+                .collect(),
+        );
+        assert_eq!(received(journal.get_name(), 3), ["1", "2", "3"]);
+        passivate(&journal);
+        let def = Context::handle()
+            .borrow_mut()
+            .retrieve_actor_def(&journal.get_id().to_string())
+            .unwrap();
+        assert!(def.state.is_none());
+        //Messages already processed are replayed on restoration - not received again
+        deliver(vec![Msg::with_text("d", "from", journal.get_name())]);
+        assert_eq!(received(journal.get_name(), 4), ["1", "2", "3", "4"]);
+        thread::sleep(Duration::from_millis(100));
+        assert_eq!(received(journal.get_name(), 4).len(), 4);
+    }
+
+    #[test]
+    fn dead_letters_test_1() {
+        system();
+        let nobody = Addr::new("dead_letters_nobody");
+        deliver(vec![Msg::with_text("lost", "from", nobody.get_name())]);
+        let recorded = dead_letters_of(nobody.get_name(), 1);
+        assert_eq!(recorded.len(), 1);
+        let id = recorded[0].get_id();
+        let inspected = dead_letter(id).unwrap().unwrap();
+        assert_eq!(inspected.get_reason(), ACTOR_NOT_FOUND);
+        assert_eq!(inspected.get_msg().as_text(), Some("lost"));
+        assert_eq!(purge_dead_letter(id).unwrap(), 1);
+        assert!(dead_letter(id).unwrap().is_none());
+        assert_eq!(purge_dead_letter(id).unwrap(), 0);
+    }
+
+    #[test]
+    fn dead_letters_test_2() {
+        system();
+        let bomb = Addr::new("dead_letters_bomb");
+        define_actor(bomb.clone(), BombProducer).unwrap();
+        deliver(vec![
+            Msg::with_text("boom", "from", bomb.get_name()),
+            Msg::with_text("after", "from", bomb.get_name()),
+        ]);
+        //Restarted actor skips the message it failed on and moves on to the next one
+        assert_eq!(received(bomb.get_name(), 1), ["after"]);
+        let recorded = dead_letters_of(bomb.get_name(), 1);
+        assert_eq!(recorded.len(), 1);
+        assert_eq!(recorded[0].get_reason(), MESSAGE_SKIPPED);
+        assert_eq!(recorded[0].get_msg().as_text(), Some("boom"));
+    }
+
+    #[test]
+    fn reinject_dead_letter_test_1() {
+        system();
+        let nobody = Addr::new("reinjected_nobody");
+        deliver(vec![Msg::with_text("again", "from", nobody.get_name())]);
+        let recorded = dead_letters_of(nobody.get_name(), 1);
+        let id = recorded[0].get_id();
+        assert!(reinject_dead_letter(id).unwrap());
+        assert!(dead_letter(id).unwrap().is_none());
+        assert!(!reinject_dead_letter(id).unwrap());
+        //Sent out afresh to the listener fronting the actor - until acknowledged
+        let outbound = Context::handle()
+            .borrow_mut()
+            .store
+            .take_due_outbound(now_millis() + 3_600_000)
+            .unwrap();
+        let reinjected: Vec<_> = outbound
+            .iter()
+            .filter(|msg| msg.get_to().as_ref() == Some(&nobody))
+            .collect();
+        assert_eq!(reinjected.len(), 1);
+        assert_eq!(reinjected[0].as_text(), Some("again"));
+        assert_ne!(reinjected[0].get_id(), recorded[0].get_msg().get_id());
+    }
+
+    #[test]
+    fn child_actors_test_1() {
+        system();
+        let parent = Addr::new("hierarchy_parent");
+        let child = Addr::new("hierarchy_child");
+        define_actor(parent.clone(), ParentProducer).unwrap();
+        tell(&parent, "spawn:hierarchy_child");
+        assert!(eventually(|| defined(&child)));
+        let children = Context::handle()
+            .borrow_mut()
+            .store
+            .children_of(&parent.get_id().to_string())
+            .unwrap();
+        assert_eq!(children, std::slice::from_ref(&child));
+        tell(&child, "hello");
+        assert_eq!(received(child.get_name(), 1), ["hello"]);
+        //Stopped child is gone for good - messages to it end up in the dead letters
+        tell(&parent, "stop:hierarchy_child");
+        assert!(eventually(|| !defined(&child)));
+        assert!(defined(&parent));
+        tell(&child, "too late");
+        let recorded = dead_letters_of(child.get_name(), 1);
+        assert_eq!(recorded[0].get_reason(), ACTOR_NOT_FOUND);
+    }
+
+    #[test]
+    fn child_actors_test_2() {
+        system();
+        let parent = Addr::new("cascade_parent");
+        let child = Addr::new("cascade_child");
+        let grandchild = Addr::new("cascade_grandchild");
+        let stranger = Addr::new("cascade_stranger");
+        define_actor(parent.clone(), ParentProducer).unwrap();
+        define_actor(stranger.clone(), ParentProducer).unwrap();
+        tell(&parent, "spawn:cascade_child");
+        assert!(eventually(|| defined(&child)));
+        tell(&child, "spawn:cascade_grandchild");
+        assert!(eventually(|| defined(&grandchild)));
+        //Actors other than own children are left alone
+        tell(&stranger, "stop:cascade_child");
+        tell(&stranger, "ping");
+        assert_eq!(received(stranger.get_name(), 1), ["ping"]);
+        assert!(defined(&child));
+        //Stopping an actor stops its children and theirs
+        tell(&parent, "quit");
+        assert!(eventually(|| !defined(&parent)));
+        assert!(!defined(&child));
+        assert!(!defined(&grandchild));
+    }
+
+    #[test]
+    fn child_actors_test_3() {
+        system();
+        //Off the default port - addresses rebuilt from names would be of other actors
+        let port = Config::get_shared().port() + 1;
+        let mut parent = Addr::new("ported_parent");
+        parent.with_port(port);
+        let mut child = Addr::new("ported_child");
+        child.with_port(port);
+        let text = serde_json::to_string(&ParentProducer as &dyn Producer).unwrap();
+        let ctx = Context::handle();
+        let mut ctx = ctx.borrow_mut();
+        ctx.define_actor(parent.clone(), ParentProducer, Supervision::default())
+            .unwrap();
+        ctx.spawn(&parent, child.clone(), &text, Supervision::default());
+        let parent_id = parent.get_id().to_string();
+        let def = ctx.retrieve_actor_def(&child.get_id().to_string()).unwrap();
+        assert_eq!(def.parent, Some((parent_id.clone(), parent.clone())));
+        assert_eq!(ctx.store.children_of(&parent_id).unwrap(), [child.clone()]);
+        assert!(ctx.restore(child.clone()).is_ok());
+        ctx.stop(&parent);
+        assert!(ctx
+            .retrieve_actor_def(&child.get_id().to_string())
+            .is_none());
+        assert!(ctx.retrieve_actor_def(&parent_id).is_none());
+    }
+
+    #[test]
+    fn schedule_once_test_1() {
+        system();
+        let timer = Addr::new("schedule_once_timer");
+        define_actor(timer.clone(), RecorderProducer).unwrap();
+        let started = Instant::now();
+        let id =
+            schedule_once(&timer, Msg::from_text("later"), Duration::from_millis(500)).unwrap();
+        thread::sleep(Duration::from_millis(200));
+        assert!(received(timer.get_name(), 0).is_empty());
+        assert_eq!(received(timer.get_name(), 1), ["later"]);
+        assert!(started.elapsed() >= Duration::from_millis(500));
+        //Fired one-off schedules are gone
+        assert!(!cancel_schedule(id).unwrap());
+    }
+
+    #[test]
+    fn schedule_every_test_1() {
+        system();
+        let ticker = Addr::new("schedule_every_ticker");
+        define_actor(ticker.clone(), RecorderProducer).unwrap();
+        let id =
+            schedule_every(&ticker, Msg::from_text("tick"), Duration::from_millis(200)).unwrap();
+        assert_eq!(received(ticker.get_name(), 3), ["tick", "tick", "tick"]);
+        assert!(cancel_schedule(id).unwrap());
+        assert!(!cancel_schedule(id).unwrap());
+        //Ticks fired before the cancellation might still be on their way
+        thread::sleep(Duration::from_millis(300));
+        let ticks = received(ticker.get_name(), 0).len();
+        thread::sleep(Duration::from_millis(600));
+        assert_eq!(received(ticker.get_name(), 0).len(), ticks);
+    }
+
+    #[test]
+    fn passivation_order_test_1() {
+        let mut actors = Actors::new();
+        let addrs: Vec<_> = (0..3)
+            .map(|index| Addr::new(&format!("passivation_order_{}", index)))
+            .collect();
+        let text = serde_json::to_string(&RecorderProducer as &dyn Producer).unwrap();
+        for addr in &addrs {
+            let actor = CachedActor::new(
+                &text,
+                addr.clone(),
+                None,
+                None,
+                Supervision::default(),
+                None,
+            );
+            actors.add(addr.clone(), actor.unwrap());
+        }
+        actors.touch(&addrs[0]);
+        assert_eq!(actors.least_recent(2), [addrs[1].clone(), addrs[2].clone()]);
+        assert!(actors.idle_for(Duration::from_secs(60)).is_empty());
+        thread::sleep(Duration::from_millis(100));
+        actors.touch(&addrs[1]);
+        assert_eq!(
+            actors.idle_for(Duration::from_millis(100)),
+            [addrs[2].clone(), addrs[0].clone()]
+        );
+        assert!(Actors::remove(&mut actors, &addrs[2]).is_some());
+        assert_eq!(actors.len(), 2);
+        assert_eq!(actors.least_recent(3), [addrs[0].clone(), addrs[1].clone()]);
+    }
+
+    #[test]
+    fn passivation_test_1() {
+        system();
+        let sleeper = Addr::new("passivated_sleeper");
+        define_actor(sleeper.clone(), RecorderProducer).unwrap();
+        tell(&sleeper, "before");
+        assert_eq!(received(sleeper.get_name(), 1), ["before"]);
+        passivate(&sleeper);
+        assert!(!Context::handle().borrow().actors.contains(&sleeper));
+        //Reactivated on its next message - resuming where it left off
+        tell(&sleeper, "after");
+        assert_eq!(received(sleeper.get_name(), 2), ["before", "after"]);
+        thread::sleep(Duration::from_millis(100));
+        assert_eq!(received(sleeper.get_name(), 0), ["before", "after"]);
+        assert!(Context::handle().borrow().actors.contains(&sleeper));
+    }
+
+    #[test]
+    fn define_pool_busy_member_test_1() {
+        system();
+        let pool = Addr::new("busy_pool");
+        define_pool(pool.clone(), GateProducer, 2, Distribution::RoundRobin).unwrap();
+        let busy = pool::member(&pool, 0);
+        tell(&busy, "wait:busy_pool");
+        assert!(eventually(|| ENTERED.lock().contains("busy_pool")));
+        //Redefinition waits for the busy member - without holding up the rest of the system
+        let redefined = {
+            let pool = pool.clone();
+            thread::spawn(move || {
+                define_pool(pool, GateProducer, 2, Distribution::RoundRobin).unwrap()
+            })
+        };
+        let bystander = Addr::new("busy_pool_bystander");
+        let defined = thread::spawn(move || define_actor(bystander, RecorderProducer).unwrap());
+        let progressed = eventually(|| defined.is_finished());
+        let waited = !redefined.is_finished();
+        OPENED.lock().insert("busy_pool".to_string());
+        assert!(progressed);
+        assert!(waited);
+        assert_eq!(redefined.join().unwrap().len(), 2);
+        assert_eq!(received(busy.get_name(), 1), ["wait:busy_pool"]);
+    }
+
+    #[test]
+    fn resize_pool_busy_member_test_1() {
+        system();
+        let pool = Addr::new("shrinking_pool");
+        define_pool(pool.clone(), GateProducer, 2, Distribution::RoundRobin).unwrap();
+        let busy = pool::member(&pool, 1);
+        tell(&busy, "wait:shrinking_pool");
+        assert!(eventually(|| ENTERED.lock().contains("shrinking_pool")));
+        //Busy member is not waited for - it is shut down once done with the message at hand
+        let resized = {
+            let pool = pool.clone();
+            thread::spawn(move || resize_pool(&pool, 1).unwrap())
+        };
+        let finished = eventually(|| resized.is_finished());
+        OPENED.lock().insert("shrinking_pool".to_string());
+        assert!(finished);
+        assert!(resized.join().unwrap());
+        assert!(!defined(&busy));
+        assert_eq!(received(busy.get_name(), 1), ["wait:shrinking_pool"]);
+        assert!(eventually(|| !Context::handle()
+            .borrow()
+            .actors
+            .contains(&busy)));
+        //Growing the pool again brings up a fresh member
+        assert!(resize_pool(&pool, 2).unwrap());
+        tell(&busy, "again");
+        assert_eq!(
+            received(busy.get_name(), 2),
+            ["wait:shrinking_pool", "again"]
+        );
+    }
 }
@@ -0,0 +1,71 @@
+use super::{now_millis, Context};
+use crate::constants::OUTBOX_TICK;
+use crate::routing::messenger::Messenger;
+use crate::Msg;
+use lazy_static::lazy_static;
+use parking_lot::Mutex;
+use std::sync::mpsc::{channel, RecvTimeoutError, Sender};
+use std::thread;
+use std::time::Duration;
+
+//Wakes up the thread sending out the messages in the outbox - one for all hosts. Neither
+//the callers recording messages nor the store event loop wait on other listeners.
+lazy_static! {
+    static ref DELIVERY: Mutex<Sender<()>> = Mutex::new(Outbox::start());
+}
+
+pub(super) struct Outbox;
+
+impl Outbox {
+    //Have messages that have fallen due sent out - starting the delivery thread if need be
+    pub(super) fn wake() {
+        if DELIVERY.lock().send(()).is_err() {
+            eprintln!("Outbox delivery thread has exited");
+        }
+    }
+
+    //Claim the messages in the outbox that have fallen due - they fall due again after a
+    //backoff unless acknowledged in the meantime
+    pub(super) fn claim() -> Vec<Msg> {
+        let due = Context::handle()
+            .borrow_mut()
+            .store
+            .take_due_outbound(now_millis());
+        due.unwrap_or_else(|err| {
+            eprintln!("Error fetching outbound messages {}", err);
+            Vec::new()
+        })
+    }
+
+    //Send out claimed messages and forget those the listeners acknowledge. Context must not
+    //be held while sending - the listener might be this very process.
+    pub(super) fn deliver(msgs: Vec<Msg>) {
+        if msgs.is_empty() {
+            return;
+        }
+        for (host_addr, msg_ids) in Messenger::deliver(msgs) {
+            let host = host_addr.to_string();
+            if let Err(err) = Context::handle()
+                .borrow_mut()
+                .store
+                .ack_outbound(&host, &msg_ids)
+            {
+                eprintln!("Error acknowledging outbound messages {}", err);
+            }
+        }
+    }
+
+    //Messages are looked for when woken up and once every tick - for those due to be sent
+    //again
+    fn start() -> Sender<()> {
+        let (waker, wake_ups) = channel();
+        thread::spawn(move || {
+            let tick = Duration::from_millis(OUTBOX_TICK);
+            while let Ok(()) | Err(RecvTimeoutError::Timeout) = wake_ups.recv_timeout(tick) {
+                while wake_ups.try_recv().is_ok() {}
+                Self::deliver(Self::claim());
+            }
+        });
+        waker
+    }
+}
@@ -1,7 +1,13 @@
-use crate::{Mail, Msg};
+use crate::common::context::Detached;
+use crate::common::executor;
+use crate::{ActorContext, Mail, Msg};
+use parking_lot::Mutex;
 use serde::{Deserialize, Serialize};
 use std::any::{self, Any};
 use std::fmt::{self, Debug, Formatter};
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
 
 pub trait Actor: Any + Send + Sync {
     //! # Actor
@@ -24,9 +30,19 @@ pub trait Actor: Any + Send + Sync {
     ///
     ///Incoming payload will be of type [Trade(Msg)](crate::common::mail::Mail).
     ///
-
     fn receive(&mut self, mail: Mail) -> Option<Mail>;
 
+    ///
+    ///Called to handle incoming messages along with the [ActorContext] - which tells the
+    ///actor about itself and the sender, and lets it send and schedule messages, spawn or
+    ///stop child actors and stop itself. Defaults to calling [receive](Actor::receive).
+    ///Actors that need the context override this method - and can leave `receive` with a
+    ///trivial implementation.
+    ///
+    fn receive_with(&mut self, mail: Mail, _ctx: &mut ActorContext) -> Option<Mail> {
+        self.receive(mail)
+    }
+
     ///
     ///Name of the type implementing the `Actor` trait
     ///
@@ -37,21 +53,57 @@ pub trait Actor: Any + Send + Sync {
     ///The startup signal that the actor receives upon definition - when actor is defined via
     ///[`define_actor!`](crate::define_actor) macro or on restoration from the backing store.
     ///
-
     fn post_start(&mut self, _mail: Mail) -> Option<Mail> {
         Some(Msg::from_text("Start up signal received").into())
     }
 
     ///
-    ///Pre-shutdown signal that the actor will receive at normal shutdown or  actor
-    ///eviction due to actor panicking while processing message. An actor is allowed to
-    ///panic a set number of times(currently 3) before eviction. Actor might panic due to
-    ///internal(faulty logic, index bounds exception) or external reasons like message
-    ///getting corrupted in transit.
+    ///Pre-shutdown signal that the actor will receive at normal shutdown, restart or stop
+    ///due to actor panicking while processing message. What happens to a panicking actor
+    ///is decided by its [Supervision](crate::Supervision) - by default the actor is
+    ///restarted skipping the message and stopped after 3 failures within a minute. Actor
+    ///might panic due to internal(faulty logic, index bounds exception) or external reasons
+    ///like message getting corrupted in transit.
     ///
     fn pre_shutdown(&mut self, _mail: Mail) -> Option<Mail> {
         Some(Msg::from_text("Shutdown signal received").into())
     }
+
+    ///
+    ///Optional hook to capture the in-memory state of the actor. The returned bytes get
+    ///persisted in the backing store periodically(configurable via `snapshot_every` and
+    ///`snapshot_interval`) and fed back via [restore_state](Actor::restore_state) when the
+    ///actor is restored. Stateless actors need not implement it.
+    ///
+    fn snapshot(&self) -> Option<Vec<u8>> {
+        None
+    }
+
+    ///
+    ///Rebuild in-memory state from the latest snapshot. Gets called before the actor
+    ///receives the startup signal on restoration.
+    ///
+    fn restore_state(&mut self, _state: &[u8]) {}
+
+    ///
+    ///Opt in to event sourced recovery. On restoration, an event sourced actor is fed the
+    ///messages it had already processed - from the latest snapshot, or from the very first
+    ///message if there is no snapshot - via [replay](Actor::replay) before it starts
+    ///receiving live messages.
+    ///
+    fn event_sourced(&self) -> bool {
+        false
+    }
+
+    ///
+    ///Re-apply an already processed message to rebuild state. Outgoing mail is suppressed
+    ///during replay - hence nothing to return. Defaults to calling `receive` and discarding
+    ///its outcome - so the actor rebuilds its state deterministically given `receive` is
+    ///deterministic.
+    ///
+    fn replay(&mut self, mail: Mail) {
+        let _suppressed = self.receive(mail);
+    }
 }
 
 impl Debug for dyn Actor {
@@ -60,6 +112,118 @@ impl Debug for dyn Actor {
         write!(f, "{}", debug_msg)
     }
 }
+
+///Future returned by an [AsyncActor] for a message - resolves to the outgoing mail, if any
+pub type MailFuture<'a> = Pin<Box<dyn Future<Output = Option<Mail>> + Send + 'a>>;
+
+pub trait AsyncActor: Any + Send + Sync {
+    //! # AsyncActor
+    //!
+    //!`AsyncActor` - an actor whose message handling is asynchronous. Handling a message
+    //!returns a future - which gets driven to completion on the executor before the actor
+    //!receives its next message. Hence async actors keep the ordering guarantees of the
+    //!synchronous ones - while other actors carry on with their messages meanwhile. The
+    //!executor is a minimal built-in one by default - the `tokio` feature runs the futures
+    //!within a shared tokio runtime.
+    //!
+    //!Async actors are handed out by [Producer](crate::Producer)s wrapped in an [Asynchronous].
+    //!A panic while the future is polled is accounted for just like a panic in
+    //![Actor::receive] - the [Supervision](crate::Supervision) of the actor decides what
+    //!happens next.
+
+    ///The required method - handle an incoming message asynchronously
+    fn receive(&mut self, mail: Mail) -> MailFuture<'_>;
+
+    ///
+    ///Handle an incoming message along with the [ActorContext]. Defaults to calling
+    ///[receive](AsyncActor::receive).
+    ///
+    fn receive_with<'a>(&'a mut self, mail: Mail, _ctx: &'a mut ActorContext) -> MailFuture<'a> {
+        self.receive(mail)
+    }
+
+    ///
+    ///Name of the type implementing the `AsyncActor` trait
+    ///
+    fn type_name(&self) -> &'static str {
+        any::type_name::<Self>()
+    }
+
+    ///
+    ///Optional hook to capture the in-memory state of the actor - see [Actor::snapshot]
+    ///
+    fn snapshot(&self) -> Option<Vec<u8>> {
+        None
+    }
+
+    ///
+    ///Rebuild in-memory state from the latest snapshot - see [Actor::restore_state]
+    ///
+    fn restore_state(&mut self, _state: &[u8]) {}
+
+    ///
+    ///Opt in to event sourced recovery - see [Actor::event_sourced]. Replayed messages are
+    ///fed to [receive](AsyncActor::receive) with the outgoing mail discarded.
+    ///
+    fn event_sourced(&self) -> bool {
+        false
+    }
+}
+
+///Runs an [AsyncActor] as an [Actor] - each message is handled by driving the future
+///returned by the async actor to completion. Messages are left to the executor, while
+///replayed ones are waited on.
+pub struct Asynchronous<A: AsyncActor>(Arc<Mutex<Option<A>>>);
+
+impl<A: AsyncActor> Asynchronous<A> {
+    ///Wrap an async actor
+    pub fn new(actor: A) -> Self {
+        Self(Arc::new(Mutex::new(Some(actor))))
+    }
+}
+
+impl<A: AsyncActor> Actor for Asynchronous<A> {
+    fn receive(&mut self, mail: Mail) -> Option<Mail> {
+        let mut actor = self.0.lock();
+        executor::block_on(actor.as_mut()?.receive(mail))
+    }
+
+    //The future takes the async actor along - and puts it back once it resolves
+    fn receive_with(&mut self, mail: Mail, ctx: &mut ActorContext) -> Option<Mail> {
+        let mut actor = self.0.lock().take()?;
+        let (home, mut detached) = (Arc::clone(&self.0), ctx.fork());
+        ctx.detach(Detached(Box::pin(async move {
+            let outgoing = actor.receive_with(mail, &mut detached).await;
+            *home.lock() = Some(actor);
+            (outgoing, detached)
+        })));
+        None
+    }
+
+    fn type_name(&self) -> &'static str {
+        self.0
+            .lock()
+            .as_ref()
+            .map_or_else(any::type_name::<A>, AsyncActor::type_name)
+    }
+
+    fn snapshot(&self) -> Option<Vec<u8>> {
+        self.0.lock().as_ref().and_then(AsyncActor::snapshot)
+    }
+
+    fn restore_state(&mut self, state: &[u8]) {
+        if let Some(actor) = self.0.lock().as_mut() {
+            actor.restore_state(state)
+        }
+    }
+
+    fn event_sourced(&self) -> bool {
+        self.0
+            .lock()
+            .as_ref()
+            .is_some_and(AsyncActor::event_sourced)
+    }
+}
 /// # Producer
 ///Implementation of an `Actor` trait involves two steps. First, implementation of the
 ///`Actor` trait itself. Once trait implentation is done - first step is complete. Making
@@ -81,11 +245,11 @@ pub trait Producer {
     ///
     ///<https://github.com/dtolnay/typetag>
     ///
-
     fn produce(&mut self) -> Box<dyn Actor>;
 
     ///A method to rebuild a `Producer` implementation. Used internally by the system to
     ///generate `Producer`s on demand from the backing store.  
+    #[allow(clippy::wrong_self_convention)]
     fn from_string(&self, content: String) -> std::io::Result<Box<dyn Producer>> {
         let producer: Box<dyn Producer> = serde_json::from_str(&content)?;
         Ok(producer)
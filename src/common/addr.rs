@@ -102,7 +102,7 @@ impl Addr {
             .host()
             .to_string()
             .parse()
-            .map_or(false, |parsed: IpAddr| parsed == ip)
+            .is_ok_and(|parsed: IpAddr| parsed == ip)
     }
     ///Mostly for routing decisions
     pub fn is_local_ip(&self) -> bool {
@@ -110,7 +110,7 @@ impl Addr {
         if host_ip.is_loopback() || host_ip == WILDCARD_IP {
             true
         } else {
-            local_ip().map_or(false, |local_ip| local_ip == host_ip)
+            local_ip().is_ok_and(|local_ip| local_ip == host_ip)
         }
     }
     ///Routing
@@ -0,0 +1,208 @@
+//! # Codec
+//!Serialization formats for messages - on the wire and in the backing store. Bincode is
+//!always available, JSON and MessagePack are enabled via the `json` and `msgpack` cargo
+//!features. The codec in use is picked via the `codec` setting of the
+//![Config](crate::Config).
+//!
+//!Each stored message and each wire frame records the id of the codec it was encoded with.
+//!Data written with one codec can thus still be read after switching to another, and by
+//!systems configured with a different codec. Data written before codecs were recorded is
+//!read as bincode.
+
+use crate::common::envelope::MSG_VERSION;
+use crate::{Error, Result};
+use byte_marks::TAIL;
+use serde::{de::DeserializeOwned, Serialize};
+use std::io::{self, BufRead, ErrorKind};
+
+//Marks a frame headed by the id of its codec and the version of its message layout. Frames
+//without it are bincode encoded in the first layout - they begin with the variant index of
+//a `Mail` or an `Option`, never with this byte.
+const FRAME_MARK: u8 = 0xC0;
+
+///A serialization format - identified by the id recorded alongside the data it encodes
+pub trait Codec {
+    ///Id of the codec - recorded with the encoded data. Must never change.
+    const ID: u8;
+    ///Name of the codec - as set via the `codec` config setting
+    const NAME: &'static str;
+    ///Encode a value into bytes
+    fn encode<T: Serialize + ?Sized>(value: &T) -> Result<Vec<u8>>;
+    ///Decode a value from bytes
+    fn decode<T: DeserializeOwned>(bytes: &[u8]) -> Result<T>;
+}
+
+///The [bincode](https://docs.rs/bincode) codec - compact and fast, the default
+pub struct Bincode;
+
+impl Codec for Bincode {
+    const ID: u8 = 0;
+    const NAME: &'static str = "bincode";
+
+    fn encode<T: Serialize + ?Sized>(value: &T) -> Result<Vec<u8>> {
+        bincode::serialize(value).map_err(|err| (*err).into())
+    }
+
+    fn decode<T: DeserializeOwned>(bytes: &[u8]) -> Result<T> {
+        bincode::deserialize(bytes).map_err(|err| (*err).into())
+    }
+}
+
+///The JSON codec - human readable and tolerant of fields added to messages later
+#[cfg(feature = "json")]
+pub struct Json;
+
+#[cfg(feature = "json")]
+impl Codec for Json {
+    const ID: u8 = 1;
+    const NAME: &'static str = "json";
+
+    fn encode<T: Serialize + ?Sized>(value: &T) -> Result<Vec<u8>> {
+        Ok(serde_json::to_vec(value)?)
+    }
+
+    fn decode<T: DeserializeOwned>(bytes: &[u8]) -> Result<T> {
+        Ok(serde_json::from_slice(bytes)?)
+    }
+}
+
+///The [MessagePack](https://msgpack.org) codec - compact and self describing
+#[cfg(feature = "msgpack")]
+pub struct MessagePack;
+
+#[cfg(feature = "msgpack")]
+impl Codec for MessagePack {
+    const ID: u8 = 2;
+    const NAME: &'static str = "msgpack";
+
+    fn encode<T: Serialize + ?Sized>(value: &T) -> Result<Vec<u8>> {
+        //Structs as maps - so that fields can be added to messages later
+        rmp_serde::to_vec_named(value).map_err(|err| Error::Other(Box::new(err)))
+    }
+
+    fn decode<T: DeserializeOwned>(bytes: &[u8]) -> Result<T> {
+        rmp_serde::from_slice(bytes).map_err(|err| Error::Other(Box::new(err)))
+    }
+}
+
+///Id of the codec with the given name - None if unknown or not enabled
+pub fn id_of(name: &str) -> Option<u8> {
+    match name {
+        Bincode::NAME => Some(Bincode::ID),
+        #[cfg(feature = "json")]
+        Json::NAME => Some(Json::ID),
+        #[cfg(feature = "msgpack")]
+        MessagePack::NAME => Some(MessagePack::ID),
+        _ => None,
+    }
+}
+
+//Encode a value with the codec of the given id
+pub(crate) fn encode<T: Serialize + ?Sized>(codec: u8, value: &T) -> Result<Vec<u8>> {
+    match codec {
+        Bincode::ID => Bincode::encode(value),
+        #[cfg(feature = "json")]
+        Json::ID => Json::encode(value),
+        #[cfg(feature = "msgpack")]
+        MessagePack::ID => MessagePack::encode(value),
+        _ => Err(Error::UnknownCodec(codec)),
+    }
+}
+
+//Decode a value with the codec of the given id
+pub(crate) fn decode<T: DeserializeOwned>(codec: u8, bytes: &[u8]) -> Result<T> {
+    match codec {
+        Bincode::ID => Bincode::decode(bytes),
+        #[cfg(feature = "json")]
+        Json::ID => Json::decode(bytes),
+        #[cfg(feature = "msgpack")]
+        MessagePack::ID => MessagePack::decode(bytes),
+        _ => Err(Error::UnknownCodec(codec)),
+    }
+}
+
+//Encode a value into a wire frame - headed by the codec id and the message version
+pub(crate) fn frame<T: Serialize + ?Sized>(codec: u8, value: &T) -> Result<Vec<u8>> {
+    let mut bytes = vec![FRAME_MARK, codec, MSG_VERSION];
+    bytes.extend(encode(codec, value)?);
+    Ok(bytes)
+}
+
+//Split a wire frame into the codec id, the message version and the encoded payload
+pub(crate) fn unframe(bytes: &[u8]) -> (u8, u8, &[u8]) {
+    match bytes {
+        [FRAME_MARK, codec, version, payload @ ..] => (*codec, *version, payload),
+        legacy => (Bincode::ID, 0, legacy),
+    }
+}
+
+//Read the next tail marked frame off a stream - None once the stream ends. Only the bytes
+//of the frame are consumed, frames pipelined after it stay in the reader.
+pub(crate) fn read_frame<R: BufRead>(reader: &mut R) -> io::Result<Option<Vec<u8>>> {
+    let tail = TAIL.as_bytes();
+    let mut bytes = Vec::new();
+    loop {
+        if reader.read_until(tail[tail.len() - 1], &mut bytes)? == 0 {
+            return match bytes.is_empty() {
+                true => Ok(None),
+                false => Err(io::Error::new(ErrorKind::UnexpectedEof, "Truncated frame")),
+            };
+        }
+        if bytes.ends_with(tail) {
+            bytes.truncate(bytes.len() - tail.len());
+            return Ok(Some(bytes));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::envelope::decode_mail;
+    use crate::{Mail, Msg};
+
+    #[test]
+    fn codec_frame_test_1() {
+        let mail = Mail::Bulk(vec![Msg::with_text("mail", "from", "to")]);
+        let codecs = [
+            Bincode::ID,
+            #[cfg(feature = "json")]
+            Json::ID,
+            #[cfg(feature = "msgpack")]
+            MessagePack::ID,
+        ];
+        for codec in codecs {
+            let bytes = frame(codec, &mail).unwrap();
+            let (id, version, payload) = unframe(&bytes);
+            assert_eq!((id, version), (codec, MSG_VERSION));
+            let decoded = decode_mail(id, version, payload).unwrap();
+            assert_eq!(decoded.messages()[0].as_text(), Some("mail"));
+        }
+        //Frames of systems predating codec ids
+        let legacy = Bincode::encode(&mail).unwrap();
+        assert_eq!(unframe(&legacy), (Bincode::ID, 0, &legacy[..]));
+        assert!(decode::<Mail>(u8::MAX, &legacy).is_err());
+    }
+
+    #[test]
+    fn codec_read_frame_test_1() {
+        let marker = byte_marks::ByteMarker::with_defaults();
+        let mut stream = Vec::new();
+        for text in ["first", "second"] {
+            let mut bytes = frame(Bincode::ID, text).unwrap();
+            marker.mark_tail(&mut bytes);
+            stream.extend(bytes);
+        }
+        stream.extend(b"trunc");
+        let mut reader = std::io::Cursor::new(stream);
+        for text in ["first", "second"] {
+            let bytes = read_frame(&mut reader).unwrap().unwrap();
+            assert_eq!(
+                decode::<String>(Bincode::ID, unframe(&bytes).2).unwrap(),
+                text
+            );
+        }
+        assert!(read_frame(&mut reader).is_err());
+        assert!(read_frame(&mut reader).unwrap().is_none());
+    }
+}
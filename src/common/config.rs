@@ -2,6 +2,7 @@
 //!The centralized configuration construct - to run multiple instances of the system
 //!supply these settings at startup
 
+use crate::common::codec;
 use lazy_static::lazy_static;
 use local_ip_address::local_ip;
 use parking_lot::RwLock;
@@ -25,6 +26,66 @@ pub struct Config {
     db_path: String,
     resident_listener: String,
     db_buff_size: usize,
+    snapshot_every: u64,
+    snapshot_interval: u64,
+    passivate_after: u64,
+    actor_cache_capacity: usize,
+    codec: u8,
+    retention: Retention,
+    compaction_interval: u64,
+    vacuum: bool,
+    backend: Backend,
+    max_connections: usize,
+    read_timeout: u64,
+    idle_timeout: u64,
+    dedup_window: u64,
+}
+
+///The kind of backing store messages, actor definitions and the rest are kept in
+#[derive(PartialEq, Eq, Clone, Copy, Debug, Hash)]
+pub enum Backend {
+    ///An sqlite database under the db path - the default
+    Sqlite,
+    ///Memory of the process - nothing survives a restart. For tests and ephemeral systems.
+    Memory,
+}
+
+impl Backend {
+    fn parse(text: &str) -> Option<Self> {
+        match text {
+            "sqlite" => Some(Self::Sqlite),
+            "memory" => Some(Self::Memory),
+            _ => None,
+        }
+    }
+}
+
+///How long processed messages are kept in the backing store. Messages an event sourced
+///actor would replay - those after its latest snapshot - are kept regardless. Event sourced
+///actors that never snapshot should be left with `All`, lest their history be lost.
+#[derive(PartialEq, Eq, Clone, Copy, Debug, Hash)]
+pub enum Retention {
+    ///Keep all messages - the default
+    All,
+    ///Delete messages as soon as the actor has processed them
+    Processed,
+    ///Keep the given number of most recently processed messages per actor
+    Last(u64),
+    ///Keep processed messages for the given number of seconds
+    For(u64),
+}
+
+impl Retention {
+    //Parse `all`, `processed`, `last:<count>` or `for:<seconds>`
+    fn parse(text: &str) -> Option<Self> {
+        match text.split_once(':') {
+            None if text == "all" => Some(Self::All),
+            None if text == "processed" => Some(Self::Processed),
+            Some(("last", count)) => count.parse().ok().map(Self::Last),
+            Some(("for", secs)) => secs.parse().ok().map(Self::For),
+            _ => None,
+        }
+    }
 }
 
 impl Config {
@@ -70,12 +131,72 @@ impl Config {
             .parse()
             .expect("db_buff_size");
 
+        let snapshot_every: u64 = env::var("snapshot_every")
+            .unwrap_or_else(|_| "100".to_string())
+            .parse()
+            .expect("snapshot_every");
+        let snapshot_interval: u64 = env::var("snapshot_interval")
+            .unwrap_or_else(|_| "0".to_string())
+            .parse()
+            .expect("snapshot_interval");
+        let passivate_after: u64 = env::var("passivate_after")
+            .unwrap_or_else(|_| "0".to_string())
+            .parse()
+            .expect("passivate_after");
+        let actor_cache_capacity: usize = env::var("actor_cache_capacity")
+            .unwrap_or_else(|_| "0".to_string())
+            .parse()
+            .expect("actor_cache_capacity");
+        let codec = env::var("codec").unwrap_or_else(|_| "bincode".to_string());
+        let codec = codec::id_of(&codec).expect("codec");
+        let retention = env::var("retention").unwrap_or_else(|_| "all".to_string());
+        let retention = Retention::parse(&retention).expect("retention");
+        let compaction_interval: u64 = env::var("compaction_interval")
+            .unwrap_or_else(|_| "60".to_string())
+            .parse()
+            .expect("compaction_interval");
+        let vacuum: bool = env::var("vacuum")
+            .unwrap_or_else(|_| "false".to_string())
+            .parse()
+            .expect("vacuum");
+        let backend = env::var("backend").unwrap_or_else(|_| "sqlite".to_string());
+        let backend = Backend::parse(&backend).expect("backend");
+        let max_connections: usize = env::var("max_connections")
+            .unwrap_or_else(|_| "64".to_string())
+            .parse()
+            .expect("max_connections");
+        let read_timeout: u64 = env::var("read_timeout")
+            .unwrap_or_else(|_| "30".to_string())
+            .parse()
+            .expect("read_timeout");
+        let idle_timeout: u64 = env::var("idle_timeout")
+            .unwrap_or_else(|_| "300".to_string())
+            .parse()
+            .expect("idle_timeout");
+        let dedup_window: u64 = env::var("dedup_window")
+            .unwrap_or_else(|_| "3600".to_string())
+            .parse()
+            .expect("dedup_window");
+
         Self {
             host,
             port,
             db_path,
             resident_listener,
             db_buff_size,
+            snapshot_every,
+            snapshot_interval,
+            passivate_after,
+            actor_cache_capacity,
+            codec,
+            retention,
+            compaction_interval,
+            vacuum,
+            backend,
+            max_connections,
+            read_timeout,
+            idle_timeout,
+            dedup_window,
         }
     }
     ///Reinit based on user supplied config when the CLI is run
@@ -119,7 +240,6 @@ impl Config {
     ///for every transaction that takes place in the system.
     ///
     ///Configurable via `db_buffer_size`.
-
     pub fn db_buff_size(&self) -> usize {
         self.db_buff_size
     }
@@ -129,4 +249,132 @@ impl Config {
     pub fn set_db_buff_size(&mut self, buff_size: usize) {
         self.db_buff_size = buff_size;
     }
+    ///Number of messages an actor processes between state snapshots. Zero disables count
+    ///based snapshots. Configurable via `snapshot_every` - defaults to 100.
+    pub fn snapshot_every(&self) -> u64 {
+        self.snapshot_every
+    }
+    ///Set the number of messages between actor state snapshots
+    pub fn set_snapshot_every(&mut self, snapshot_every: u64) {
+        self.snapshot_every = snapshot_every;
+    }
+    ///Seconds between actor state snapshots. Zero(the default) disables time based
+    ///snapshots. Configurable via `snapshot_interval`.
+    pub fn snapshot_interval(&self) -> u64 {
+        self.snapshot_interval
+    }
+    ///Set the seconds between actor state snapshots
+    pub fn set_snapshot_interval(&mut self, snapshot_interval: u64) {
+        self.snapshot_interval = snapshot_interval;
+    }
+    ///Seconds an actor may stay idle before it is passivated - dropped from memory to be
+    ///reactivated on its next message. Zero(the default) keeps idle actors around.
+    ///Configurable via `passivate_after`.
+    pub fn passivate_after(&self) -> u64 {
+        self.passivate_after
+    }
+    ///Set the seconds after which idle actors get passivated
+    pub fn set_passivate_after(&mut self, passivate_after: u64) {
+        self.passivate_after = passivate_after;
+    }
+    ///Maximum number of actors kept in memory - least recently used ones beyond it get
+    ///passivated. Zero(the default) means no limit. Configurable via `actor_cache_capacity`.
+    pub fn actor_cache_capacity(&self) -> usize {
+        self.actor_cache_capacity
+    }
+    ///Set the maximum number of actors kept in memory
+    pub fn set_actor_cache_capacity(&mut self, actor_cache_capacity: usize) {
+        self.actor_cache_capacity = actor_cache_capacity;
+    }
+    ///Id of the [Codec](crate::Codec) messages get encoded with - on the wire and in the
+    ///backing store. Configurable via `codec` - `bincode`(the default), `json` or `msgpack`
+    ///as enabled via cargo features.
+    pub fn codec(&self) -> u8 {
+        self.codec
+    }
+    ///Set the codec messages get encoded with - data encoded earlier remains readable
+    pub fn set_codec(&mut self, codec: u8) {
+        self.codec = codec;
+    }
+    ///How long processed messages are kept in the backing store. Configurable via
+    ///`retention` - `all`(the default), `processed`, `last:<count>` or `for:<seconds>`.
+    pub fn retention(&self) -> Retention {
+        self.retention
+    }
+    ///Set the retention of processed messages
+    pub fn set_retention(&mut self, retention: Retention) {
+        self.retention = retention;
+    }
+    ///Seconds between background compactions of the backing store - which apply the
+    ///retention. Zero disables them. Configurable via `compaction_interval` - defaults to 60.
+    pub fn compaction_interval(&self) -> u64 {
+        self.compaction_interval
+    }
+    ///Set the seconds between background compactions
+    pub fn set_compaction_interval(&mut self, compaction_interval: u64) {
+        self.compaction_interval = compaction_interval;
+    }
+    ///Whether compaction reclaims the space freed up - incrementally if the database has
+    ///`auto_vacuum` set to incremental, via a full `VACUUM` otherwise. Configurable via
+    ///`vacuum` - defaults to false.
+    pub fn vacuum(&self) -> bool {
+        self.vacuum
+    }
+    ///Set whether compaction reclaims free space
+    pub fn set_vacuum(&mut self, vacuum: bool) {
+        self.vacuum = vacuum;
+    }
+    ///The backing store in use. Configurable via `backend` - `sqlite`(the default) or
+    ///`memory`.
+    pub fn backend(&self) -> Backend {
+        self.backend
+    }
+    ///Set the backing store to use - before the system is brought up
+    pub fn set_backend(&mut self, backend: Backend) {
+        self.backend = backend;
+    }
+    ///Maximum number of connections the listener serves at a time - connections beyond it
+    ///are refused with a busy error. Each open connection takes up a worker until it is
+    ///closed - idle ones included, see [idle_timeout](Self::idle_timeout). Configurable via
+    ///`max_connections` - defaults to 64.
+    pub fn max_connections(&self) -> usize {
+        self.max_connections
+    }
+    ///Set the maximum number of connections served at a time
+    pub fn set_max_connections(&mut self, max_connections: usize) {
+        self.max_connections = max_connections;
+    }
+    ///Seconds the listener waits on the rest of a frame once it has started arriving -
+    ///the connection is closed if it does not arrive in time. Zero waits indefinitely.
+    ///Configurable via `read_timeout` - defaults to 30.
+    pub fn read_timeout(&self) -> u64 {
+        self.read_timeout
+    }
+    ///Set the seconds the listener waits on the rest of a frame
+    pub fn set_read_timeout(&mut self, read_timeout: u64) {
+        self.read_timeout = read_timeout;
+    }
+    ///Seconds a connection may stay idle before the listener closes it. Zero keeps idle
+    ///connections open. Clients keep their connections open between sends - each holding on
+    ///to a listener worker for as long as this. As many idle clients as
+    ///[max_connections](Self::max_connections) keep others out meanwhile. Configurable via
+    ///`idle_timeout` - defaults to 300.
+    pub fn idle_timeout(&self) -> u64 {
+        self.idle_timeout
+    }
+    ///Set the seconds after which idle connections get closed
+    pub fn set_idle_timeout(&mut self, idle_timeout: u64) {
+        self.idle_timeout = idle_timeout;
+    }
+    ///Seconds the ids of ingested messages are remembered - a message arriving again within
+    ///it is reported back to its sender as a duplicate instead of being stored twice, even if
+    ///the original has been compacted away. Messages still in the backing store are always
+    ///recognized. Configurable via `dedup_window` - defaults to 3600.
+    pub fn dedup_window(&self) -> u64 {
+        self.dedup_window
+    }
+    ///Set the seconds the ids of ingested messages are remembered
+    pub fn set_dedup_window(&mut self, dedup_window: u64) {
+        self.dedup_window = dedup_window;
+    }
 }
@@ -0,0 +1,200 @@
+//! # ActorContext
+//!A handle to the actor system that an actor receives along with every message - via
+//![receive_with](crate::Actor::receive_with). Actors use it to find out about themselves
+//!and the message at hand, to send and publish further messages, to spawn and stop child
+//!actors and to schedule messages - without resorting to the global macros.
+//!
+//!Requests made through the context take effect once the actor is done with the message.
+//!Messages sent through the context go out along with the returned outgoing mail.
+
+use crate::{compute_hash, Addr, Error, Mail, Msg, Producer, Supervision};
+use std::fmt::{self, Debug, Formatter};
+use std::future::Future;
+use std::pin::Pin;
+use std::time::Duration;
+use uuid::Uuid;
+
+//Requests made by an actor while processing a message
+#[derive(Debug)]
+pub(crate) enum Effect {
+    Spawn(Addr, String, Supervision),
+    Stop(Addr),
+    StopSelf,
+    Schedule(u64, Msg, Duration, Option<Duration>),
+    CancelSchedule(u64),
+    Publish(String, Msg),
+}
+
+//Future an async actor handles a message with - resolves to the outgoing mail along with
+//the context it made its requests through
+pub(crate) struct Detached(pub(crate) Pin<Box<dyn Future<Output = Resolved> + Send>>);
+
+pub(crate) type Resolved = (Option<Mail>, ActorContext);
+
+impl Debug for Detached {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "Detached future")
+    }
+}
+
+///Context of the actor processing a message
+#[derive(Debug)]
+pub struct ActorContext {
+    addr: Addr,
+    parent: Option<Addr>,
+    sequence: i64,
+    sender: Option<Addr>,
+    sent: Vec<Msg>,
+    effects: Vec<Effect>,
+    detached: Option<Detached>,
+}
+
+impl ActorContext {
+    pub(crate) fn new(
+        addr: Addr,
+        parent: Option<Addr>,
+        sequence: i64,
+        sender: Option<Addr>,
+    ) -> Self {
+        Self {
+            addr,
+            parent,
+            sequence,
+            sender,
+            sent: Vec::new(),
+            effects: Vec::new(),
+            detached: None,
+        }
+    }
+
+    //Fresh context for the same message - for an async actor to take along into its future
+    pub(crate) fn fork(&self) -> Self {
+        Self::new(
+            self.addr.clone(),
+            self.parent.clone(),
+            self.sequence,
+            self.sender.clone(),
+        )
+    }
+
+    ///Address of the actor itself
+    pub fn self_addr(&self) -> &Addr {
+        &self.addr
+    }
+
+    ///Address of the actor that spawned this actor - if any
+    pub fn parent(&self) -> Option<&Addr> {
+        self.parent.as_ref()
+    }
+
+    ///Sequence of the actor - the number of messages and signals it has processed so far
+    pub fn sequence(&self) -> i64 {
+        self.sequence
+    }
+
+    ///Address of the sender of the message being processed - if known
+    pub fn sender(&self) -> Option<&Addr> {
+        self.sender.as_ref()
+    }
+
+    ///Send a message to an actor - local or remote. The message goes out along with the
+    ///outgoing mail returned from `receive_with`.
+    pub fn send(&mut self, to: &Addr, mut msg: Msg) {
+        msg.set_recipient_addr(to);
+        self.sent.push(msg);
+    }
+
+    ///Publish a message to the subscribers of a topic - each subscriber gets its own copy
+    pub fn publish(&mut self, topic: &str, mut msg: Msg) {
+        msg.set_from(&self.addr);
+        self.effects.push(Effect::Publish(topic.to_string(), msg));
+    }
+
+    ///Send a message to an actor after the given delay. Returns the id of the schedule -
+    ///which can be used to cancel it. Schedules survive restarts.
+    pub fn send_after(&mut self, to: &Addr, msg: Msg, delay: Duration) -> u64 {
+        self.schedule(to, msg, delay, None)
+    }
+
+    ///Send a message to an actor periodically - first one after one period. Returns the id
+    ///of the schedule.
+    pub fn send_every(&mut self, to: &Addr, msg: Msg, every: Duration) -> u64 {
+        self.schedule(to, msg, every, Some(every))
+    }
+
+    ///Send a message to the actor itself after the given delay. Returns the id of the
+    ///schedule.
+    pub fn schedule_self(&mut self, msg: Msg, delay: Duration) -> u64 {
+        let addr = self.addr.clone();
+        self.send_after(&addr, msg, delay)
+    }
+
+    ///Cancel a schedule
+    pub fn cancel_schedule(&mut self, id: u64) {
+        self.effects.push(Effect::CancelSchedule(id));
+    }
+
+    fn schedule(
+        &mut self,
+        to: &Addr,
+        mut msg: Msg,
+        after: Duration,
+        every: Option<Duration>,
+    ) -> u64 {
+        msg.set_recipient_addr(to);
+        msg.set_from(&self.addr);
+        let id = compute_hash(&Uuid::new_v4());
+        self.effects.push(Effect::Schedule(id, msg, after, every));
+        id
+    }
+
+    ///Spawn a child actor with the given name. The child gets the default
+    ///[Supervision](crate::Supervision). Spawning a child that already exists leaves the
+    ///running child undisturbed. Children get stopped along with their parent.
+    pub fn spawn(&mut self, name: &str, producer: impl Producer) -> Result<Addr, Error> {
+        self.spawn_supervised(name, producer, Supervision::default())
+    }
+
+    ///Spawn a child actor with the given supervision - the parent decides what happens
+    ///when the child panics
+    pub fn spawn_supervised(
+        &mut self,
+        name: &str,
+        producer: impl Producer,
+        supervision: Supervision,
+    ) -> Result<Addr, Error> {
+        let text = serde_json::to_string(&producer as &dyn Producer)?;
+        let addr = Addr::new(name);
+        self.effects
+            .push(Effect::Spawn(addr.clone(), text, supervision));
+        Ok(addr)
+    }
+
+    ///Stop a child actor - the child and its own children receive the pre-shutdown signal
+    ///and get removed from the system. Actors other than own children are left alone.
+    pub fn stop(&mut self, child: &Addr) {
+        self.effects.push(Effect::Stop(child.clone()));
+    }
+
+    ///Stop the actor itself along with its children - once done with the current message
+    pub fn stop_self(&mut self) {
+        self.effects.push(Effect::StopSelf);
+    }
+
+    pub(crate) fn take_sent(&mut self) -> Vec<Msg> {
+        std::mem::take(&mut self.sent)
+    }
+
+    pub(crate) fn take_effects(&mut self) -> Vec<Effect> {
+        std::mem::take(&mut self.effects)
+    }
+
+    //Leave the message to a future - the actor is done with it once the future resolves
+    pub(crate) fn detach(&mut self, detached: Detached) {
+        self.detached = Some(detached);
+    }
+
+    pub(crate) fn take_detached(&mut self) -> Option<Detached> {
+        self.detached.take()
+    }
+}
@@ -0,0 +1,183 @@
+//! # Envelope
+//!Messages are stored and transmitted in an envelope recording the [codec](crate::Codec)
+//!and the version of the [Msg] layout they were encoded with - as columns alongside the
+//!stored message and as the header of wire frames. Messages of older versions are decoded
+//!in the layout of their time and upcast to the current one - so that pending messages
+//!survive upgrades that change `Msg`, `Addr` or `Content`.
+//!
+//!Changing the layout means bumping [MSG_VERSION], freezing a copy of the outgoing layout
+//!here, converting it into the current `Msg` and registering an `Upcaster` for it. Earlier
+//!frozen layouts are converted into the next one - upcasts chain through the versions.
+
+use crate::common::codec;
+use crate::{Addr, Error, Mail, Msg, Result};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::time::SystemTime;
+
+///Version of the [Msg] layout written by this build
+pub(crate) const MSG_VERSION: u8 = 1;
+
+//Decodes messages of a past version - as stored and as sent on the wire - into the current
+//layout
+struct Upcaster {
+    version: u8,
+    msg: fn(u8, &[u8]) -> Result<Msg>,
+    mail: fn(u8, &[u8]) -> Result<Mail>,
+    reply: fn(u8, &[u8]) -> Result<Option<Mail>>,
+}
+
+const UPCASTERS: &[Upcaster] = &[Upcaster {
+    version: 0,
+    msg: upcast_msg::<MsgV0>,
+    mail: upcast_mail::<MsgV0>,
+    reply: upcast_reply::<MsgV0>,
+}];
+
+//Layout of messages written by releases up to 0.1.16 - before time-to-live and keys
+#[derive(Deserialize)]
+struct MsgV0 {
+    id: u64,
+    from: Option<Addr>,
+    to: Option<Addr>,
+    content: Option<crate::common::mail::Content>,
+    dispatched: Option<SystemTime>,
+}
+
+impl From<MsgV0> for Msg {
+    fn from(msg: MsgV0) -> Self {
+        Msg {
+            id: msg.id,
+            from: msg.from,
+            to: msg.to,
+            content: msg.content,
+            dispatched: msg.dispatched,
+            ttl: None,
+            key: None,
+        }
+    }
+}
+
+//Mail carrying messages of a past layout - variants in the order of `Mail`
+#[derive(Deserialize)]
+enum MailOf<M> {
+    Trade(M),
+    Bulk(Vec<M>),
+    Blank,
+}
+
+impl<M: Into<Msg>> From<MailOf<M>> for Mail {
+    fn from(mail: MailOf<M>) -> Self {
+        match mail {
+            MailOf::Trade(msg) => Mail::Trade(msg.into()),
+            MailOf::Bulk(msgs) => Mail::Bulk(msgs.into_iter().map(Into::into).collect()),
+            MailOf::Blank => Mail::Blank,
+        }
+    }
+}
+
+fn upcast_msg<M: DeserializeOwned + Into<Msg>>(codec: u8, bytes: &[u8]) -> Result<Msg> {
+    Ok(codec::decode::<M>(codec, bytes)?.into())
+}
+
+fn upcast_mail<M: DeserializeOwned + Into<Msg>>(codec: u8, bytes: &[u8]) -> Result<Mail> {
+    Ok(codec::decode::<MailOf<M>>(codec, bytes)?.into())
+}
+
+fn upcast_reply<M: DeserializeOwned + Into<Msg>>(codec: u8, bytes: &[u8]) -> Result<Option<Mail>> {
+    Ok(codec::decode::<Option<MailOf<M>>>(codec, bytes)?.map(Into::into))
+}
+
+fn upcaster(version: u8) -> Result<&'static Upcaster> {
+    UPCASTERS
+        .iter()
+        .find(|upcaster| upcaster.version == version)
+        .ok_or(Error::UnknownMsgVersion(version))
+}
+
+//Decode a stored message of the given codec and version
+pub(crate) fn decode_msg(codec: u8, version: u8, bytes: &[u8]) -> Result<Msg> {
+    match version {
+        MSG_VERSION => codec::decode(codec, bytes),
+        _ => (upcaster(version)?.msg)(codec, bytes),
+    }
+}
+
+//Decode mail received over the wire
+pub(crate) fn decode_mail(codec: u8, version: u8, bytes: &[u8]) -> Result<Mail> {
+    match version {
+        MSG_VERSION => codec::decode(codec, bytes),
+        _ => (upcaster(version)?.mail)(codec, bytes),
+    }
+}
+
+//Decode the reply to an ask received over the wire
+pub(crate) fn decode_reply(codec: u8, version: u8, bytes: &[u8]) -> Result<Option<Mail>> {
+    match version {
+        MSG_VERSION => codec::decode(codec, bytes),
+        _ => (upcaster(version)?.reply)(codec, bytes),
+    }
+}
+
+//Acknowledgement of mail received over the wire - ids of the messages persisted and of
+//those that had already been ingested. Both are safe for the sender to forget.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub(crate) struct Ack {
+    pub(crate) accepted: Vec<u64>,
+    pub(crate) duplicates: Vec<u64>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::codec::{Bincode, Codec};
+    use crate::common::mail::Content;
+    use serde::Serialize;
+
+    //Message as written by releases up to 0.1.16
+    #[derive(Serialize)]
+    struct LegacyMsg {
+        id: u64,
+        from: Option<Addr>,
+        to: Option<Addr>,
+        content: Option<Content>,
+        dispatched: Option<SystemTime>,
+    }
+
+    #[derive(Serialize)]
+    enum LegacyMail {
+        _Trade(Box<LegacyMsg>),
+        Bulk(Vec<LegacyMsg>),
+    }
+
+    fn legacy_msg() -> LegacyMsg {
+        LegacyMsg {
+            id: 42,
+            from: Some(Addr::new("from")),
+            to: Some(Addr::new("to")),
+            content: Some(Content::Text("legacy".to_string())),
+            dispatched: None,
+        }
+    }
+
+    #[test]
+    fn envelope_upcast_test_1() {
+        let bytes = Bincode::encode(&legacy_msg()).unwrap();
+        //Not readable in the current layout
+        assert!(decode_msg(Bincode::ID, MSG_VERSION, &bytes).is_err());
+        let msg = decode_msg(Bincode::ID, 0, &bytes).unwrap();
+        assert_eq!(msg.as_text(), Some("legacy"));
+        assert_eq!(msg.get_to_id(), Addr::new("to").get_id());
+
+        let bytes = Bincode::encode(&LegacyMail::Bulk(vec![legacy_msg()])).unwrap();
+        let mail = decode_mail(Bincode::ID, 0, &bytes).unwrap();
+        assert_eq!(mail.messages()[0].as_text(), Some("legacy"));
+
+        let current = Msg::with_text("current", "from", "to");
+        let bytes = Bincode::encode(&current).unwrap();
+        assert_eq!(
+            decode_msg(Bincode::ID, MSG_VERSION, &bytes).unwrap(),
+            current
+        );
+        assert!(decode_msg(Bincode::ID, MSG_VERSION + 1, &bytes).is_err());
+    }
+}
@@ -19,6 +19,15 @@ pub enum Error {
     ///Most of the tings gets stored as binary blobs and system depends heavily depends on
     ///bincode - this variant captures errors related bincode
     Bincode(bincode::ErrorKind),
+    ///No reply arrived for an asked message before the deadline passed
+    Timeout,
+    ///Data recorded with a codec that is unknown or not enabled in this build
+    UnknownCodec(u8),
+    ///The backing store was migrated by a newer version of arrows - holds the schema version
+    ///found and the latest one supported
+    SchemaTooNew(u32, u32),
+    ///Message written in a layout version no upcaster is registered for
+    UnknownMsgVersion(u8),
 }
 
 use std::fmt::{Display, Formatter, Result as FResult};
@@ -33,6 +42,14 @@ impl Display for Error {
             Self::RegistrationError => write!(f, "RegistrationError"),
             Self::RestorationError => write!(f, "RestorationError"),
             Self::Bincode(ref err) => write!(f, "Bincode: {}", err),
+            Self::Timeout => write!(f, "Timed out waiting for reply"),
+            Self::UnknownCodec(id) => write!(f, "Unknown codec: {}", id),
+            Self::UnknownMsgVersion(version) => write!(f, "Unknown message version: {}", version),
+            Self::SchemaTooNew(found, supported) => write!(
+                f,
+                "Database schema version {} is newer than the supported version {}",
+                found, supported
+            ),
         }
     }
 }
@@ -48,6 +65,12 @@ impl From<serde_json::Error> for Error {
     }
 }
 
+impl From<rusqlite::Error> for Error {
+    fn from(err: rusqlite::Error) -> Error {
+        Error::Other(Box::new(err))
+    }
+}
+
 impl From<bincode::ErrorKind> for Error {
     fn from(err: bincode::ErrorKind) -> Error {
         Error::Bincode(err)
@@ -55,7 +78,7 @@ impl From<bincode::ErrorKind> for Error {
 }
 impl From<Error> for std::io::Error {
     fn from(_err: Error) -> std::io::Error {
-        std::io::Error::new(std::io::ErrorKind::Other, "Arrows error")
+        std::io::Error::other("Arrows error")
     }
 }
 
@@ -0,0 +1,185 @@
+//! # Executor
+//!Drives the futures returned by [AsyncActor](crate::AsyncActor)s to completion in the
+//!background - the delegate handling the message moves on to other actors meanwhile. By
+//!default a minimal built-in executor is used - which suffices for futures that do not
+//!depend on a particular runtime. With the `tokio` feature enabled, futures run within a
+//!shared multi-threaded tokio runtime - so that they can make use of tokio I/O, timers and
+//!spawn tasks of their own.
+
+use std::future::Future;
+use std::thread;
+
+//Drive a future to completion in the background. Once it resolves, its output - or the
+//panic if the future panics - is handed to `then`, which may block.
+#[cfg(not(feature = "tokio"))]
+pub(crate) fn spawn<F, T>(future: F, then: T)
+where
+    F: Future + Send + 'static,
+    T: FnOnce(thread::Result<F::Output>) + Send + 'static,
+{
+    thread::spawn(move || then(builtin::catch_unwind(future)));
+}
+
+#[cfg(feature = "tokio")]
+pub(crate) fn spawn<F, T>(future: F, then: T)
+where
+    F: Future + Send + 'static,
+    F::Output: Send,
+    T: FnOnce(thread::Result<F::Output>) + Send + 'static,
+{
+    runtime::RUNTIME.spawn(async move {
+        let resolved = runtime::CatchUnwind(Box::pin(future)).await;
+        //Runtime workers are not to be blocked
+        tokio::task::spawn_blocking(move || then(resolved));
+    });
+}
+
+//Run a future to completion - blocking the calling thread. Panics in the future propagate
+//to the caller.
+#[cfg(not(feature = "tokio"))]
+pub(crate) fn block_on<F: Future>(future: F) -> F::Output {
+    builtin::block_on(future)
+}
+
+#[cfg(feature = "tokio")]
+pub(crate) fn block_on<F: Future>(future: F) -> F::Output {
+    //A runtime can not be started from within a runtime - as when an actor is defined from
+    //async code
+    match tokio::runtime::Handle::try_current() {
+        Ok(_) => builtin::block_on(future),
+        Err(_) => runtime::RUNTIME.block_on(future),
+    }
+}
+
+#[cfg(feature = "tokio")]
+mod runtime {
+    use lazy_static::lazy_static;
+    use std::future::Future;
+    use std::panic::{self, AssertUnwindSafe};
+    use std::pin::Pin;
+    use std::task::{Context, Poll};
+    use std::thread;
+    use tokio::runtime::{Builder, Runtime};
+
+    lazy_static! {
+        pub(super) static ref RUNTIME: Runtime = Builder::new_multi_thread()
+            .enable_all()
+            .thread_name("arrows-async")
+            .build()
+            .expect("Async runtime");
+    }
+
+    //Resolves to the panic of the future, if it panics while being polled
+    pub(super) struct CatchUnwind<F: Future>(pub(super) Pin<Box<F>>);
+
+    impl<F: Future> Future for CatchUnwind<F> {
+        type Output = thread::Result<F::Output>;
+        fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+            let future = self.0.as_mut();
+            match panic::catch_unwind(AssertUnwindSafe(|| future.poll(cx))) {
+                Ok(Poll::Ready(output)) => Poll::Ready(Ok(output)),
+                Ok(Poll::Pending) => Poll::Pending,
+                Err(err) => Poll::Ready(Err(err)),
+            }
+        }
+    }
+}
+
+mod builtin {
+    use std::future::Future;
+    #[cfg(not(feature = "tokio"))]
+    use std::panic::{self, AssertUnwindSafe};
+    use std::sync::Arc;
+    use std::task::{Context, Poll, Wake, Waker};
+    use std::thread::{self, Thread};
+
+    //Wakes up the thread blocked on the future
+    struct ThreadWaker(Thread);
+
+    impl Wake for ThreadWaker {
+        fn wake(self: Arc<Self>) {
+            self.0.unpark();
+        }
+
+        fn wake_by_ref(self: &Arc<Self>) {
+            self.0.unpark();
+        }
+    }
+
+    pub(super) fn block_on<F: Future>(future: F) -> F::Output {
+        let mut future = Box::pin(future);
+        let waker = Waker::from(Arc::new(ThreadWaker(thread::current())));
+        let mut cx = Context::from_waker(&waker);
+        loop {
+            match future.as_mut().poll(&mut cx) {
+                Poll::Ready(output) => return output,
+                //Spurious wake ups just lead to another poll
+                Poll::Pending => thread::park(),
+            }
+        }
+    }
+
+    //Run a future to completion - with the panic, if it panics, as the outcome
+    #[cfg(not(feature = "tokio"))]
+    pub(super) fn catch_unwind<F: Future>(future: F) -> thread::Result<F::Output> {
+        panic::catch_unwind(AssertUnwindSafe(|| block_on(future)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::pin::Pin;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::{mpsc, Arc};
+    use std::task::{Context, Poll};
+    use std::thread;
+    use std::time::Duration;
+
+    //Ready once set from another thread
+    struct Flag(Arc<AtomicBool>, bool);
+
+    impl Future for Flag {
+        type Output = u8;
+        fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<u8> {
+            if self.0.load(Ordering::Acquire) {
+                return Poll::Ready(42);
+            }
+            if !self.1 {
+                self.1 = true;
+                let (flag, waker) = (Arc::clone(&self.0), cx.waker().clone());
+                thread::spawn(move || {
+                    thread::sleep(Duration::from_millis(50));
+                    flag.store(true, Ordering::Release);
+                    waker.wake();
+                });
+            }
+            Poll::Pending
+        }
+    }
+
+    #[test]
+    fn executor_block_on_test_1() {
+        assert_eq!(block_on(async { 7 }), 7);
+        assert_eq!(block_on(Flag(Arc::new(AtomicBool::new(false)), false)), 42);
+    }
+
+    #[test]
+    fn executor_spawn_test_1() {
+        let (sender, receiver) = mpsc::channel();
+        let flag = Arc::new(AtomicBool::new(false));
+        let done = sender.clone();
+        spawn(Flag(Arc::clone(&flag), false), move |resolved| {
+            done.send(resolved.ok()).unwrap()
+        });
+        //Spawning does not wait for the future
+        assert!(!flag.load(Ordering::Acquire));
+        assert_eq!(receiver.recv_timeout(Duration::from_secs(5)), Ok(Some(42)));
+        //Panics are handed over as the outcome
+        spawn(
+            async { panic!("Async panic") },
+            move |resolved: thread::Result<u8>| sender.send(resolved.ok()).unwrap(),
+        );
+        assert_eq!(receiver.recv_timeout(Duration::from_secs(5)), Ok(None));
+    }
+}
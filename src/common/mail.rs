@@ -1,22 +1,23 @@
 #![allow(clippy::large_enum_variant)]
-use crate::Result;
 use crate::{
+    common::codec,
     common::utils::{compute_hash, from_bytes, option_of_bytes},
-    Addr,
+    Addr, Error, Result, Tagged,
 };
 
-use serde::{Deserialize, Serialize};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
 use std::mem::{replace, swap};
-use std::time::SystemTime;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use uuid::Uuid;
-///The variants of actual message payload - Text, Binary blob or a Command adjoining an
-///Action
+///The variants of actual message payload - Text, Binary blob, a Command adjoining an
+///Action or a Typed value - serialized along with its type tag
 
 #[derive(PartialEq, Clone, Debug, Serialize, Deserialize)]
 pub enum Content {
     Text(String),
     Binary(Vec<u8>),
     Command(Action),
+    Typed(String, Vec<u8>),
 }
 impl std::fmt::Display for Action {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
@@ -24,19 +25,21 @@ impl std::fmt::Display for Action {
             action @ Self::Shutdown => write!(f, "{}", action.as_text()),
             cont @ Self::Continue => write!(f, "{}", cont.as_text()),
             Self::Echo(s) => write!(f, "Echo({})", s),
+            Self::Ask(msg, _) => write!(f, "Ask({})", msg),
         }
     }
 }
 
 use Content::*;
 ///The Mail enum which could be Trade(single message), Bulk(multiple messages) or Blank
-#[derive(PartialEq, Clone, Debug, Serialize, Deserialize)]
+#[derive(PartialEq, Clone, Debug, Default, Serialize, Deserialize)]
 pub enum Mail {
     ///A mail variant with a single message inside. Actor's receives this variant
     Trade(Msg),
     ///Contains multiple messages - used for buffering, single shot transmission over the wire
     Bulk(Vec<Msg>),
     ///An empty mail
+    #[default]
     Blank,
 }
 use Mail::*;
@@ -166,8 +169,8 @@ impl Mail {
     }
     ///Set from address on all the messagess inside a potential mail
     pub fn set_from(mail: &mut Option<Mail>, from: &Addr) {
-        match mail {
-            Some(mail) => match mail {
+        if let Some(mail) = mail {
+            match mail {
                 Trade(msg) => msg.set_from(from),
                 Bulk(msgs) => {
                     for msg in msgs.iter_mut() {
@@ -175,8 +178,7 @@ impl Mail {
                     }
                 }
                 Blank => (),
-            },
-            None => (),
+            }
         }
     }
 }
@@ -206,6 +208,9 @@ pub enum Action {
     Echo(String),
     ///Good to go, carry on
     Continue,
+    ///Deliver the wrapped message and wait(upto the given milliseconds) for the reply from
+    ///the recipient actor
+    Ask(Box<Msg>, u64),
 }
 impl Action {
     //Meant for internal use by the system
@@ -214,6 +219,7 @@ impl Action {
             Self::Shutdown => "Shutdown",
             Self::Echo(_) => "Echo",
             Self::Continue => "Continue",
+            Self::Ask(_, _) => "Ask",
         }
     }
     ///Inner content such as echo message
@@ -222,6 +228,7 @@ impl Action {
             Self::Shutdown => "",
             Self::Continue => "",
             Self::Echo(s) => s,
+            Self::Ask(_, _) => "",
         }
     }
 
@@ -237,11 +244,15 @@ impl Action {
 ///The actual payload received by actors inside a Mail enum construct
 #[derive(PartialEq, Clone, Debug, Serialize, Deserialize, Default)]
 pub struct Msg {
-    id: u64,
-    from: Option<Addr>,
-    to: Option<Addr>,
-    content: Option<Content>,
-    dispatched: Option<SystemTime>,
+    pub(crate) id: u64,
+    pub(crate) from: Option<Addr>,
+    pub(crate) to: Option<Addr>,
+    pub(crate) content: Option<Content>,
+    pub(crate) dispatched: Option<SystemTime>,
+    #[serde(default)]
+    pub(crate) ttl: Option<Duration>,
+    #[serde(default)]
+    pub(crate) key: Option<String>,
 }
 
 impl Msg {
@@ -253,6 +264,8 @@ impl Msg {
             to: Some(Addr::new(to)),
             content: content.map(Binary),
             dispatched: None,
+            ttl: None,
+            key: None,
         }
     }
     ///Update the message with binary content
@@ -263,6 +276,8 @@ impl Msg {
             to: None,
             content: content.map(Binary),
             dispatched: None,
+            ttl: None,
+            key: None,
         }
     }
 
@@ -286,6 +301,8 @@ impl Msg {
             to: None,
             content: Some(Text(content.to_string())),
             dispatched: Some(SystemTime::now()),
+            ttl: None,
+            key: None,
         }
     }
     ///Construct a text message with from and to addresses
@@ -296,6 +313,68 @@ impl Msg {
             to: Some(Addr::new(to)),
             content: Some(Text(content.to_string())),
             dispatched: Some(SystemTime::now()),
+            ttl: None,
+            key: None,
+        }
+    }
+    ///Create a msg carrying a serde serializable value. The message is tagged with the
+    ///[TAG](crate::Tagged::TAG) of the value's type - receivers can check for it with
+    ///[is_of](Msg::is_of) and get the value back with [decode](Msg::decode).
+    ///
+    ///
+    /// # Example
+    ///
+    ///```
+    ///use arrows::{send, Msg, Tagged};
+    ///use serde::{Deserialize, Serialize};
+    ///
+    ///#[derive(Debug, Serialize, Deserialize)]
+    ///struct Deposit {
+    ///    amount: u64,
+    ///}
+    ///
+    ///impl Tagged for Deposit {
+    ///    const TAG: &'static str = "account.deposit";
+    ///}
+    ///
+    ///let m = Msg::typed(&Deposit { amount: 100 });
+    ///send!("account", m);
+    ///
+    ///```
+    pub fn typed<T: Serialize + Tagged + ?Sized>(value: &T) -> Self {
+        Self {
+            id: compute_hash(&Uuid::new_v4()),
+            from: None,
+            to: None,
+            content: Some(Typed(
+                T::TAG.to_string(),
+                bincode::serialize(value).unwrap_or_default(),
+            )),
+            dispatched: Some(SystemTime::now()),
+            ttl: None,
+            key: None,
+        }
+    }
+    ///Type tag of the value a typed message carries
+    pub fn type_tag(&self) -> Option<&str> {
+        match self.content {
+            Some(Typed(ref tag, _)) => Some(tag),
+            _ => None,
+        }
+    }
+    ///Does the message carry a value of the given type?
+    pub fn is_of<T: Tagged + ?Sized>(&self) -> bool {
+        self.type_tag() == Some(T::TAG)
+    }
+    ///Get the value a typed message carries. Fails if the message carries a value of some
+    ///other type. Binary content is decoded as is - without a type check.
+    pub fn decode<T: DeserializeOwned + Tagged>(&self) -> Result<T> {
+        match self.content {
+            Some(Typed(ref tag, ref bytes)) if tag == T::TAG => {
+                bincode::deserialize(bytes).map_err(|err| (*err).into())
+            }
+            Some(Binary(ref bytes)) => bincode::deserialize(bytes).map_err(|err| (*err).into()),
+            _ => Err(Error::InvalidData),
         }
     }
     /// Get the content of msg as text. In case - binary content being actually binary
@@ -308,7 +387,7 @@ impl Msg {
             }
             Some(Text(ref s)) => Some(s),
             Some(Command(ref action)) => Some(action.inner()),
-            None => None,
+            Some(Typed(..)) | None => None,
         }
     }
     ///Is the message actually a command?
@@ -342,15 +421,19 @@ impl Msg {
     pub fn as_bytes(&self) -> Vec<u8> {
         option_of_bytes(self).unwrap_or_default()
     }
+    //The message encoded with the codec of the given id
+    pub(crate) fn encode(&self, codec: u8) -> Result<Vec<u8>> {
+        codec::encode(codec, self)
+    }
     ///Construct a text reply with content as string and message direction reversed
     pub fn text_reply(&mut self, reply: &str) {
         swap(&mut self.from, &mut self.to);
-        let _ignore = replace(&mut self.content, Some(Text(reply.to_string())));
+        let _ignore = self.content.replace(Text(reply.to_string()));
     }
 
     ///Update the content of the message - text
     pub fn update_text_content(&mut self, reply: &str) {
-        let _ignore = replace(&mut self.content, Some(Text(reply.to_string())));
+        let _ignore = self.content.replace(Text(reply.to_string()));
     }
     ///Set new binary content and new local recipient actor address
     pub fn with_content_and_to(&mut self, new_content: Vec<u8>, new_to: &str) {
@@ -420,6 +503,61 @@ impl Msg {
     pub fn get_id(&self) -> &u64 {
         &self.id
     }
+    //A fresh identity for a message that is being sent anew - such as a re-injected dead
+    //letter. The time-to-live clock - if any - starts afresh.
+    pub(crate) fn renew_id(&mut self) {
+        self.id = compute_hash(&Uuid::new_v4());
+        if self.ttl.is_some() {
+            self.dispatched = Some(SystemTime::now());
+        }
+    }
+    ///Set a time-to-live on the message. A message not delivered within the time-to-live
+    ///is not handed to the recipient - it ends up in the dead letters instead. The clock
+    ///starts at dispatch - or now for messages without a dispatch time.
+    pub fn set_ttl(&mut self, ttl: Duration) {
+        self.dispatched.get_or_insert_with(SystemTime::now);
+        self.ttl = Some(ttl);
+    }
+    ///Get the time-to-live - if any
+    pub fn get_ttl(&self) -> Option<Duration> {
+        self.ttl
+    }
+    ///Has the time-to-live of the message elapsed?
+    pub fn is_expired(&self) -> bool {
+        match (self.dispatched, self.ttl) {
+            (Some(dispatched), Some(ttl)) => {
+                dispatched.elapsed().is_ok_and(|elapsed| elapsed >= ttl)
+            }
+            _ => false,
+        }
+    }
+    //Expiry time in milliseconds since epoch - stored alongside the message so that
+    //expired messages can be swept
+    pub(crate) fn expires_at(&self) -> Option<i64> {
+        let expiry = self.dispatched? + self.ttl?;
+        expiry
+            .duration_since(UNIX_EPOCH)
+            .ok()
+            .map(|since| since.as_millis() as i64)
+    }
+    ///Set a key on the message - messages sent to a pool with
+    ///[ConsistentHash](crate::Distribution::ConsistentHash) distribution go to the same
+    ///member if their keys are the same
+    pub fn set_key(&mut self, key: &str) {
+        self.key = Some(key.to_string());
+    }
+    ///Get the key - if any
+    pub fn get_key(&self) -> Option<&str> {
+        self.key.as_deref()
+    }
+    //A copy of the message for another recipient - a delivery of its own that keeps the
+    //time-to-live clock of the original
+    pub(crate) fn copy_to(&self, to: &Addr) -> Msg {
+        let mut copy = self.clone();
+        copy.id = compute_hash(&Uuid::new_v4());
+        copy.to = Some(to.clone());
+        copy
+    }
     ///Get the id as string. Required because unique ids overflow i64 range supported by
     ///the backing store
     pub fn id_as_string(&self) -> String {
@@ -448,29 +586,30 @@ impl Msg {
     ///Set the from address of a message - specific usage while sending out actor message
     ///processing outcome
     pub fn set_from(&mut self, from: &Addr) {
-        let _ignore = std::mem::replace(&mut self.from, Some(from.clone()));
+        let _ignore = self.from.replace(from.clone());
     }
 
     ///Construct a Shutdown command to shutdown the system listener
     pub fn shutdown() -> Self {
         let mut cmd = Msg::default();
-        let _ignore = std::mem::replace(&mut cmd.content, Some(Content::Command(Action::Shutdown)));
+        let _ignore = cmd.content.replace(Content::Command(Action::Shutdown));
         cmd
     }
     ///Construct a Echo command to send to the system listener to check its liveness
     pub fn echo(s: &str) -> Self {
         let mut cmd = Msg::default();
-        let _ignore = std::mem::replace(
-            &mut cmd.content,
-            Some(Content::Command(Action::Echo(s.to_string()))),
-        );
+        let _ignore = cmd
+            .content
+            .replace(Content::Command(Action::Echo(s.to_string())));
         cmd
     }
-}
-
-impl Default for Mail {
-    fn default() -> Self {
-        Mail::Blank
+    ///Construct an Ask command wrapping a message whose reply the sender is going to wait
+    ///for - the reply is whatever the recipient actor returns for the wrapped message
+    pub fn ask(msg: Msg, timeout: Duration) -> Self {
+        let mut cmd = Msg::default();
+        let millis = timeout.as_millis() as u64;
+        cmd.content = Some(Content::Command(Action::Ask(Box::new(msg), millis)));
+        cmd
     }
 }
 
@@ -481,6 +620,7 @@ impl From<Msg> for Mail {
 }
 
 //A mail with extra details - inbound/outbound, seq, from & destined to
+#[derive(Clone)]
 pub(crate) enum RichMail {
     RichContent(Mail, bool, i64, Option<Addr>, Option<Addr>),
 }
@@ -549,6 +689,9 @@ impl std::fmt::Display for Content {
             Command(c) => {
                 write!(f, "Command({})", c)
             }
+            Typed(tag, value) => {
+                write!(f, "Typed({}) -> length {}", tag, value.len())
+            }
         }
     }
 }
@@ -581,6 +724,21 @@ mod tests {
     use super::*;
     use crate::common::utils::{from_bytes, option_of_bytes};
 
+    #[test]
+    fn msg_ttl_test_1() {
+        let mut msg = Msg::from_text("Short lived");
+        assert!(!msg.is_expired());
+        assert_eq!(msg.expires_at(), None);
+        msg.set_ttl(Duration::from_millis(10));
+        assert!(msg.expires_at().is_some());
+        std::thread::sleep(Duration::from_millis(20));
+        assert!(msg.is_expired());
+        msg.renew_id();
+        assert!(!msg.is_expired());
+        let msg: Msg = from_bytes(&msg.as_bytes()).unwrap();
+        assert_eq!(msg.get_ttl(), Some(Duration::from_millis(10)));
+    }
+
     #[test]
     fn create_trade_msg_test_content_and_to() {
         let mut msg = Msg::new(option_of_bytes(&"Content"), "addr_from", "addr_to");
@@ -716,6 +874,23 @@ mod tests {
         println!("Bulk {}", Mail::Blank);
     }
 
+    #[test]
+    fn ask_command_test_1() {
+        let asked = Msg::with_text("Some text", "from", "to");
+        let mail: Mail = Mail::Bulk(vec![Msg::ask(asked.clone(), Duration::from_secs(2))]);
+        assert!(mail.is_command());
+        assert!(mail.command_equals(Action::Ask(Box::default(), 0)));
+        let bytes = option_of_bytes(&mail).unwrap();
+        let mail: Mail = from_bytes(&bytes).unwrap();
+        match mail.action() {
+            Some(Action::Ask(msg, millis)) => {
+                assert_eq!(*msg, asked);
+                assert_eq!(millis, 2000);
+            }
+            _ => panic!("Expected an ask command"),
+        }
+    }
+
     #[test]
     fn mail_is_command_test_1() {
         let trade_mail: Mail = Msg::with_text("Some text", "from", "to").into();
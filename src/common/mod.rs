@@ -3,7 +3,14 @@
 
 pub(crate) mod actor;
 pub(crate) mod addr;
+pub mod codec;
 pub mod config;
+pub(crate) mod context;
+pub(crate) mod envelope;
 pub(crate) mod errs;
+pub(crate) mod executor;
 pub(crate) mod mail;
+pub(crate) mod pool;
+pub(crate) mod supervision;
+pub(crate) mod typed;
 pub(crate) mod utils;
@@ -0,0 +1,80 @@
+//! # Pool
+//!A group of identical actors behind one logical address. Members are produced by a single
+//![Producer](crate::Producer) and messages sent to the pool address are handed out to the
+//!members as per the pool's [Distribution]. Pool definition and size are persisted -
+//!pools survive restarts and can be resized at runtime.
+//!
+//!Members are named after the pool - `<pool name>#<index>` - and are children of the pool.
+
+use crate::Addr;
+use serde::{Deserialize, Serialize};
+
+///How messages sent to a pool are handed out to its members
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Distribution {
+    ///Members take turns
+    RoundRobin,
+    ///The member with the fewest messages pending gets the message
+    SmallestInbox,
+    ///Messages with the same key go to the same member - see [Msg::set_key](crate::Msg::set_key).
+    ///Resizing moves only the keys of the members added or removed. Messages without a
+    ///key are spread by their id.
+    ConsistentHash,
+    ///Every member gets a copy
+    Broadcast,
+}
+
+impl Distribution {
+    pub(crate) fn to_text(self) -> String {
+        serde_json::to_string(&self).unwrap_or_default()
+    }
+
+    pub(crate) fn from_text(text: &str) -> Self {
+        serde_json::from_str(text).unwrap_or(Distribution::RoundRobin)
+    }
+}
+
+//Address of a pool member
+pub(crate) fn member(pool: &Addr, index: usize) -> Addr {
+    Addr::new(&format!("{}#{}", pool.get_name(), index))
+}
+
+//Jump consistent hash - maps a key to one of the buckets such that growing or shrinking the
+//buckets by one moves only the keys of that bucket
+pub(crate) fn jump_hash(mut key: u64, buckets: usize) -> usize {
+    let (mut bucket, mut next) = (-1i64, 0i64);
+    while next < buckets as i64 {
+        bucket = next;
+        key = key.wrapping_mul(2862933555777941757).wrapping_add(1);
+        next = ((bucket + 1) as f64 * ((1u64 << 31) as f64 / ((key >> 33) + 1) as f64)) as i64;
+    }
+    bucket.max(0) as usize
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pool_member_test_1() {
+        let pool = Addr::new("workers");
+        assert_eq!(member(&pool, 2).get_name(), "workers#2");
+        assert_eq!(member(&pool, 2), member(&pool, 2));
+        assert_eq!(
+            Distribution::from_text(&Distribution::ConsistentHash.to_text()),
+            Distribution::ConsistentHash
+        );
+    }
+
+    #[test]
+    fn pool_jump_hash_test_1() {
+        for key in 0..1000u64 {
+            let key = crate::compute_hash(&key);
+            let (four, five) = (jump_hash(key, 4), jump_hash(key, 5));
+            assert!(four < 4);
+            //Growing the pool moves keys only to the new member
+            assert!(five == four || five == 4);
+        }
+        assert_eq!(jump_hash(42, 1), 0);
+    }
+}
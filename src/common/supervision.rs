@@ -0,0 +1,177 @@
+//! # Supervision
+//!How the system reacts when an actor panics while processing a message. Supervision is
+//!chosen per actor at definition time and gets persisted alongside the producer
+//!definition.
+//!
+//!Failures are counted within a sliding time window. Once an actor fails `max_failures`
+//!times within the window - it is stopped irrespective of the strategy.
+
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+///What to do with an actor that has panicked on a message
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Strategy {
+    ///Replace the actor with a fresh instance and hand it the failed message again
+    RestartResume,
+    ///Replace the actor with a fresh instance and move past the failed message. The
+    ///message ends up in the dead letters.
+    RestartSkip,
+    ///Replace the actor with a fresh instance and hand it the failed message again after
+    ///a delay. The delay starts at `initial` and doubles with every failure within the
+    ///window - capped at `max`. Messages to the actor arriving meanwhile wait for the retry,
+    ///other actors are not held up.
+    Backoff {
+        ///Delay after the first failure
+        initial: Duration,
+        ///Upper bound of the delay
+        max: Duration,
+    },
+    ///Stop the actor at the first failure. The failed message ends up in the dead letters.
+    Stop,
+    ///Let the supervision of the parent actor decide. Actors without a parent are stopped.
+    Escalate,
+}
+
+///Supervision strategy of an actor along with its failure tolerance
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Supervision {
+    strategy: Strategy,
+    max_failures: u32,
+    within: Duration,
+}
+
+//Course of action decided for a failed actor
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Directive {
+    Restart { retry: bool, after: Duration },
+    Stop,
+    Escalate,
+}
+
+impl Default for Supervision {
+    ///Restart skipping the failed message - stop after 3 failures within a minute
+    fn default() -> Self {
+        Self {
+            strategy: Strategy::RestartSkip,
+            max_failures: 3,
+            within: Duration::from_secs(60),
+        }
+    }
+}
+
+impl Supervision {
+    ///Supervision with the given strategy and the default failure tolerance
+    pub fn new(strategy: Strategy) -> Self {
+        Self {
+            strategy,
+            ..Default::default()
+        }
+    }
+
+    ///Stop the actor once it fails `max_failures` times within the given window
+    pub fn tolerate(mut self, max_failures: u32, within: Duration) -> Self {
+        self.max_failures = max_failures.max(1);
+        self.within = within;
+        self
+    }
+
+    ///The strategy
+    pub fn strategy(&self) -> Strategy {
+        self.strategy
+    }
+
+    ///Failures that lead to the actor being stopped
+    pub fn max_failures(&self) -> u32 {
+        self.max_failures
+    }
+
+    ///The window failures are counted within
+    pub fn within(&self) -> Duration {
+        self.within
+    }
+
+    //Decide the course of action given the number of failures within the window - the
+    //latest one included
+    pub(crate) fn directive(&self, failures: u32) -> Directive {
+        match self.strategy {
+            Strategy::Stop => Directive::Stop,
+            Strategy::Escalate => Directive::Escalate,
+            _ if failures >= self.max_failures => Directive::Stop,
+            Strategy::RestartResume => Directive::Restart {
+                retry: true,
+                after: Duration::ZERO,
+            },
+            Strategy::RestartSkip => Directive::Restart {
+                retry: false,
+                after: Duration::ZERO,
+            },
+            Strategy::Backoff { initial, max } => {
+                let factor = 2u32.saturating_pow(failures.saturating_sub(1));
+                Directive::Restart {
+                    retry: true,
+                    after: initial.saturating_mul(factor).min(max),
+                }
+            }
+        }
+    }
+
+    pub(crate) fn to_text(&self) -> String {
+        serde_json::to_string(self).unwrap_or_default()
+    }
+
+    //Actors defined before supervision was persisted get the default
+    pub(crate) fn from_text(text: Option<&str>) -> Self {
+        text.and_then(|text| serde_json::from_str(text).ok())
+            .unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn supervision_directive_test_1() {
+        let supervision = Supervision::new(Strategy::RestartResume);
+        assert_eq!(
+            supervision.directive(1),
+            Directive::Restart {
+                retry: true,
+                after: Duration::ZERO
+            }
+        );
+        assert_eq!(supervision.directive(3), Directive::Stop);
+        assert_eq!(
+            Supervision::new(Strategy::Stop).directive(1),
+            Directive::Stop
+        );
+    }
+
+    #[test]
+    fn supervision_backoff_test_1() {
+        let supervision = Supervision::new(Strategy::Backoff {
+            initial: Duration::from_millis(100),
+            max: Duration::from_millis(300),
+        })
+        .tolerate(5, Duration::from_secs(10));
+        let delay = |failures| match supervision.directive(failures) {
+            Directive::Restart { after, .. } => after,
+            _ => panic!("Expected restart"),
+        };
+        assert_eq!(delay(1), Duration::from_millis(100));
+        assert_eq!(delay(2), Duration::from_millis(200));
+        assert_eq!(delay(3), Duration::from_millis(300));
+        assert_eq!(supervision.directive(5), Directive::Stop);
+    }
+
+    #[test]
+    fn supervision_text_test_1() {
+        let supervision = Supervision::new(Strategy::Escalate);
+        assert_eq!(
+            Supervision::from_text(Some(&supervision.to_text())),
+            supervision
+        );
+        assert_eq!(Supervision::from_text(None), Supervision::default());
+    }
+}
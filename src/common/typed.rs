@@ -0,0 +1,234 @@
+//! # Typed
+//!Actors that work with serde types instead of raw [Mail](crate::Mail). Messages are
+//!created with [Msg::typed](crate::Msg::typed) - which tags them with the [Tagged::TAG] of
+//!the value they carry. A [TypedActor] declares the messages it handles as a [TypedMessage] - usually
+//!an enum with a variant per handled type, defined via the
+//![typed_messages!](crate::typed_messages) macro. Incoming messages are decoded into it
+//!before being handed to the actor - anything else goes to the
+//![unhandled](TypedActor::unhandled) hook.
+
+use crate::{Actor, ActorContext, Mail, Msg};
+use std::any;
+use std::marker::PhantomData;
+
+///Types carried by typed messages. The tag identifies the type on the wire - it is chosen
+///by the implementor so that it stays the same across builds, compilers and module moves.
+///Tags should be unique among the types an actor handles.
+pub trait Tagged {
+    ///Identifier of the type - e.g. "account.deposit"
+    const TAG: &'static str;
+}
+
+///Messages a [TypedActor] handles - decoded from incoming messages by their type tag
+pub trait TypedMessage: Sized + 'static {
+    ///Decode a message - None if the message is not one of the handled ones
+    fn from_msg(msg: &Msg) -> Option<Self>;
+}
+
+pub trait TypedActor<M: TypedMessage>: Send + Sync + 'static {
+    //! # TypedActor
+    //!
+    //!`TypedActor` - an actor that receives decoded messages of type `M`. Typed actors are
+    //!handed out by [Producer](crate::Producer)s wrapped in a [Typed].
+
+    ///The required method - handle a decoded message
+    fn handle(&mut self, msg: M) -> Option<Mail>;
+
+    ///
+    ///Handle a decoded message along with the [ActorContext]. Defaults to calling
+    ///[handle](TypedActor::handle).
+    ///
+    fn handle_with(&mut self, msg: M, _ctx: &mut ActorContext) -> Option<Mail> {
+        self.handle(msg)
+    }
+
+    ///
+    ///Mail that does not decode into `M` - messages of other types, bulk mail and the
+    ///startup signal. Ignored by default.
+    ///
+    fn unhandled(&mut self, _mail: Mail) -> Option<Mail> {
+        None
+    }
+
+    ///
+    ///Name of the type implementing the `TypedActor` trait
+    ///
+    fn type_name(&self) -> &'static str {
+        any::type_name::<Self>()
+    }
+
+    ///
+    ///Optional hook to capture the in-memory state of the actor - see [Actor::snapshot]
+    ///
+    fn snapshot(&self) -> Option<Vec<u8>> {
+        None
+    }
+
+    ///
+    ///Rebuild in-memory state from the latest snapshot - see [Actor::restore_state]
+    ///
+    fn restore_state(&mut self, _state: &[u8]) {}
+
+    ///
+    ///Opt in to event sourced recovery - see [Actor::event_sourced]
+    ///
+    fn event_sourced(&self) -> bool {
+        false
+    }
+}
+
+///Runs a [TypedActor] as an [Actor] - incoming messages are decoded before being handed to
+///the typed actor
+pub struct Typed<A: TypedActor<M>, M: TypedMessage> {
+    actor: A,
+    handles: PhantomData<fn() -> M>,
+}
+
+impl<A: TypedActor<M>, M: TypedMessage> Typed<A, M> {
+    ///Wrap a typed actor
+    pub fn new(actor: A) -> Self {
+        Self {
+            actor,
+            handles: PhantomData,
+        }
+    }
+
+    fn decode(mail: &Mail) -> Option<M> {
+        match mail {
+            Mail::Trade(msg) => M::from_msg(msg),
+            _ => None,
+        }
+    }
+}
+
+impl<A: TypedActor<M>, M: TypedMessage> Actor for Typed<A, M> {
+    fn receive(&mut self, mail: Mail) -> Option<Mail> {
+        match Self::decode(&mail) {
+            Some(msg) => self.actor.handle(msg),
+            None => self.actor.unhandled(mail),
+        }
+    }
+
+    fn receive_with(&mut self, mail: Mail, ctx: &mut ActorContext) -> Option<Mail> {
+        match Self::decode(&mail) {
+            Some(msg) => self.actor.handle_with(msg, ctx),
+            None => self.actor.unhandled(mail),
+        }
+    }
+
+    fn type_name(&self) -> &'static str {
+        self.actor.type_name()
+    }
+
+    fn snapshot(&self) -> Option<Vec<u8>> {
+        self.actor.snapshot()
+    }
+
+    fn restore_state(&mut self, state: &[u8]) {
+        self.actor.restore_state(state)
+    }
+
+    fn event_sourced(&self) -> bool {
+        self.actor.event_sourced()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::typed_messages;
+    use serde::{Deserialize, Serialize};
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Deposit {
+        amount: u64,
+    }
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Withdraw {
+        amount: u64,
+    }
+
+    impl Tagged for Deposit {
+        const TAG: &'static str = "account.deposit";
+    }
+
+    impl Tagged for Withdraw {
+        const TAG: &'static str = "account.withdraw";
+    }
+
+    //The same message - as defined by some other module or node
+    mod elsewhere {
+        use super::Tagged;
+        use serde::{Deserialize, Serialize};
+
+        #[derive(Debug, PartialEq, Serialize, Deserialize)]
+        pub(super) struct Deposit {
+            pub(super) amount: u64,
+        }
+
+        impl Tagged for Deposit {
+            const TAG: &'static str = "account.deposit";
+        }
+    }
+
+    typed_messages! {
+        enum Account {
+            Deposit(Deposit),
+            Withdraw(Withdraw),
+        }
+    }
+
+    #[derive(Default)]
+    struct Balance(i64);
+
+    impl TypedActor<Account> for Balance {
+        fn handle(&mut self, msg: Account) -> Option<Mail> {
+            match msg {
+                Account::Deposit(deposit) => self.0 += deposit.amount as i64,
+                Account::Withdraw(withdraw) => self.0 -= withdraw.amount as i64,
+            }
+            None
+        }
+
+        fn unhandled(&mut self, _mail: Mail) -> Option<Mail> {
+            Some(Msg::from_text("Unhandled").into())
+        }
+    }
+
+    #[test]
+    fn typed_msg_test_1() {
+        let msg = Msg::typed(&Deposit { amount: 5 });
+        assert!(msg.is_of::<Deposit>());
+        assert!(!msg.is_of::<Withdraw>());
+        assert_eq!(msg.decode::<Deposit>().ok(), Some(Deposit { amount: 5 }));
+        assert!(msg.decode::<Withdraw>().is_err());
+        assert!(Msg::from_text("5").decode::<Deposit>().is_err());
+    }
+
+    #[test]
+    fn typed_msg_test_2() {
+        let msg = Msg::typed(&elsewhere::Deposit { amount: 5 });
+        assert_eq!(msg.type_tag(), Some("account.deposit"));
+        assert!(msg.is_of::<Deposit>());
+        assert_eq!(msg.decode::<Deposit>().ok(), Some(Deposit { amount: 5 }));
+        let msg = Msg::typed(&Deposit { amount: 7 });
+        assert_eq!(
+            msg.decode::<elsewhere::Deposit>().ok(),
+            Some(elsewhere::Deposit { amount: 7 })
+        );
+    }
+
+    #[test]
+    fn typed_actor_test_1() {
+        let mut actor = Typed::new(Balance::default());
+        assert!(actor
+            .receive(Msg::typed(&Deposit { amount: 10 }).into())
+            .is_none());
+        assert!(actor
+            .receive(Msg::typed(&Withdraw { amount: 3 }).into())
+            .is_none());
+        assert!(actor.receive(Msg::from_text("10").into()).is_some());
+        assert_eq!(actor.actor.0, 7);
+    }
+}
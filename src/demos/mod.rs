@@ -13,8 +13,9 @@ impl Actor for DemoActor {
     fn receive(&mut self, incoming: Mail) -> Option<Mail> {
         match incoming {
             Mail::Trade(msg) => println!("DemoActor received: {:?}", msg.as_text()),
-            bulk @ Mail::Bulk(_) => 
-                println!("DemoActor received: {:?}", bulk.messages()[0].as_text()),
+            bulk @ Mail::Bulk(_) => {
+                println!("DemoActor received: {:?}", bulk.messages()[0].as_text())
+            }
             Mail::Blank => println!("DemoActor received blank"),
         }
         Some(Msg::from_text("Message from DemoActor").into())
@@ -57,3 +58,41 @@ impl Producer for AnotherProducer {
         Box::new(AnotherActor)
     }
 }
+
+///A sample stateful actor - keeps count of the messages it has received. The count
+///survives restarts via state snapshots.
+#[derive(Default)]
+pub struct CounterActor {
+    count: u64,
+}
+
+impl Actor for CounterActor {
+    fn receive(&mut self, incoming: Mail) -> Option<Mail> {
+        if let Mail::Trade(_) = incoming {
+            self.count += 1;
+            println!("CounterActor count {}", self.count);
+        }
+        Some(Msg::from_text(&format!("Count {}", self.count)).into())
+    }
+
+    fn snapshot(&self) -> Option<Vec<u8>> {
+        Some(self.count.to_be_bytes().to_vec())
+    }
+
+    fn restore_state(&mut self, state: &[u8]) {
+        let mut count = [0; 8];
+        count.copy_from_slice(&state[..8]);
+        self.count = u64::from_be_bytes(count);
+    }
+}
+
+///Produces instances of CounterActor
+#[derive(Debug, Serialize, Deserialize, Default)]
+pub struct CounterProducer;
+
+#[typetag::serde]
+impl Producer for CounterProducer {
+    fn produce(&mut self) -> Box<dyn Actor> {
+        Box::new(CounterActor::default())
+    }
+}
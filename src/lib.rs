@@ -92,7 +92,7 @@
 //! 3) Fire the `register.sh` script in the project directory.
 //! 4) In another terminal launch the `server.sh` script in the same directory
 //! 5) From previous termainal launch the `send.sh` script - actors should start receiving
-//! messages.
+//!    messages.
 //!
 //!Contribution:
 //!This project is still evolving. Contributions are welcome.
@@ -104,13 +104,23 @@
 #![warn(missing_docs)]
 #![cfg_attr(docsrs, feature(doc_cfg))]
 
+pub use catalog::DeadLetter;
 pub(crate) use common::actor::ProducerDeserializer;
-pub use common::actor::{Actor, Producer};
+pub use common::actor::{Actor, AsyncActor, Asynchronous, MailFuture, Producer};
 pub use common::addr::Addr;
-pub use common::config::Config;
+#[cfg(feature = "json")]
+pub use common::codec::Json;
+#[cfg(feature = "msgpack")]
+pub use common::codec::MessagePack;
+pub use common::codec::{Bincode, Codec};
+pub use common::config::{Backend, Config, Retention};
+pub use common::context::ActorContext;
 pub use common::errs::{Error, Result};
 pub(crate) use common::mail::RichMail;
 pub use common::mail::{Action, Mail, Msg};
+pub use common::pool::Distribution;
+pub use common::supervision::{Strategy, Supervision};
+pub use common::typed::{Tagged, Typed, TypedActor, TypedMessage};
 pub use common::utils::*;
 pub use demos::*;
 pub(crate) use store::*;
@@ -4,6 +4,12 @@
 //![define_actor!](crate::define_actor)
 //!
 //![send!](crate::send)
+//!
+//![ask!](crate::ask)
+//!
+//![publish!](crate::publish)
+//!
+//![typed_messages!](crate::typed_messages)
 
 ///This macro defines a new actor instance in the system. It takes a literal string as
 ///actor name and an implmentation of [Producer](crate::common::actor::Producer) that is
@@ -77,6 +83,18 @@
 ///define_actor!("yet_another_actor", producer3);
 ///
 ///```
+///A [Supervision](crate::Supervision) can be supplied as well - to decide what happens
+///when the actor panics while processing a message.
+///
+///```
+///use arrows::{Strategy, Supervision};
+///use std::time::Duration;
+///
+///let producer4 = NewProducer::default();
+///let supervision = Supervision::new(Strategy::RestartResume).tolerate(5, Duration::from_secs(30));
+///define_actor!("supervised_actor", producer4, supervision);
+///
+///```
 ///
 ///
 #[macro_export]
@@ -85,10 +103,19 @@ macro_rules! define_actor {
         let addr = $crate::Addr::new($actor_name);
         let _res = $crate::catalog::define_actor(addr, $actor_producer);
     }};
+    ($actor_name:literal, $actor_producer:path, $supervision:expr) => {{
+        let addr = $crate::Addr::new($actor_name);
+        let _res = $crate::catalog::define_supervised_actor(addr, $actor_producer, $supervision);
+    }};
     ($actor_addr:expr, $actor_producer:path) => {{
         let actor_addr: $crate::Addr = $actor_addr;
         let _res = $crate::catalog::define_actor(actor_addr, $actor_producer);
     }};
+    ($actor_addr:expr, $actor_producer:path, $supervision:expr) => {{
+        let actor_addr: $crate::Addr = $actor_addr;
+        let _res =
+            $crate::catalog::define_supervised_actor(actor_addr, $actor_producer, $supervision);
+    }};
 }
 ///Sends one or more messages to one or more actors defined in the system. This function is
 ///responsible for gathering and dispatching messages received from the macro invocation
@@ -177,3 +204,156 @@ macro_rules! send {
         $crate::Addr::new($actor_name)
     };
 }
+
+///Sends a message to an actor and waits for its reply. The reply is whatever the actor
+///returns from `receive` for the message. Evaluates to a `Result<Mail>` - which would be
+///a [Timeout](crate::Error::Timeout) error if the reply does not arrive in time. The wait
+///defaults to [ASK_TIMEOUT](crate::routing::messenger::ASK_TIMEOUT) if no timeout is given.
+///
+///Example
+///
+///```
+///use arrows::{ask, Addr, Msg};
+///use std::time::Duration;
+///
+///let m1 = Msg::from_text("Message to another_actor");
+///let reply = ask!("another_actor", m1);
+///
+///let remote_addr = Addr::remote("actor1", "10.10.10.10:7171");
+///let m2 = Msg::from_text("Message to remote actor1");
+///let reply = ask!(remote_addr, m2, Duration::from_secs(10));
+///
+///```
+#[macro_export]
+macro_rules! ask {
+    ($actor_name:literal, $msg:expr) => {
+        $crate::ask!($actor_name, $msg, $crate::routing::messenger::ASK_TIMEOUT)
+    };
+
+    ($actor_name:literal, $msg:expr, $timeout:expr) => {
+        $crate::ask!($crate::Addr::new($actor_name), $msg, $timeout)
+    };
+
+    ($addr:expr, $msg:expr) => {
+        $crate::ask!($addr, $msg, $crate::routing::messenger::ASK_TIMEOUT)
+    };
+
+    ($addr:expr, $msg:expr, $timeout:expr) => {{
+        let addr: $crate::Addr = $addr;
+        let msg: $crate::Msg = $msg;
+        $crate::routing::messenger::Messenger::ask(&addr, msg, $timeout)
+    }};
+}
+
+///Publishes one or more messages to one or more topics. Every actor subscribed to a topic
+///gets its own copy of each message published to it - in the order of publication.
+///Messages can be grouped per topic just like with [send!](crate::send). Evaluates to a
+///`Result<usize>` - the number of copies sent out.
+///
+///Example
+///
+///```
+///use arrows::catalog::subscribe;
+///use arrows::{publish, Addr, Msg};
+///
+///subscribe("prices", &Addr::new("actor1"))?;
+///subscribe("prices", &Addr::remote("actor2", "10.10.10.10:7171"))?;
+///
+///let m1 = Msg::from_text("Price update 1");
+///let m2 = Msg::from_text("Price update 2");
+///let m3 = Msg::from_text("Trade executed");
+///publish!("prices", m1, m2);
+///publish!("prices", (Msg::from_text("Price update 3")), "trades", (m3));
+///
+///```
+#[macro_export]
+macro_rules! publish {
+    ($($topic:expr, ($($msg:expr),*)),+) => {{
+        let mut published: $crate::Result<usize> = Ok(0);
+        $(
+            published = published.and_then(|count| {
+                $crate::catalog::publish_all($topic, vec![$($msg),*]).map(|more| count + more)
+            });
+        )+
+        published
+    }};
+
+    ($topic:expr, $($msg:expr),+) => {
+        $crate::catalog::publish_all($topic, vec![$($msg),+])
+    };
+}
+
+///Defines an enum of the messages a [TypedActor](crate::TypedActor) handles - a variant per
+///message type - along with its [TypedMessage](crate::TypedMessage) implementation. Incoming
+///messages created via [Msg::typed](crate::Msg::typed) are decoded into the variant whose
+///type's [TAG](crate::Tagged::TAG) they carry.
+///
+///Example
+///
+///```
+///use arrows::{typed_messages, Mail, Tagged, TypedActor};
+///use serde::{Deserialize, Serialize};
+///
+///#[derive(Debug, Serialize, Deserialize)]
+///pub struct Deposit {
+///    amount: u64,
+///}
+///
+///impl Tagged for Deposit {
+///    const TAG: &'static str = "account.deposit";
+///}
+///
+///#[derive(Debug, Serialize, Deserialize)]
+///pub struct Withdraw {
+///    amount: u64,
+///}
+///
+///impl Tagged for Withdraw {
+///    const TAG: &'static str = "account.withdraw";
+///}
+///
+///typed_messages! {
+///    pub enum AccountMsg {
+///        Deposit(Deposit),
+///        Withdraw(Withdraw),
+///    }
+///}
+///
+///pub struct Account {
+///    balance: u64,
+///}
+///
+///impl TypedActor<AccountMsg> for Account {
+///    fn handle(&mut self, msg: AccountMsg) -> Option<Mail> {
+///        match msg {
+///            AccountMsg::Deposit(deposit) => self.balance += deposit.amount,
+///            AccountMsg::Withdraw(withdraw) => self.balance -= withdraw.amount,
+///        }
+///        None
+///    }
+///}
+///
+///```
+#[macro_export]
+macro_rules! typed_messages {
+    ($(#[$meta:meta])* $vis:vis enum $name:ident { $($variant:ident($ty:ty)),+ $(,)? }) => {
+        $(#[$meta])*
+        $vis enum $name {
+            $(
+                #[allow(missing_docs)]
+                $variant($ty),
+            )+
+        }
+
+        impl $crate::TypedMessage for $name {
+            fn from_msg(msg: &$crate::Msg) -> Option<Self> {
+                $(
+                    if msg.is_of::<$ty>() {
+                        return msg.decode::<$ty>().ok().map($name::$variant);
+                    }
+                )+
+                None
+            }
+        }
+    };
+}
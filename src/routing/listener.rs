@@ -4,17 +4,31 @@
 //!It would be automatically launched when a message ingress happens via the `send!`
 //!macro invocation
 //!
-use crate::catalog::ingress;
+use crate::catalog::{self, ingest};
 
-use crate::{from_bytes, Action, Action::*, Addr, Config, Mail};
-use byte_marks::Marked;
-use std::io::{BufReader, BufWriter, Result, Write};
-use std::net::{SocketAddr, TcpListener, TcpStream};
+use crate::common::codec;
+use crate::common::envelope::{self, Ack};
+use crate::routing::protocol::{
+    self, Delivery, ErrorCode, Failure, Frame, FrameType, Status, LEGACY_PROTOCOL, PROTOCOL_VERSION,
+};
+use crate::{Action, Action::*, Addr, Config, Mail, Msg};
+use byte_marks::ByteMarker;
+use parking_lot::Mutex;
+use std::io::ErrorKind::{TimedOut, WouldBlock};
+use std::io::{self, BufRead, BufReader, BufWriter, Result, Write};
+use std::net::{self, SocketAddr, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc::{channel, Receiver};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+//How long a refused client gets to read that the listener is busy
+const REFUSAL_GRACE: Duration = Duration::from_secs(1);
 
 ///Message ingestion entry point of the actor system. Each listener instance fronts a
 ///completely independent actor system that supports message persistence, actor life-cycle
 ///management(defintion, activation/passivation, panicking actor eviction etc) and remoting.
-
 pub struct MessageListener {
     addr: SocketAddr,
 }
@@ -38,18 +52,61 @@ impl MessageListener {
         }
     }
 
-    pub(crate) fn run(mut self) -> Result<()> {
+    pub(crate) fn run(self) -> Result<()> {
         let listener = TcpListener::bind(self.addr)?;
+        //Bring the actor system up front - schedules must fire and pending messages get
+        //routed without waiting for the first incoming message
+        catalog::init();
+        let (max_connections, timeouts) = {
+            let config = Config::get_shared();
+            let timeouts = Timeouts {
+                read: seconds(config.read_timeout()),
+                idle: seconds(config.idle_timeout()),
+            };
+            (config.max_connections().max(1), timeouts)
+        };
+        Self::accept(listener, max_connections, timeouts)
+    }
+
+    //Connections are served by a fixed number of workers - clients keep them open and
+    //pipeline their mails. Connections beyond the number of workers are refused with an
+    //error frame and closed.
+    fn accept(listener: TcpListener, max_connections: usize, timeouts: Timeouts) -> Result<()> {
+        let local_addr = listener.local_addr()?;
+        let shutdown = Arc::new(AtomicBool::new(false));
+        let active = Arc::new(AtomicUsize::new(0));
+        let (sender, receiver) = channel();
+        let receiver = Arc::new(Mutex::new(receiver));
+        for _ in 0..max_connections {
+            let worker = Worker {
+                receiver: Arc::clone(&receiver),
+                active: Arc::clone(&active),
+                shutdown: Arc::clone(&shutdown),
+                local_addr,
+                timeouts,
+            };
+            worker.start();
+        }
         for stream in listener.incoming() {
+            if shutdown.load(Ordering::Acquire) {
+                println!("Shutdown request received!");
+                break;
+            }
             match stream {
-                Ok(inner_stream) => match self.serve(inner_stream) {
-                    Err(serving_error) => eprintln!("Error serving client {}", serving_error),
-                    Ok(Shutdown) => {
-                        println!("Shutdown request received!");
+                Ok(inner_stream) if active.load(Ordering::Acquire) >= max_connections => {
+                    eprintln!(
+                        "Connection limit {} reached - refusing connection from {:?}",
+                        max_connections,
+                        inner_stream.peer_addr()
+                    );
+                    thread::spawn(move || Self::refuse(inner_stream, max_connections));
+                }
+                Ok(inner_stream) => {
+                    active.fetch_add(1, Ordering::AcqRel);
+                    if sender.send(inner_stream).is_err() {
                         break;
                     }
-                    Ok(_) => continue,
-                },
+                }
                 Err(e) => {
                     eprintln!("Error handling connection {}", e);
                 }
@@ -58,26 +115,76 @@ impl MessageListener {
         Ok(())
     }
 
-    fn serve(&mut self, tcp: TcpStream) -> Result<Action> {
+    //Tell a client over the connection limit that the listener is busy. The error frame is
+    //made out by clients greeting with a hello - whatever they sent is drained before
+    //closing, lest the connection gets reset before they read it.
+    fn refuse(mut stream: TcpStream, max_connections: usize) {
+        let failure = Failure::new(
+            ErrorCode::Busy,
+            format!("Connection limit {} reached", max_connections),
+        );
+        let busy = Frame::failure(FrameType::Error, 0, &failure).to_bytes();
+        if let Err(err) = stream
+            .write_all(&busy)
+            .and_then(|_| stream.shutdown(net::Shutdown::Write))
+            .and_then(|_| stream.set_read_timeout(Some(REFUSAL_GRACE)))
+        {
+            eprintln!("Error refusing connection {}", err);
+            return;
+        }
+        let _drained = io::copy(&mut stream, &mut io::sink());
+    }
+
+    //Each incoming frame is responded to in turn. Versioned frames are answered with frames
+    //bearing their correlation ids, tail marked ones with tail marked responses - in the
+    //order they came in. Connections that stay idle or stall midway through a frame are
+    //closed.
+    fn serve(tcp: TcpStream, timeouts: Timeouts) -> Result<Action> {
         let cloned = tcp.try_clone()?;
         let mut reader = BufReader::new(cloned);
         let mut writer = BufWriter::new(tcp);
-        let marked = Marked::with_defaults(&mut reader);
-        let mut response = String::from("Ok");
-        for mail in marked {
-            match self.process(mail) {
-                Ok(Shutdown) => return Ok(Shutdown),
-                Ok(Continue) => continue,
-                Ok(Echo(text)) => {
-                    response.clear();
-                    response.push_str(&text);
+        let marker = ByteMarker::with_defaults();
+        loop {
+            reader.get_ref().set_read_timeout(timeouts.idle)?;
+            match reader.fill_buf() {
+                Ok([]) => break,
+                Ok(_) => (),
+                Err(err) if matches!(err.kind(), WouldBlock | TimedOut) => {
+                    println!(
+                        "Closing idle connection from {:?}",
+                        writer.get_ref().peer_addr()
+                    );
                     break;
                 }
-                Err(err) => eprintln!("Error ingressing mail {}", err),
+                Err(err) => return Err(err),
+            }
+            reader.get_ref().set_read_timeout(timeouts.read)?;
+            let (response, action) = if protocol::is_versioned(reader.buffer()) {
+                match Frame::read(&mut reader)? {
+                    Some(frame) => Self::answer(frame),
+                    None => break,
+                }
+            } else {
+                let frame = match codec::read_frame(&mut reader)? {
+                    Some(frame) => frame,
+                    None => break,
+                };
+                let (mut response, action) = match Self::respond(frame) {
+                    Ok(responded) => responded,
+                    Err(err) => {
+                        eprintln!("Error ingressing mail {}", err);
+                        (b"Ok".to_vec(), Continue)
+                    }
+                };
+                marker.mark_tail(&mut response);
+                (response, action)
+            };
+            writer.write_all(&response)?;
+            writer.flush()?;
+            if let Shutdown = action {
+                return Ok(Shutdown);
             }
         }
-        writer.write_all(response.as_bytes())?;
-        writer.flush()?;
         Ok(Continue)
     }
     //Process action from mails - that might contain command
@@ -85,6 +192,7 @@ impl MessageListener {
         match cmd.action() {
             Some(Shutdown) => Ok(Shutdown),
             Some(Continue) => Ok(Continue),
+            Some(ask @ Ask(_, _)) => Ok(ask),
             //Take out the echo action from mail, execute it feeding the mail, return the
             //echo action from the ouput of the execution
             //Executing echo action reverses the string inside it
@@ -94,13 +202,257 @@ impl MessageListener {
             None => Ok(Continue),
         }
     }
+    //Ingress the asked message and wait for the reply. Reply goes back as an
+    //`Option<Mail>` - `None` meaning the recipient did not reply in time. It is encoded
+    //with the codec the ask came in with.
+    fn await_reply(msg: Msg, millis: u64, codec: u8) -> Vec<u8> {
+        let reply = catalog::ask(msg, Duration::from_millis(millis));
+        codec::frame(codec, &reply).unwrap_or_default()
+    }
+
+    //Answer to a versioned frame - along with the action it results in. Hellos are answered
+    //with the protocol version to use. Frames that can not be made sense of are refused
+    //with a nack. Responses carry the payload encoded with the codec the mail came in with.
+    fn answer(frame: Frame) -> (Vec<u8>, Action) {
+        let Frame {
+            version,
+            kind,
+            correlation,
+            payload,
+        } = frame;
+        let nack = |code, reason: String| {
+            let failure = Failure::new(code, reason);
+            (
+                Frame::failure(FrameType::Nack, correlation, &failure),
+                Continue,
+            )
+        };
+        let (response, action) = match kind {
+            FrameType::Hello if version > LEGACY_PROTOCOL => {
+                (Frame::hello(version.min(PROTOCOL_VERSION)), Continue)
+            }
+            _ if !protocol::is_supported(version) => nack(
+                ErrorCode::UnsupportedVersion,
+                format!("Protocol version {}", version),
+            ),
+            FrameType::Data | FrameType::Command => {
+                let (codec, msg_version, payload) = codec::unframe(&payload);
+                match envelope::decode_mail(codec, msg_version, payload) {
+                    Ok(mail) if kind == FrameType::Command => {
+                        Self::command(mail, codec, correlation)
+                    }
+                    Ok(mail) => (Self::receipt(mail, codec, correlation), Continue),
+                    Err(err) => nack(ErrorCode::from(&err), err.to_string()),
+                }
+            }
+            kind => nack(ErrorCode::UnexpectedFrame, format!("{:?} frame", kind)),
+        };
+        (response.to_bytes(), action)
+    }
+
+    fn command(cmd: Mail, codec: u8, correlation: u64) -> (Frame, Action) {
+        let ack = |payload| Frame::new(FrameType::Ack, correlation, payload);
+        match Self::process_cmd(cmd) {
+            Ok(Echo(text)) => (ack(text.into_bytes()), Continue),
+            Ok(Ask(msg, millis)) => (ack(Self::await_reply(*msg, millis, codec)), Continue),
+            Ok(action) => (ack(Vec::new()), action),
+            Err(err) => {
+                let failure = Failure::new(ErrorCode::Internal, err);
+                (
+                    Frame::failure(FrameType::Error, correlation, &failure),
+                    Continue,
+                )
+            }
+        }
+    }
+
+    //Ack telling what became of each message of a mail
+    fn receipt(mail: Mail, codec: u8, correlation: u64) -> Frame {
+        let deliveries = Self::take_in(mail);
+        match codec::frame(codec, &deliveries) {
+            Ok(payload) => Frame::new(FrameType::Ack, correlation, payload),
+            Err(err) => {
+                let failure = Failure::new(ErrorCode::Internal, err);
+                Frame::failure(FrameType::Error, correlation, &failure)
+            }
+        }
+    }
+
+    //Ingest the messages of a mail - those without a recipient fail. Messages ingested
+    //before are told apart as duplicates.
+    fn take_in(mail: Mail) -> Vec<Delivery> {
+        let delivery = |msg_id, status| Delivery { msg_id, status };
+        let (msgs, strays): (Vec<Msg>, Vec<Msg>) = mail
+            .take_all()
+            .into_iter()
+            .partition(|msg| msg.get_to().is_some());
+        let mut deliveries: Vec<Delivery> = strays
+            .iter()
+            .map(|msg| delivery(*msg.get_id(), Status::Failed(ErrorCode::NoRecipient)))
+            .collect();
+        if msgs.is_empty() {
+            return deliveries;
+        }
+        let msg_ids: Vec<u64> = msgs.iter().map(|msg| *msg.get_id()).collect();
+        match ingest(Mail::Bulk(msgs)) {
+            Ok(mut duplicates) => {
+                //A message may come twice in the same mail
+                for msg_id in msg_ids {
+                    let status = match duplicates.iter().position(|id| *id == msg_id) {
+                        Some(at) => {
+                            duplicates.swap_remove(at);
+                            Status::Duplicate
+                        }
+                        None => Status::Accepted,
+                    };
+                    deliveries.push(delivery(msg_id, status));
+                }
+            }
+            Err(err) => {
+                eprintln!("Error ingressing mail {}", err);
+                let failed = Status::Failed(ErrorCode::Storage);
+                deliveries.extend(msg_ids.into_iter().map(|msg_id| delivery(msg_id, failed)));
+            }
+        }
+        deliveries
+    }
 
-    fn process(&self, payload: Vec<u8>) -> Result<Action> {
-        let payload = from_bytes::<'_, Mail>(&payload)?;
-        match payload {
-            m @ Mail::Bulk(_) if m.is_command() => Self::process_cmd(m),
-            m @ Mail::Bulk(_) => ingress(m).map(|_| Continue),
-            _ => Ok(Continue),
+    //Response to a tail marked frame - along with the action it results in. Mails are
+    //acknowledged with the ids of their messages once persisted - none if persisting failed.
+    //Messages ingested before are acknowledged as duplicates. Acks are encoded with the
+    //codec the mail came in with.
+    fn respond(frame: Vec<u8>) -> Result<(Vec<u8>, Action)> {
+        let (codec, version, payload) = codec::unframe(&frame);
+        match envelope::decode_mail(codec, version, payload)? {
+            m @ Mail::Bulk(_) if m.is_command() => match Self::process_cmd(m)? {
+                Echo(text) => Ok((text.into_bytes(), Continue)),
+                Ask(msg, millis) => Ok((Self::await_reply(*msg, millis, codec), Continue)),
+                action => Ok((b"Ok".to_vec(), action)),
+            },
+            m @ Mail::Bulk(_) => {
+                let mut ack = Ack::default();
+                for Delivery { msg_id, status } in Self::take_in(m) {
+                    match status {
+                        Status::Accepted => ack.accepted.push(msg_id),
+                        Status::Duplicate => ack.duplicates.push(msg_id),
+                        Status::Failed(_) => (),
+                    }
+                }
+                Ok((codec::frame(codec, &ack)?, Continue))
+            }
+            _ => Ok((b"Ok".to_vec(), Continue)),
         }
     }
 }
+
+//How long connections may take to deliver the rest of a frame and how long they may stay
+//idle - None meaning indefinitely
+#[derive(Clone, Copy)]
+struct Timeouts {
+    read: Option<Duration>,
+    idle: Option<Duration>,
+}
+
+fn seconds(secs: u64) -> Option<Duration> {
+    (secs > 0).then(|| Duration::from_secs(secs))
+}
+
+//Serves accepted connections one after another
+struct Worker {
+    receiver: Arc<Mutex<Receiver<TcpStream>>>,
+    active: Arc<AtomicUsize>,
+    shutdown: Arc<AtomicBool>,
+    local_addr: SocketAddr,
+    timeouts: Timeouts,
+}
+
+impl Worker {
+    //Worker exits once the listener is gone
+    fn start(self) {
+        thread::spawn(move || loop {
+            let stream = match self.receiver.lock().recv() {
+                Ok(stream) => stream,
+                Err(_) => break,
+            };
+            let served = MessageListener::serve(stream, self.timeouts);
+            self.active.fetch_sub(1, Ordering::AcqRel);
+            match served {
+                Err(serving_error) => eprintln!("Error serving client {}", serving_error),
+                Ok(Shutdown) => {
+                    self.shutdown.store(true, Ordering::Release);
+                    //Wake up the accepting loop
+                    let _ignore = TcpStream::connect(self.local_addr);
+                }
+                Ok(_) => (),
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+    use std::time::Instant;
+
+    //Address of a listener serving connections the given way - without an actor system
+    //behind it, which hellos and idling do not call for
+    fn listening(max_connections: usize, read: u64, idle: u64) -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let timeouts = Timeouts {
+            read: seconds(read),
+            idle: seconds(idle),
+        };
+        thread::spawn(move || MessageListener::accept(listener, max_connections, timeouts));
+        addr
+    }
+
+    fn greet(addr: SocketAddr) -> Result<(BufReader<TcpStream>, u8)> {
+        let stream = TcpStream::connect(addr)?;
+        stream.set_read_timeout(Some(Duration::from_secs(5)))?;
+        let mut reader = BufReader::new(stream.try_clone()?);
+        let mut writer = BufWriter::new(stream);
+        let version = protocol::negotiate(&mut reader, &mut writer)?;
+        Ok((reader, version))
+    }
+
+    //Whether the listener closed the connection - within the time the client waits
+    fn closed(reader: &mut BufReader<TcpStream>) -> bool {
+        matches!(reader.read(&mut [0; 1]), Ok(0))
+    }
+
+    #[test]
+    fn listener_connection_limit_test_1() {
+        let addr = listening(1, 0, 0);
+        let (_served, version) = greet(addr).unwrap();
+        assert_eq!(version, PROTOCOL_VERSION);
+        let refused = greet(addr).unwrap_err();
+        assert!(refused.to_string().contains("Busy"), "{}", refused);
+    }
+
+    #[test]
+    fn listener_idle_timeout_test_1() {
+        let addr = listening(1, 0, 1);
+        let (mut idle, _) = greet(addr).unwrap();
+        let started = Instant::now();
+        assert!(closed(&mut idle));
+        assert!(started.elapsed() < Duration::from_secs(3));
+        //The worker is free to serve another connection
+        assert!(greet(addr).is_ok());
+    }
+
+    #[test]
+    fn listener_read_timeout_test_1() {
+        let addr = listening(1, 1, 60);
+        let mut stalled = TcpStream::connect(addr).unwrap();
+        stalled
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        let hello = Frame::hello(PROTOCOL_VERSION).to_bytes();
+        stalled.write_all(&hello[..hello.len() / 2]).unwrap();
+        let started = Instant::now();
+        assert!(matches!(stalled.read(&mut [0; 1]), Ok(0)));
+        assert!(started.elapsed() < Duration::from_secs(3));
+    }
+}
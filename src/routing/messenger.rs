@@ -2,13 +2,25 @@
 //!listener binary in case of local connection failure.
 //!
 //!Uses a tcp client which serializes collection of messages. Each collection of messages
-//!ends with byte marks <https://github.com/ratulb/byte_marks>. This is how the receiving
-//!end reconstruct messages back. Message serialization and deserialization is based on
+//!goes out in a versioned frame of the wire protocol - or ends with byte marks
+//!<https://github.com/ratulb/byte_marks> when talking to listeners that predate versioned
+//!frames. Message serialization and deserialization is based on
 //![bincode] <https://github.com/bincode-org/bincode> library.
+//!
+//!Connections to each destination host are pooled and kept open. Batches of messages are
+//!pipelined over them - each one acknowledged by the receiving end with the outcome of its
+//!messages. Dropped connections are re-established with backoff.
 
+use crate::catalog;
 use crate::common::config::Config;
 use crate::routing::messenger::client::Client;
-use crate::{Action, Addr, Error::MsgSendError, Mail, Msg, Result};
+use crate::routing::messenger::connections::CONNECTIONS;
+use crate::routing::protocol::{self, Delivery, Frame, Status};
+use crate::{
+    Action, Addr,
+    Error::{InvalidData, MsgSendError, Timeout},
+    Mail, Msg, Result,
+};
 use std::collections::HashMap;
 use std::io::ErrorKind::ConnectionRefused;
 use std::net::SocketAddr;
@@ -16,6 +28,9 @@ use std::process::Command;
 use std::thread;
 use std::time::Duration;
 
+///How long [ask!](crate::ask) waits for a reply when no timeout is given
+pub const ASK_TIMEOUT: Duration = Duration::from_secs(5);
+
 ///The client face of actor system. Sends out messages with text or binary(v8) as payload.
 pub struct Messenger;
 
@@ -81,54 +96,168 @@ impl Messenger {
     ///copies of themselves.
     ///
     ///Actors are allowed to panic a set number of times(currently 3).
-    ///                                  
-
-    pub fn send(mut mails: HashMap<&Addr, Vec<Msg>>) -> Result<()> {
-        mails.iter_mut().for_each(|(addr, msgs)| {
+    pub fn send(mails: HashMap<&Addr, Vec<Msg>>) -> Result<()> {
+        //Commands are pipelined over the connection of their host - mail goes through the
+        //outbox, to be sent again until acknowledged
+        let mut commands: HashMap<SocketAddr, Vec<Vec<Msg>>> = HashMap::new();
+        let mut outbound = Vec::new();
+        for (addr, mut msgs) in mails {
             msgs.iter_mut().for_each(|msg| {
                 msg.set_recipient_addr(addr);
             });
-            if let Some(host_addr) = addr.get_socket_addr() {
-                match Client::connect(host_addr) {
-                    Ok(mut client) => {
-                        if let Err(err) = client.send(msgs) {
-                            println!("{}", err);
-                        } else {
-                            println!("Messages sent to host {}", host_addr);
+            match addr.get_socket_addr() {
+                Some(host_addr) if msgs.first().is_some_and(Msg::is_command) => {
+                    commands.entry(host_addr).or_default().push(msgs)
+                }
+                Some(_) => outbound.extend(msgs),
+                None => (),
+            }
+        }
+        if !outbound.is_empty() {
+            catalog::send_out_now(outbound)?;
+        }
+        for (host_addr, mut batches) in commands {
+            match CONNECTIONS.send(host_addr, &mut batches) {
+                Ok(acks) => {
+                    //Echoes come back as text
+                    for ack in acks {
+                        match protocol::outcome(ack) {
+                            Ok(text) if text.is_empty() => println!("Ok"),
+                            Ok(text) => println!("{}", String::from_utf8_lossy(&text)),
+                            Err(failure) => eprintln!("Command failed - {}", failure),
                         }
                     }
-                    Err(err) => {
-                        eprintln!("Host: {} {}", host_addr, err);
-                        let _rs = Self::handle_err(msgs, host_addr, err);
-                    }
+                    println!("Messages sent to host {}", host_addr);
+                }
+                Err(err) => {
+                    eprintln!("Host: {} {}", host_addr, err);
+                    let _rs = Self::handle_err(&mut batches.concat(), host_addr, err);
                 }
             }
-        });
+        }
         Ok(())
     }
 
+    ///Sends a message to an actor and blocks until the actor's reply arrives or the timeout
+    ///elapses. The reply is whatever the actor returns from `receive` for the message - it
+    ///is handed back to the caller instead of being routed further. Works alike for local
+    ///and remote actors - the message goes through the listener fronting the actor.
+    ///
+    ///Example
+    ///
+    ///```
+    ///use arrows::{Addr, Msg};
+    ///use arrows::routing::messenger::Messenger;
+    ///use std::time::Duration;
+    ///
+    ///let m = Msg::from_text("Are you there?");
+    ///let reply = Messenger::ask(&Addr::new("another_actor"), m, Duration::from_secs(2));
+    ///
+    ///```
+    ///Fails with [Timeout](crate::Error::Timeout) if the reply does not arrive in time. Asks
+    ///go over connections of their own - a pending reply does not hold up the messages sent
+    ///to the same host.
+    pub fn ask(addr: &Addr, mut msg: Msg, timeout: Duration) -> Result<Mail> {
+        msg.set_recipient_addr(addr);
+        let host_addr = addr.get_socket_addr().ok_or(InvalidData)?;
+        let mut client = match Client::connect(host_addr) {
+            Ok(client) => client,
+            Err(err) if err.kind() == ConnectionRefused && Addr::is_ip_local(host_addr.ip()) => {
+                Self::bootup()?;
+                thread::sleep(Duration::from_millis(100));
+                Client::connect(host_addr).map_err(MsgSendError)?
+            }
+            Err(err) => {
+                eprintln!("Host: {} {}", host_addr, err);
+                return Err(MsgSendError(err));
+            }
+        };
+        match client.ask(Msg::ask(msg, timeout), timeout) {
+            Ok(Some(reply)) => Ok(reply),
+            Ok(None) => Err(Timeout),
+            Err(err) => Err(MsgSendError(err)),
+        }
+    }
+
+    //Send out messages grouped by destination host. Failure to deliver to any of the hosts
+    //is reported back.
     pub(crate) fn mail(mail: Mail) -> Result<()> {
-        Self::group_by(mail.take_all())
-            .into_iter()
-            .for_each(|(host_addr, mut msgs)| {
-                let _rs = match Client::connect(host_addr) {
-                    Ok(mut client) => match client.send(&mut msgs) {
-                        Ok(ok) => {
-                            println!("Messages sent to {}", host_addr);
-                            Ok(ok)
-                        }
-                        Err(err) => {
-                            eprintln!("{}", err);
-                            Err(err.into())
+        let mut outcome = Ok(());
+        for (host_addr, msgs) in Self::group_by(mail.take_all()) {
+            let mut batches = vec![msgs];
+            let sent = match CONNECTIONS.send(host_addr, &mut batches) {
+                Ok(_) => {
+                    println!("Messages sent to {}", host_addr);
+                    Ok(())
+                }
+                Err(err) => {
+                    eprintln!("Host: {} {}", host_addr, err);
+                    Self::handle_err(&mut batches.concat(), host_addr, err)
+                }
+            };
+            if sent.is_err() {
+                outcome = sent;
+            }
+        }
+        outcome
+    }
+
+    //Deliver messages to the listeners fronting their recipients - returning the ids of the
+    //messages each host acknowledged. Failures are reported, the messages not acknowledged
+    //are up to the caller to send again.
+    pub(crate) fn deliver(msgs: Vec<Msg>) -> Vec<(SocketAddr, Vec<u64>)> {
+        let mut acked = Vec::new();
+        for (host_addr, msgs) in Self::group_by(msgs) {
+            let mut batches = [msgs];
+            match CONNECTIONS.send(host_addr, &mut batches) {
+                Ok(acks) => {
+                    let msg_ids = batches
+                        .iter()
+                        .zip(&acks)
+                        .flat_map(|(msgs, ack)| Self::acked_ids(ack, msgs))
+                        .collect();
+                    acked.push((host_addr, msg_ids));
+                }
+                Err(err) => {
+                    eprintln!("Host: {} {}", host_addr, err);
+                    if err.kind() == ConnectionRefused && Addr::is_ip_local(host_addr.ip()) {
+                        if let Err(err) = Self::bootup() {
+                            eprintln!("Bootup error {:?}", err);
                         }
-                    },
-                    Err(err) => {
-                        eprintln!("Host: {} {}", host_addr, err);
-                        Self::handle_err(&mut msgs, host_addr, err)
                     }
-                };
-            });
-        Ok(())
+                }
+            }
+        }
+        acked
+    }
+
+    //Ids of the messages an ack acknowledges - duplicates included. Messages that failed
+    //are left out - none if the mail was refused or the ack can not be made sense of.
+    fn acked_ids(ack: &Frame, msgs: &[Msg]) -> Vec<u64> {
+        let msg_ids: Vec<_> = msgs.iter().map(|msg| *msg.get_id()).collect();
+        let deliveries = match protocol::deliveries(ack, &msg_ids) {
+            Ok(deliveries) => deliveries,
+            Err(failure) => {
+                eprintln!("Mail not taken - {}", failure);
+                return Vec::new();
+            }
+        };
+        let mut acked = Vec::with_capacity(deliveries.len());
+        let mut duplicates = 0;
+        for Delivery { msg_id, status } in deliveries {
+            match status {
+                Status::Accepted => acked.push(msg_id),
+                Status::Duplicate => {
+                    duplicates += 1;
+                    acked.push(msg_id);
+                }
+                Status::Failed(code) => eprintln!("Message {} failed - {:?}", msg_id, code),
+            }
+        }
+        if duplicates > 0 {
+            println!("{} messages had been received before", duplicates);
+        }
+        acked
     }
 
     fn group_by(msgs: Vec<Msg>) -> HashMap<SocketAddr, Vec<Msg>> {
@@ -175,7 +304,6 @@ impl Messenger {
 
     ///Boots up the msg listener(`MessageListener`) binary. The resident binary path is
     ///configurable via the environment variable 'resident_listener'.
-
     pub fn bootup() -> Result<()> {
         let mut resident_listener = std::env::current_dir()?;
         resident_listener.push(Config::get_shared().resident_listener());
@@ -192,47 +320,357 @@ impl Messenger {
 
 pub(super) mod client {
 
-    use crate::{option_of_bytes, Mail, Msg};
-    use byte_marks::ByteMarker;
+    use crate::common::{codec, envelope};
+    use crate::routing::protocol::{self, Frame, FrameType, LEGACY_PROTOCOL};
+    use crate::{Config, Mail, Msg};
+    use byte_marks::TAIL;
+
+    use std::io::{BufReader, BufWriter, Error, ErrorKind, Result, Write};
+    use std::net::{SocketAddr, TcpStream};
+    use std::time::Duration;
 
-    use std::io::{BufReader, BufWriter, Error, ErrorKind, Read, Result, Write};
-    use std::net::{TcpStream, ToSocketAddrs};
+    //Extra time the client allows over the ask timeout - for the listener to respond
+    const ASK_GRACE: Duration = Duration::from_secs(1);
+    const CONNECT_TIMEOUT: Duration = Duration::from_secs(3);
 
-    pub struct Client<'a> {
+    pub struct Client {
         reader: BufReader<TcpStream>,
         writer: BufWriter<TcpStream>,
-        marker: ByteMarker<'a>,
+        version: u8,
     }
 
-    impl Client<'_> {
-        pub fn connect<A: ToSocketAddrs>(addr: A) -> Result<Self> {
-            let stream = TcpStream::connect(addr)?;
-            let write_half = stream.try_clone()?;
+    impl Client {
+        pub fn connect(host_addr: SocketAddr) -> Result<Self> {
+            let (reader, writer, version) = open(host_addr)?;
             Ok(Client {
-                reader: BufReader::new(stream),
-                writer: BufWriter::new(write_half),
-                marker: ByteMarker::with_defaults(),
+                reader,
+                writer,
+                version,
             })
         }
 
-        pub fn send(&mut self, msgs: &mut Vec<Msg>) -> Result<()> {
-            let bulk = Mail::Bulk(std::mem::take(msgs));
-            match option_of_bytes(&bulk) {
-                Some(ref mut bytes) => {
-                    self.marker.mark_tail(bytes);
-                    self.writer.write_all(bytes)?;
-                    self.writer.flush()?;
-                    let mut buf = vec![0; 256];
-                    let len = self.reader.read(&mut buf)?;
-                    println!("{}", String::from_utf8_lossy(&buf[..len]));
-                    Ok(())
+        //Send an Ask command and read back the reply
+        pub fn ask(&mut self, ask: Msg, timeout: Duration) -> Result<Option<Mail>> {
+            let (kind, payload) = encode(&mut vec![ask])?;
+            self.writer
+                .write_all(&wrap(self.version, kind, 0, payload))?;
+            self.writer.flush()?;
+            self.reader
+                .get_ref()
+                .set_read_timeout(Some(timeout + ASK_GRACE))?;
+            let reply = match self.version {
+                LEGACY_PROTOCOL => protocol::read_legacy(&mut self.reader)?,
+                _ => match Frame::read(&mut self.reader)? {
+                    Some(response) => Some(
+                        protocol::outcome(response)
+                            .map_err(|failure| Error::other(failure.to_string()))?,
+                    ),
+                    None => None,
+                },
+            };
+            match reply {
+                Some(bytes) => {
+                    let (codec, version, payload) = codec::unframe(&bytes);
+                    Ok(envelope::decode_reply(codec, version, payload)?)
+                }
+                None => Err(Error::new(ErrorKind::UnexpectedEof, "No reply received")),
+            }
+        }
+    }
+
+    //Connect to a listener and agree on the protocol version to talk in. Listeners predating
+    //versioned frames answer the hello as if it were mail - 0.1.16 ones hanging up right
+    //after. Mail to them goes over a connection of its own.
+    pub(super) fn open(
+        host_addr: SocketAddr,
+    ) -> Result<(BufReader<TcpStream>, BufWriter<TcpStream>, u8)> {
+        let connect = || -> Result<(BufReader<TcpStream>, BufWriter<TcpStream>)> {
+            let stream = TcpStream::connect_timeout(&host_addr, CONNECT_TIMEOUT)?;
+            stream.set_nodelay(true)?;
+            Ok((BufReader::new(stream.try_clone()?), BufWriter::new(stream)))
+        };
+        let (mut reader, mut writer) = connect()?;
+        reader.get_ref().set_read_timeout(Some(CONNECT_TIMEOUT))?;
+        let negotiated = protocol::negotiate(&mut reader, &mut writer);
+        reader.get_ref().set_read_timeout(None)?;
+        match negotiated? {
+            LEGACY_PROTOCOL => {
+                let (reader, writer) = connect()?;
+                Ok((reader, writer, LEGACY_PROTOCOL))
+            }
+            version => Ok((reader, writer, version)),
+        }
+    }
+
+    //Frame type and payload of a batch of messages - the batch is left as it was
+    pub(super) fn encode(msgs: &mut Vec<Msg>) -> Result<(FrameType, Vec<u8>)> {
+        let kind = match msgs.first() {
+            Some(msg) if msg.is_command() => FrameType::Command,
+            _ => FrameType::Data,
+        };
+        let bulk = Mail::Bulk(std::mem::take(msgs));
+        let codec = Config::get_shared().codec();
+        let framed = codec::frame(codec, &bulk);
+        *msgs = bulk.take_all();
+        match framed {
+            Ok(payload) => Ok((kind, payload)),
+            Err(_) => {
+                eprintln!("Error converting message to bytes");
+                Err(Error::other("Error converting message to bytes"))
+            }
+        }
+    }
+
+    //Bytes going out on a connection talking the given protocol version
+    pub(super) fn wrap(
+        version: u8,
+        kind: FrameType,
+        correlation: u64,
+        payload: Vec<u8>,
+    ) -> Vec<u8> {
+        match version {
+            LEGACY_PROTOCOL => {
+                let mut bytes = payload;
+                bytes.extend(TAIL.as_bytes());
+                bytes
+            }
+            _ => Frame {
+                version,
+                ..Frame::new(kind, correlation, payload)
+            }
+            .to_bytes(),
+        }
+    }
+}
+
+pub(super) mod connections {
+    //Long-lived connections to listeners - one per destination host. Batches are written
+    //back to back without waiting for the acks of the ones before. A reader thread per
+    //connection hands the acks over to the senders waiting on them - matched by correlation
+    //id, or in the order the batches were written if the listener predates versioned
+    //frames.
+
+    use super::client;
+    use crate::routing::protocol::{self, Frame, FrameType, LEGACY_PROTOCOL};
+    use crate::Msg;
+    use lazy_static::lazy_static;
+    use parking_lot::Mutex;
+
+    use std::collections::{HashMap, VecDeque};
+    use std::io::{BufWriter, Error, ErrorKind, Result, Write};
+    use std::net::{Shutdown, SocketAddr, TcpStream};
+    use std::sync::mpsc::{channel, Receiver, RecvTimeoutError, Sender};
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::Duration;
+
+    const ACK_TIMEOUT: Duration = Duration::from_secs(10);
+    const RECONNECT_ATTEMPTS: u32 = 3;
+    const INITIAL_BACKOFF: Duration = Duration::from_millis(100);
+    const MAX_BACKOFF: Duration = Duration::from_secs(2);
+
+    lazy_static! {
+        pub(crate) static ref CONNECTIONS: Connections = Connections::default();
+    }
+
+    type Ack = Result<Frame>;
+
+    //Senders waiting on acks - along with the correlation ids of their batches, in the order
+    //the batches were written
+    struct Pending {
+        open: bool,
+        waiting: VecDeque<(u64, Sender<Ack>)>,
+    }
+
+    impl Pending {
+        fn waiting_on(&mut self, ack: &Frame) -> Option<Sender<Ack>> {
+            match ack.version {
+                LEGACY_PROTOCOL => self.waiting.pop_front(),
+                _ => self
+                    .waiting
+                    .iter()
+                    .position(|(correlation, _)| *correlation == ack.correlation)
+                    .and_then(|at| self.waiting.remove(at)),
+            }
+            .map(|(_, sender)| sender)
+        }
+    }
+
+    struct Connection {
+        version: u8,
+        //Correlation id of the next batch - along with the writing half of the connection
+        writer: Mutex<(u64, BufWriter<TcpStream>)>,
+        pending: Arc<Mutex<Pending>>,
+    }
+
+    impl Connection {
+        fn open(host_addr: SocketAddr) -> Result<Self> {
+            let (mut reader, writer, version) = client::open(host_addr)?;
+            let pending = Arc::new(Mutex::new(Pending {
+                open: true,
+                waiting: VecDeque::new(),
+            }));
+            let acks = Arc::clone(&pending);
+            thread::spawn(move || {
+                let closed = loop {
+                    let ack = match version {
+                        LEGACY_PROTOCOL => {
+                            protocol::read_legacy(&mut reader).map(|ack| ack.map(Frame::legacy))
+                        }
+                        _ => Frame::read(&mut reader),
+                    };
+                    match ack {
+                        Ok(Some(ack)) => {
+                            if let Some(sender) = acks.lock().waiting_on(&ack) {
+                                let _ignore = sender.send(Ok(ack));
+                            }
+                        }
+                        Ok(None) => {
+                            break Error::new(ErrorKind::UnexpectedEof, "Connection closed")
+                        }
+                        Err(err) => break err,
+                    }
+                };
+                let mut acks = acks.lock();
+                acks.open = false;
+                for (_, sender) in acks.waiting.drain(..) {
+                    let _ignore = sender.send(Err(Error::new(closed.kind(), closed.to_string())));
                 }
-                None => {
-                    eprintln!("Error converting message to bytes");
-                    Err(Error::new(
-                        ErrorKind::Other,
-                        "Error converting message to bytes",
-                    ))
+            });
+            Ok(Self {
+                version,
+                writer: Mutex::new((0, writer)),
+                pending,
+            })
+        }
+
+        fn is_open(&self) -> bool {
+            self.pending.lock().open
+        }
+
+        //Write a batch - returning where its ack would arrive
+        fn write(&self, kind: FrameType, payload: &[u8]) -> Result<Receiver<Ack>> {
+            let (sender, ack) = channel();
+            let mut writer = self.writer.lock();
+            let (next, writer) = &mut *writer;
+            let correlation = *next;
+            *next += 1;
+            {
+                let mut pending = self.pending.lock();
+                if !pending.open {
+                    return Err(Error::new(ErrorKind::NotConnected, "Connection closed"));
+                }
+                pending.waiting.push_back((correlation, sender));
+            }
+            let bytes = client::wrap(self.version, kind, correlation, payload.to_vec());
+            if let Err(err) = writer.write_all(&bytes).and_then(|_| writer.flush()) {
+                //Lets the reader fail the acks still awaited
+                let _ignore = writer.get_ref().shutdown(Shutdown::Both);
+                return Err(err);
+            }
+            Ok(ack)
+        }
+    }
+
+    fn await_ack(ack: Receiver<Ack>) -> Ack {
+        match ack.recv_timeout(ACK_TIMEOUT) {
+            Ok(ack) => ack,
+            Err(RecvTimeoutError::Timeout) => {
+                Err(Error::new(ErrorKind::TimedOut, "No ack received"))
+            }
+            Err(RecvTimeoutError::Disconnected) => {
+                Err(Error::new(ErrorKind::NotConnected, "Connection closed"))
+            }
+        }
+    }
+
+    //Whether the connection went away from under a send
+    fn dropped(err: &Error) -> bool {
+        matches!(
+            err.kind(),
+            ErrorKind::BrokenPipe
+                | ErrorKind::ConnectionReset
+                | ErrorKind::ConnectionAborted
+                | ErrorKind::NotConnected
+                | ErrorKind::UnexpectedEof
+        )
+    }
+
+    #[derive(Default)]
+    pub(crate) struct Connections {
+        connections: Mutex<HashMap<SocketAddr, Arc<Connection>>>,
+    }
+
+    impl Connections {
+        //Pipeline batches of messages to a host - returning their acks. Batches are sent
+        //again over a new connection if the connection drops before they are acked.
+        //Re-connection is attempted a few times - backing off in between.
+        pub(crate) fn send(
+            &self,
+            host_addr: SocketAddr,
+            batches: &mut [Vec<Msg>],
+        ) -> Result<Vec<Frame>> {
+            let frames = batches
+                .iter_mut()
+                .map(client::encode)
+                .collect::<Result<Vec<_>>>()?;
+            let mut acks = Vec::with_capacity(frames.len());
+            let mut backoff = INITIAL_BACKOFF;
+            let mut attempt = 0;
+            loop {
+                match self.pipeline(host_addr, &frames[acks.len()..], &mut acks) {
+                    Ok(()) => return Ok(acks),
+                    //Once dropped, a host that is still coming back up gets retried too
+                    Err(err)
+                        if attempt < RECONNECT_ATTEMPTS
+                            && (dropped(&err)
+                                || attempt > 0 && err.kind() == ErrorKind::ConnectionRefused) =>
+                    {
+                        eprintln!("Host: {} {} - reconnecting", host_addr, err);
+                        thread::sleep(backoff);
+                        backoff = (backoff * 2).min(MAX_BACKOFF);
+                        attempt += 1;
+                    }
+                    Err(err) => return Err(err),
+                }
+            }
+        }
+
+        fn pipeline(
+            &self,
+            host_addr: SocketAddr,
+            frames: &[(FrameType, Vec<u8>)],
+            acks: &mut Vec<Frame>,
+        ) -> Result<()> {
+            let connection = self.connection(host_addr)?;
+            let mut awaited = Vec::with_capacity(frames.len());
+            for (kind, payload) in frames {
+                match connection.write(*kind, payload) {
+                    Ok(ack) => awaited.push(ack),
+                    Err(err) if awaited.is_empty() => return Err(err),
+                    //Collect the acks of the frames written so far
+                    Err(_) => break,
+                }
+            }
+            let written = awaited.len();
+            for ack in awaited {
+                acks.push(await_ack(ack)?);
+            }
+            match written == frames.len() {
+                true => Ok(()),
+                false => Err(Error::new(ErrorKind::NotConnected, "Connection closed")),
+            }
+        }
+
+        //Open connection to a host - a new one if there is none or it has been closed
+        fn connection(&self, host_addr: SocketAddr) -> Result<Arc<Connection>> {
+            let mut connections = self.connections.lock();
+            match connections.get(&host_addr) {
+                Some(connection) if connection.is_open() => Ok(Arc::clone(connection)),
+                _ => {
+                    let connection = Arc::new(Connection::open(host_addr)?);
+                    connections.insert(host_addr, Arc::clone(&connection));
+                    Ok(connection)
                 }
             }
         }
@@ -1,67 +1,67 @@
 //! This module handles method invocation on actors. Maintains a number of `Delegates`
 //! equal to the number of cpus in the running nodes.
 //!
-//!The `Router` is responsible for handing out messages to the delegates which shares a
-//!`Receiver` queue.
+//!The `Router` is responsible for handing out messages to the delegates. Each delegate has a
+//!queue of its own - messages to an actor always go to the same delegate. Hence an actor
+//!processes its messages one at a time and in order, while different actors run in
+//!parallel on different delegates.
 
 pub mod listener;
 pub mod messenger;
+pub(crate) mod protocol;
 use crate::catalog::{self};
 use crate::RichMail;
-use parking_lot::Mutex;
 
 use std::sync::mpsc::{channel, Receiver, Sender};
-use std::sync::Arc;
 use std::thread::{self, JoinHandle};
 
 pub(crate) struct Delegate {
-    receiver: Option<Arc<Mutex<Receiver<RichMail>>>>,
+    receiver: Option<Receiver<RichMail>>,
 }
 
 impl Delegate {
-    pub(crate) fn new(receiver: Arc<Mutex<Receiver<RichMail>>>) -> Self {
+    pub(crate) fn new(receiver: Receiver<RichMail>) -> Self {
         Self {
             receiver: Some(receiver),
         }
     }
+    //Delegate exits once the router is gone
     pub(crate) fn start(&mut self) -> JoinHandle<()> {
         let receiver = self.receiver.take().expect("Receiver");
-        thread::spawn(move || loop {
-            let receiver = receiver.lock();
-            match receiver.recv() {
-                Ok(rich_mail) => {
-                    catalog::handle_invocation(rich_mail);
-                }
-                Err(err) => {
-                    eprintln!("Error receiving msg {}", err);
-                    continue;
-                }
+        thread::spawn(move || {
+            while let Ok(rich_mail) = receiver.recv() {
+                catalog::handle_invocation(rich_mail);
             }
         })
     }
 }
 
 pub(crate) struct Router {
-    sender: Sender<RichMail>,
+    senders: Vec<Sender<RichMail>>,
     delegates: Vec<JoinHandle<()>>,
 }
 
 impl Router {
     pub(crate) fn new(count: usize) -> Self {
         assert!(count > 0);
-        let (sender, receiver) = channel();
+        let mut senders = Vec::with_capacity(count);
         let mut delegates = Vec::with_capacity(count);
-        let receiver = Arc::new(Mutex::new(receiver));
         for i in 0..count {
+            let (sender, receiver) = channel();
             println!("Delegate started = {}", i);
-            delegates.push(Delegate::new(Arc::clone(&receiver)).start());
+            delegates.push(Delegate::new(receiver).start());
+            senders.push(sender);
         }
-        Self { sender, delegates }
+        Self { senders, delegates }
     }
 
+    //Messages are sharded across the delegates by recipient actor
     pub(crate) fn route(&mut self, msgs: Vec<RichMail>) {
         for msg in msgs {
-            match self.sender.send(msg) {
+            let delegate = msg.to().map_or(0, |addr| {
+                (addr.get_id() % self.senders.len() as u64) as usize
+            });
+            match self.senders[delegate].send(msg) {
                 Ok(_) => (),
                 Err(err) => {
                     eprintln!("Router: error routing message {}", err);
@@ -72,6 +72,8 @@ impl Router {
 }
 impl Drop for Router {
     fn drop(&mut self) {
+        //Delegates stop once their queues are disconnected
+        self.senders.clear();
         for handle in std::mem::take(&mut self.delegates) {
             let _ignore = handle.join();
         }
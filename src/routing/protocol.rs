@@ -0,0 +1,426 @@
+//! # Wire protocol
+//!Listeners and their clients talk in versioned frames. Each frame is headed by the version
+//!of the protocol, the type of the frame, a correlation id and the length of its payload:
+//!
+//!| Mark | Version | Type | Correlation id | Payload length | Payload |
+//!|------|---------|------|----------------|----------------|---------|
+//!| 1 byte | 1 byte | 1 byte | 8 bytes | 4 bytes | as long as given |
+//!
+//!Numbers are big endian. Data and command frames carry mail - as framed by the
+//![codec](crate::Codec) it was encoded with. Each of them is answered with a frame bearing
+//!its correlation id - an ack, a nack if the frame was refused or an error if it could not
+//!be processed. Acks of data frames say for each message whether it was accepted, rejected
+//!as a duplicate or failed - along with an error code.
+//!
+//!Clients open connections with a hello carrying the latest protocol version they speak and
+//!listeners reply with the version to use. Hellos are tail marked so that peers predating
+//!versioned frames can make out where they end - such peers do not reply with a hello and
+//!connections to them fall back to tail marked mail. Listeners serve both kinds of frames.
+
+use crate::common::codec::{self, Bincode, Codec};
+use crate::common::envelope::Ack;
+use crate::Error;
+use byte_marks::TAIL;
+use serde::{Deserialize, Serialize};
+use std::fmt::{Display, Formatter, Result as FResult};
+use std::io::{self, BufRead, ErrorKind, Read, Write};
+
+//Heads versioned frames - tail marked frames begin with a codec frame mark or the variant
+//index of a bincode encoded `Mail`, never with this byte
+const WIRE_MARK: u8 = 0xC1;
+const HEADER_LEN: usize = 15;
+//Payloads beyond this are taken for a corrupt stream
+const MAX_PAYLOAD: usize = 64 * 1024 * 1024;
+
+///Version of the wire protocol spoken by this build
+pub(crate) const PROTOCOL_VERSION: u8 = 2;
+///Tail marked mail - spoken by peers predating versioned frames
+pub(crate) const LEGACY_PROTOCOL: u8 = 1;
+//Response of listeners predating acks - to mail and to hellos alike
+const LEGACY_OK: &[u8] = b"Ok";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum FrameType {
+    Hello = 0,
+    Data = 1,
+    Command = 2,
+    Ack = 3,
+    Nack = 4,
+    Error = 5,
+}
+
+impl FrameType {
+    fn from_u8(kind: u8) -> Option<Self> {
+        match kind {
+            0 => Some(Self::Hello),
+            1 => Some(Self::Data),
+            2 => Some(Self::Command),
+            3 => Some(Self::Ack),
+            4 => Some(Self::Nack),
+            5 => Some(Self::Error),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub(crate) struct Frame {
+    pub(crate) version: u8,
+    pub(crate) kind: FrameType,
+    pub(crate) correlation: u64,
+    pub(crate) payload: Vec<u8>,
+}
+
+impl Frame {
+    pub(crate) fn new(kind: FrameType, correlation: u64, payload: Vec<u8>) -> Self {
+        Self {
+            version: PROTOCOL_VERSION,
+            kind,
+            correlation,
+            payload,
+        }
+    }
+
+    //Hello of a client - or the reply of a listener with the version agreed upon
+    pub(crate) fn hello(version: u8) -> Self {
+        Self {
+            version,
+            ..Self::new(FrameType::Hello, 0, Vec::new())
+        }
+    }
+
+    //Response of a listener that predates versioned frames
+    pub(crate) fn legacy(payload: Vec<u8>) -> Self {
+        Self {
+            version: LEGACY_PROTOCOL,
+            ..Self::new(FrameType::Ack, 0, payload)
+        }
+    }
+
+    //Frame refusing or failing the one with the given correlation id
+    pub(crate) fn failure(kind: FrameType, correlation: u64, failure: &Failure) -> Self {
+        let payload = codec::frame(Bincode::ID, failure).unwrap_or_default();
+        Self::new(kind, correlation, payload)
+    }
+
+    pub(crate) fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(HEADER_LEN + self.payload.len());
+        bytes.extend([WIRE_MARK, self.version, self.kind as u8]);
+        bytes.extend(self.correlation.to_be_bytes());
+        bytes.extend((self.payload.len() as u32).to_be_bytes());
+        bytes.extend(&self.payload);
+        //Lets peers predating versioned frames make out the end of a hello
+        if self.kind == FrameType::Hello {
+            bytes.extend(TAIL.as_bytes());
+        }
+        bytes
+    }
+
+    //Read the next frame off a stream - None once the stream ends
+    pub(crate) fn read<R: Read>(reader: &mut R) -> io::Result<Option<Self>> {
+        let mut header = [0; HEADER_LEN];
+        if reader.read(&mut header[..1])? == 0 {
+            return Ok(None);
+        }
+        reader.read_exact(&mut header[1..])?;
+        let kind = FrameType::from_u8(header[2]);
+        let len = u32::from_be_bytes([header[11], header[12], header[13], header[14]]) as usize;
+        let kind = match kind {
+            Some(kind) if header[0] == WIRE_MARK && len <= MAX_PAYLOAD => kind,
+            _ => return Err(io::Error::new(ErrorKind::InvalidData, "Malformed frame")),
+        };
+        let mut payload = vec![0; len];
+        reader.read_exact(&mut payload)?;
+        if kind == FrameType::Hello {
+            let mut tail = vec![0; TAIL.len()];
+            reader.read_exact(&mut tail)?;
+            if tail != TAIL.as_bytes() {
+                return Err(io::Error::new(ErrorKind::InvalidData, "Malformed hello"));
+            }
+        }
+        let mut correlation = [0; 8];
+        correlation.copy_from_slice(&header[3..11]);
+        Ok(Some(Self {
+            version: header[1],
+            kind,
+            correlation: u64::from_be_bytes(correlation),
+            payload,
+        }))
+    }
+}
+
+//Whether the bytes buffered off a stream begin a versioned frame
+pub(crate) fn is_versioned(bytes: &[u8]) -> bool {
+    bytes.first() == Some(&WIRE_MARK)
+}
+
+//Whether frames of the given version can be made sense of
+pub(crate) fn is_supported(version: u8) -> bool {
+    version > LEGACY_PROTOCOL && version <= PROTOCOL_VERSION
+}
+
+//Greet a listener - returning the protocol version it agreed to. Listeners predating
+//versioned frames reply with something other than a hello. Connections closed or timing
+//out before a reply, refused by the listener or replied to in ways that can not be made
+//sense of fail the greeting.
+pub(crate) fn negotiate<R: BufRead, W: Write>(reader: &mut R, writer: &mut W) -> io::Result<u8> {
+    writer.write_all(&Frame::hello(PROTOCOL_VERSION).to_bytes())?;
+    writer.flush()?;
+    if reader.fill_buf()?.is_empty() {
+        return Err(io::Error::new(
+            ErrorKind::UnexpectedEof,
+            "No hello received",
+        ));
+    }
+    if !is_versioned(reader.fill_buf()?) {
+        let _reply = read_legacy(reader)?;
+        return Ok(LEGACY_PROTOCOL);
+    }
+    match Frame::read(reader)? {
+        Some(Frame {
+            version,
+            kind: FrameType::Hello,
+            ..
+        }) if is_supported(version) => Ok(version.min(PROTOCOL_VERSION)),
+        Some(refusal) if matches!(refusal.kind, FrameType::Nack | FrameType::Error) => {
+            Err(io::Error::other(failure(&refusal).to_string()))
+        }
+        _ => Err(io::Error::new(
+            ErrorKind::InvalidData,
+            "Unexpected reply to hello",
+        )),
+    }
+}
+
+//Next response of a listener predating versioned frames - None once the stream ends.
+//Listeners of 0.1.16 reply with a bare "Ok" and hang up without tail marking it.
+pub(crate) fn read_legacy<R: BufRead>(reader: &mut R) -> io::Result<Option<Vec<u8>>> {
+    let tail = TAIL.as_bytes();
+    let mut bytes = Vec::new();
+    loop {
+        if reader.read_until(tail[tail.len() - 1], &mut bytes)? == 0 {
+            return Ok((!bytes.is_empty()).then_some(bytes));
+        }
+        if bytes.ends_with(tail) {
+            bytes.truncate(bytes.len() - tail.len());
+            return Ok(Some(bytes));
+        }
+    }
+}
+
+//Why a frame or a message could not be processed
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) enum ErrorCode {
+    //Payload could not be decoded
+    Malformed = 1,
+    //Protocol or message version not supported
+    UnsupportedVersion = 2,
+    //Payload encoded with a codec not enabled in the receiving build
+    UnknownCodec = 3,
+    //Frame of a type not expected from the sender
+    UnexpectedFrame = 4,
+    //Message without a recipient
+    NoRecipient = 5,
+    //Message could not be persisted
+    Storage = 6,
+    //Anything else that went wrong processing a frame
+    Internal = 7,
+    //Listener is serving as many connections as it may
+    Busy = 8,
+}
+
+impl From<&Error> for ErrorCode {
+    fn from(err: &Error) -> Self {
+        match err {
+            Error::UnknownCodec(_) => Self::UnknownCodec,
+            Error::UnknownMsgVersion(_) => Self::UnsupportedVersion,
+            Error::Bincode(_) | Error::SerdeJson(_) | Error::InvalidData => Self::Malformed,
+            _ => Self::Internal,
+        }
+    }
+}
+
+//Payload of nacks and errors
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub(crate) struct Failure {
+    pub(crate) code: ErrorCode,
+    pub(crate) reason: String,
+}
+
+impl Failure {
+    pub(crate) fn new(code: ErrorCode, reason: impl ToString) -> Self {
+        Self {
+            code,
+            reason: reason.to_string(),
+        }
+    }
+}
+
+impl Display for Failure {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FResult {
+        write!(f, "{:?}({}): {}", self.code, self.code as u8, self.reason)
+    }
+}
+
+//What became of a message of a data frame
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) enum Status {
+    Accepted,
+    Duplicate,
+    Failed(ErrorCode),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct Delivery {
+    pub(crate) msg_id: u64,
+    pub(crate) status: Status,
+}
+
+//Outcome of each message of a data frame carrying messages of the given ids - as told by the
+//response to it. Frames refused or failed as a whole are told by their failure.
+pub(crate) fn deliveries(
+    response: &Frame,
+    msg_ids: &[u64],
+) -> std::result::Result<Vec<Delivery>, Failure> {
+    let (codec, _, payload) = codec::unframe(&response.payload);
+    let malformed = |err: Error| Failure::new(ErrorCode::Malformed, err);
+    match response.kind {
+        //Listeners of 0.1.16 take mail as a whole
+        FrameType::Ack if response.version == LEGACY_PROTOCOL && response.payload == LEGACY_OK => {
+            Ok(msg_ids
+                .iter()
+                .map(|&msg_id| Delivery {
+                    msg_id,
+                    status: Status::Accepted,
+                })
+                .collect())
+        }
+        //Later listeners predating versioned frames ack mail with the ids of the messages taken
+        FrameType::Ack if response.version == LEGACY_PROTOCOL => {
+            let ack: Ack = codec::decode(codec, payload).map_err(malformed)?;
+            let accepted = ack.accepted.into_iter().map(|msg_id| Delivery {
+                msg_id,
+                status: Status::Accepted,
+            });
+            let duplicates = ack.duplicates.into_iter().map(|msg_id| Delivery {
+                msg_id,
+                status: Status::Duplicate,
+            });
+            Ok(accepted.chain(duplicates).collect())
+        }
+        FrameType::Ack => codec::decode(codec, payload).map_err(malformed),
+        _ => Err(failure(response)),
+    }
+}
+
+//Payload of the response to a command - the echoed text or the reply to an ask. Commands
+//refused or failed are told by their failure.
+pub(crate) fn outcome(response: Frame) -> std::result::Result<Vec<u8>, Failure> {
+    match response.kind {
+        FrameType::Ack => Ok(response.payload),
+        _ => Err(failure(&response)),
+    }
+}
+
+fn failure(response: &Frame) -> Failure {
+    let (codec, _, payload) = codec::unframe(&response.payload);
+    match response.kind {
+        FrameType::Nack | FrameType::Error => codec::decode(codec, payload)
+            .unwrap_or_else(|err| Failure::new(ErrorCode::Malformed, err)),
+        kind => Failure::new(
+            ErrorCode::UnexpectedFrame,
+            format!("{:?} in response", kind),
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn protocol_frame_test_1() {
+        let frames = [
+            Frame::new(FrameType::Data, 7, b"mail".to_vec()),
+            Frame::hello(PROTOCOL_VERSION),
+            Frame::failure(
+                FrameType::Nack,
+                u64::MAX,
+                &Failure::new(ErrorCode::Malformed, "bad mail"),
+            ),
+        ];
+        let mut stream: Vec<u8> = frames.iter().flat_map(Frame::to_bytes).collect();
+        let mut truncated = Frame::new(FrameType::Ack, 8, b"ack".to_vec()).to_bytes();
+        truncated.pop();
+        stream.extend(truncated);
+        let mut reader = Cursor::new(stream);
+        for frame in &frames {
+            assert!(is_versioned(
+                &reader.get_ref()[reader.position() as usize..]
+            ));
+            assert_eq!(Frame::read(&mut reader).unwrap().as_ref(), Some(frame));
+        }
+        assert!(Frame::read(&mut reader).is_err());
+        assert!(Frame::read(&mut reader).unwrap().is_none());
+
+        let failure = deliveries(&frames[2], &[]).unwrap_err();
+        assert_eq!(
+            (failure.code, failure.reason.as_str()),
+            (ErrorCode::Malformed, "bad mail")
+        );
+        let legacy = Ack {
+            accepted: vec![1],
+            duplicates: vec![2],
+        };
+        let legacy = Frame::legacy(codec::frame(Bincode::ID, &legacy).unwrap());
+        assert_eq!(
+            deliveries(&legacy, &[1, 2, 3]).unwrap(),
+            vec![
+                Delivery {
+                    msg_id: 1,
+                    status: Status::Accepted
+                },
+                Delivery {
+                    msg_id: 2,
+                    status: Status::Duplicate
+                }
+            ]
+        );
+        //Listeners of 0.1.16 take the whole mail
+        let taken = deliveries(&Frame::legacy(b"Ok".to_vec()), &[1, 2]).unwrap();
+        assert_eq!(
+            taken
+                .iter()
+                .map(|delivery| delivery.msg_id)
+                .collect::<Vec<_>>(),
+            vec![1, 2]
+        );
+        assert!(taken
+            .iter()
+            .all(|delivery| delivery.status == Status::Accepted));
+        assert!(deliveries(&Frame::legacy(b"Ko".to_vec()), &[1]).is_err());
+    }
+
+    #[test]
+    fn protocol_negotiate_test_1() {
+        let mut sent = Vec::new();
+        let mut reply = Cursor::new(Frame::hello(PROTOCOL_VERSION).to_bytes());
+        assert_eq!(negotiate(&mut reply, &mut sent).unwrap(), PROTOCOL_VERSION);
+        assert_eq!(
+            Frame::read(&mut sent.as_slice()).unwrap(),
+            Some(Frame::hello(PROTOCOL_VERSION))
+        );
+        //Listeners predating versioned frames fail to make sense of the hello - 0.1.16 ones
+        //reply without tail marking it
+        let mut reply = Cursor::new([b"Ok", TAIL.as_bytes()].concat());
+        assert_eq!(negotiate(&mut reply, &mut sent).unwrap(), LEGACY_PROTOCOL);
+        let mut reply = Cursor::new(b"Ok".to_vec());
+        assert_eq!(negotiate(&mut reply, &mut sent).unwrap(), LEGACY_PROTOCOL);
+        let closed = negotiate(&mut Cursor::new(Vec::new()), &mut sent).unwrap_err();
+        assert_eq!(closed.kind(), ErrorKind::UnexpectedEof);
+        let mut reply = Cursor::new(Frame::new(FrameType::Ack, 0, Vec::new()).to_bytes());
+        let unexpected = negotiate(&mut reply, &mut sent).unwrap_err();
+        assert_eq!(unexpected.kind(), ErrorKind::InvalidData);
+    }
+}
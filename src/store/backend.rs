@@ -0,0 +1,175 @@
+#![allow(clippy::wrong_self_convention)]
+//! # Storage backend
+//!What the system needs from its backing store - persistence of messages, actor and pool
+//!definitions, sequence tracking, dead letters, schedules and subscriptions - along with
+//!notification of the messages that get stored. The catalog and the routing layer only
+//!ever see this trait.
+//!
+//!Sqlite is the default backend. The in-memory one keeps nothing across restarts - it
+//!serves tests and ephemeral systems. The backend is picked via the `backend` setting of
+//!the [Config](crate::Config).
+
+use crate::catalog::DeadLetter;
+use crate::constants::{OUTBOX_BACKOFF, OUTBOX_MAX_BACKOFF};
+use crate::memory::MemoryBackend;
+use crate::pubsub::Notifier;
+use crate::sqlite::SqliteBackend;
+use crate::{Addr, Backend, Config, Msg, Result, Retention, RichMail};
+
+//Actor definition as persisted in the backing store
+#[derive(Debug)]
+pub(crate) struct ActorDef {
+    pub(crate) name: String,
+    pub(crate) producer: String,
+    pub(crate) msg_seq: i64,
+    pub(crate) state: Option<Vec<u8>>,
+    pub(crate) supervision: Option<String>,
+    //Identity and address of the parent actor or pool - if spawned by one
+    pub(crate) parent: Option<(String, Addr)>,
+}
+
+//Pool definition as persisted in the backing store
+#[derive(Debug)]
+pub(crate) struct PoolDef {
+    pub(crate) name: String,
+    pub(crate) producer: String,
+    pub(crate) distribution: String,
+    pub(crate) size: usize,
+    //Round robin turn of the next message
+    pub(crate) next: usize,
+    //Sequence of the last message handed out
+    pub(crate) msg_seq: i64,
+}
+
+//Stored messages are identified by their row ids - assigned by the backend in insertion
+//order. Each inbound message gets the next sequence of its recipient actor. Actors are
+//identified by the numeric identity of their address - as text.
+pub(crate) trait StorageBackend {
+    //Prepare the store for use - bringing its schema up to date
+    fn setup(&mut self) -> Result<()>;
+    //Announce the row ids of messages as they get stored
+    fn watch(&mut self, notifier: Notifier);
+
+    //Store messages - skipping duplicates of those ingested within the dedup window or
+    //still around. Returns the ids of the duplicates.
+    fn insert_messages(&mut self, msgs: Vec<Msg>) -> Result<Vec<u64>>;
+    //Forget the ids of messages ingested before the given time - in seconds since epoch
+    fn forget_ingested(&mut self, before: i64) -> Result<usize>;
+    //Stored messages by row id - in the order they were stored
+    fn from_messages(&mut self, rowids: Vec<i64>) -> Result<Vec<RichMail>>;
+    //Inbound messages of an actor in sequence order - starting at `from_seq` and stopping
+    //short of `until_seq`. Fetched in batches.
+    fn replay_messages(
+        &mut self,
+        actor_id: &str,
+        from_seq: i64,
+        until_seq: i64,
+    ) -> Result<Vec<(i64, Msg)>>;
+    //Sequence of the latest message of an actor - processed ones count even if compacted
+    fn max_msg_seq(&mut self, actor_id: &str) -> Result<i64>;
+    fn delete_message(&mut self, msg: &Msg) -> Result<usize>;
+    //Delivered messages stay around for replay even after their time-to-live elapses
+    fn clear_expiry(&mut self, msg: &Msg) -> Result<usize>;
+    //Remove messages whose time-to-live elapsed before `now` - returning them
+    fn take_expired_messages(&mut self, now: i64) -> Result<Vec<Msg>>;
+
+    //Record the row ids of stored messages that are yet to be routed
+    fn persist_events(&mut self, rowids: Vec<i64>) -> Result<Vec<i64>>;
+    //Row ids of stored messages to be routed again on restart
+    fn read_events(&mut self) -> Result<Vec<i64>>;
+
+    //Event sourced actors keep the messages they replay from the start - until a snapshot
+    fn save_producer(
+        &mut self,
+        actor_id: &str,
+        addr: Addr,
+        actor_def: &str,
+        supervision: &str,
+        parent: Option<&Addr>,
+        event_sourced: bool,
+    ) -> Result<()>;
+    //Returns the number of definitions removed
+    fn remove_actor(&mut self, actor_id: &str) -> Result<usize>;
+    fn retrieve_actor_def(&mut self, actor_id: &str) -> Result<Option<ActorDef>>;
+    //Addresses of the actors spawned by an actor or a pool
+    fn children_of(&mut self, actor_id: &str) -> Result<Vec<Addr>>;
+    //Record the sequence of the latest message an actor has processed
+    fn update_actor_event_seq(&mut self, actor_id: &str, msg_seq: i64) -> Result<()>;
+    //Persist the latest state snapshot of an actor - taken at the given message sequence
+    fn save_state(&mut self, actor_id: &str, seq: i64, state: &[u8]) -> Result<()>;
+
+    fn record_dead_letter(&mut self, msg: &Msg, reason: &str, recorded_at: i64) -> Result<()>;
+    fn dead_letters(&mut self) -> Result<Vec<DeadLetter>>;
+    fn dead_letter(&mut self, id: i64) -> Result<Option<DeadLetter>>;
+    //Delete the given dead letter - or all of them if none is given
+    fn delete_dead_letters(&mut self, id: Option<i64>) -> Result<usize>;
+
+    //Persist a message to be delivered at `due_at` - and every `every` milliseconds after
+    //that if periodic
+    fn save_schedule(&mut self, id: &str, msg: &Msg, due_at: i64, every: i64) -> Result<()>;
+    fn delete_schedule(&mut self, id: &str) -> Result<usize>;
+    //Claim the messages of schedules that have fallen due. One-off schedules are removed,
+    //periodic ones are moved to their next run.
+    fn take_due_schedules(&mut self, now: i64) -> Result<Vec<Msg>>;
+
+    //Record messages bound for listeners on other hosts - due for delivery at `now`.
+    //Messages without a host are skipped.
+    fn save_outbound(&mut self, msgs: &[Msg], now: i64) -> Result<()>;
+    //Claim the outbound messages of the hosts whose oldest one has fallen due - in the order
+    //they were recorded. Claimed messages fall due again after a backoff - unless they get
+    //acknowledged in the meantime.
+    fn take_due_outbound(&mut self, now: i64) -> Result<Vec<Msg>>;
+    //Remove the outbound messages a host has acknowledged
+    fn ack_outbound(&mut self, host: &str, msg_ids: &[u64]) -> Result<usize>;
+
+    //Subscribe an actor to a topic. Returns 0 if already subscribed.
+    fn subscribe(&mut self, topic: &str, addr: &Addr) -> Result<usize>;
+    fn unsubscribe(&mut self, topic: &str, actor_id: &str) -> Result<usize>;
+    //Remove all the subscriptions of an actor - across topics
+    fn drop_subscriptions(&mut self, actor_id: &str) -> Result<usize>;
+    //Subscribers of a topic - in the order they subscribed
+    fn subscribers(&mut self, topic: &str) -> Result<Vec<Addr>>;
+
+    //Redefining a pool keeps track of the messages already handed out
+    fn save_pool(
+        &mut self,
+        pool_id: &str,
+        name: &str,
+        producer: &str,
+        distribution: &str,
+        size: usize,
+    ) -> Result<()>;
+    fn pool_def(&mut self, pool_id: &str) -> Result<Option<PoolDef>>;
+    fn resize_pool(&mut self, pool_id: &str, size: usize) -> Result<usize>;
+    //Record the progress of a pool - the next round robin turn and the last message handed
+    //out
+    fn save_pool_cursor(&mut self, pool_id: &str, next: usize, msg_seq: i64) -> Result<()>;
+    fn delete_pool(&mut self, pool_id: &str) -> Result<usize>;
+
+    //Delete processed messages as the retention allows - optionally reclaiming the space
+    //freed up. Returns the number of messages deleted.
+    fn compact(&mut self, retention: Retention, vacuum: bool) -> Result<usize>;
+    //Delete the processed messages of an actor right away
+    fn compact_actor(&mut self, actor_id: &str) -> Result<usize>;
+}
+
+//Host the listener fronting the recipient of a message is at
+pub(crate) fn host_of(msg: &Msg) -> Option<String> {
+    msg.get_to()
+        .as_ref()
+        .and_then(Addr::get_socket_addr)
+        .map(|host_addr| host_addr.to_string())
+}
+
+//When an outbound message claimed after the given number of attempts falls due again
+pub(crate) fn redelivery_at(now: i64, attempts: i64) -> i64 {
+    now + (OUTBOX_BACKOFF << attempts.clamp(0, 16)).min(OUTBOX_MAX_BACKOFF)
+}
+
+//The backend configured
+pub(crate) fn open() -> Box<dyn StorageBackend> {
+    match Config::get_shared().backend() {
+        Backend::Sqlite => Box::new(SqliteBackend::new()),
+        Backend::Memory => Box::new(MemoryBackend::new()),
+    }
+}
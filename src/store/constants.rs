@@ -3,30 +3,111 @@ pub(crate) const FETCH_LIMIT: usize = 1000;
 pub(crate) const TABLE_MESSAGES: &str = "messages";
 //In seconds
 pub(crate) const EVENT_MAX_AGE: u64 = 1;
+//In milliseconds - how often schedules are checked for messages that have fallen due
+pub(crate) const SCHEDULE_TICK: u64 = 100;
+//In seconds - how often messages past their time-to-live are swept out
+pub(crate) const EXPIRY_SWEEP_INTERVAL: u64 = 5;
+//In seconds - how often actors are checked for idleness
+pub(crate) const PASSIVATION_INTERVAL: u64 = 1;
+//In milliseconds - how often the outbox is checked for messages due for delivery
+pub(crate) const OUTBOX_TICK: u64 = 100;
+//In milliseconds - backoff before an unacknowledged outbound message is sent again. It
+//doubles with every attempt, up to the maximum.
+pub(crate) const OUTBOX_BACKOFF: i64 = 1000;
+pub(crate) const OUTBOX_MAX_BACKOFF: i64 = 60_000;
 pub(crate) const TX_BEGIN: &str = "BEGIN TRANSACTION;";
 pub(crate) const TX_COMMIT: &str = "COMMIT TRANSACTION;";
 pub(crate) const SELECT_ACTORS: &str = "SELECT actor_id FROM actors";
 //TODO check where its being used?
 //pub(self) const DOES_TABLE_EXIST: &str =
 //  "SELECT count(1) FROM sqlite_master WHERE type='table' AND name=?";
+//Tables are created as they were first introduced - later changes are applied by the
+//migrations
+pub(crate) const SCHEMA_VERSION_TABLE: &str =
+    "CREATE TABLE IF NOT EXISTS schema_version (version INTEGER PRIMARY KEY, description TEXT, applied_at INTEGER)";
+pub(crate) const SCHEMA_VERSION_SELECT: &str = "SELECT IFNULL(MAX(version), 0) FROM schema_version";
+pub(crate) const SCHEMA_VERSION_INSERT: &str =
+    "INSERT INTO schema_version (version, description, applied_at) VALUES (?, ?, ?)";
+pub(crate) const COLUMN_EXISTS: &str = "SELECT count(1) FROM pragma_table_info(?) WHERE name = ?";
 pub(crate) const MESSAGES: &str =
 "CREATE TABLE IF NOT EXISTS messages (actor_id TEXT, msg_id TEXT, msg_seq INTEGER,inbound INTEGER DEFAULT 1, msg BLOB, PRIMARY KEY (actor_id, msg_id))";
+pub(crate) const MESSAGES_ADD_EXPIRES_AT: &str =
+    "ALTER TABLE messages ADD COLUMN expires_at INTEGER DEFAULT NULL";
+//Messages recorded before codecs were tracked are bincode encoded
+pub(crate) const MESSAGES_ADD_CODEC: &str =
+    "ALTER TABLE messages ADD COLUMN codec INTEGER NOT NULL DEFAULT 0";
+pub(crate) const SCHEDULES_ADD_CODEC: &str =
+    "ALTER TABLE schedules ADD COLUMN codec INTEGER NOT NULL DEFAULT 0";
+pub(crate) const DEAD_LETTERS_ADD_CODEC: &str =
+    "ALTER TABLE dead_letters ADD COLUMN codec INTEGER NOT NULL DEFAULT 0";
+//Messages recorded before layout versions existed are of the first layout
+pub(crate) const MESSAGES_ADD_MSG_VERSION: &str =
+    "ALTER TABLE messages ADD COLUMN msg_version INTEGER NOT NULL DEFAULT 0";
+pub(crate) const SCHEDULES_ADD_MSG_VERSION: &str =
+    "ALTER TABLE schedules ADD COLUMN msg_version INTEGER NOT NULL DEFAULT 0";
+pub(crate) const DEAD_LETTERS_ADD_MSG_VERSION: &str =
+    "ALTER TABLE dead_letters ADD COLUMN msg_version INTEGER NOT NULL DEFAULT 0";
+//Seconds since epoch - messages stored before it was recorded count as old
+pub(crate) const MESSAGES_ADD_STORED_AT: &str =
+    "ALTER TABLE messages ADD COLUMN stored_at INTEGER DEFAULT NULL";
+//Sequence of the latest snapshot - event sourced actors replay messages from it. Starts at 0
+//for event sourced actors, NULL for others until they take a snapshot
+pub(crate) const ACTORS_ADD_SNAPSHOT_SEQ: &str =
+    "ALTER TABLE actors ADD COLUMN snapshot_seq INTEGER DEFAULT NULL";
 
 pub(crate) const ACTORS: &str =
     "CREATE TABLE IF NOT EXISTS actors (actor_id TEXT PRIMARY KEY, actor_name Text DEFAULT NULL, actor_def TEXT, state BLOB DEFAULT NULL, msg_seq INTEGER DEFAULT 0)";
+pub(crate) const ACTORS_ADD_SUPERVISION: &str =
+    "ALTER TABLE actors ADD COLUMN supervision TEXT DEFAULT NULL";
+pub(crate) const ACTORS_ADD_PARENT: &str =
+    "ALTER TABLE actors ADD COLUMN parent_id TEXT DEFAULT NULL";
+//Serialized addresses of an actor and its parent - host and port included. NULL for actors
+//defined before they were recorded - those are taken to be local.
+pub(crate) const ACTORS_ADD_ADDR: &str = "ALTER TABLE actors ADD COLUMN addr BLOB DEFAULT NULL";
+pub(crate) const ACTORS_ADD_PARENT_ADDR: &str =
+    "ALTER TABLE actors ADD COLUMN parent_addr BLOB DEFAULT NULL";
 pub(crate) const EVENTS: &str =
     "CREATE TABLE IF NOT EXISTS events (row_id INTEGER PRIMARY KEY, status TEXT DEFAULT 'N')";
+pub(crate) const SCHEDULES: &str =
+    "CREATE TABLE IF NOT EXISTS schedules (id TEXT PRIMARY KEY, actor_id TEXT, msg BLOB, due_at INTEGER, every INTEGER DEFAULT 0)";
+pub(crate) const SCHEDULES_DUE_AT: &str =
+    "CREATE INDEX IF NOT EXISTS schedules_due_at ON schedules (due_at)";
+pub(crate) const SUBSCRIPTIONS: &str =
+    "CREATE TABLE IF NOT EXISTS subscriptions (topic TEXT, actor_id TEXT, addr BLOB, PRIMARY KEY (topic, actor_id))";
+pub(crate) const POOLS: &str =
+    "CREATE TABLE IF NOT EXISTS pools (pool_id TEXT PRIMARY KEY, pool_name TEXT, producer TEXT, distribution TEXT, size INTEGER, next INTEGER DEFAULT 0, msg_seq INTEGER DEFAULT 0)";
+pub(crate) const DEAD_LETTERS: &str =
+    "CREATE TABLE IF NOT EXISTS dead_letters (id INTEGER PRIMARY KEY, actor_id TEXT, msg_id TEXT, addr BLOB, msg BLOB, reason TEXT, recorded_at INTEGER)";
+pub(crate) const OUTBOX: &str =
+    "CREATE TABLE IF NOT EXISTS outbox (id INTEGER PRIMARY KEY AUTOINCREMENT, host TEXT, msg_id TEXT, msg BLOB, codec INTEGER, msg_version INTEGER, attempts INTEGER DEFAULT 0, due_at INTEGER)";
+pub(crate) const OUTBOX_HOST: &str =
+    "CREATE INDEX IF NOT EXISTS outbox_host ON outbox (host, msg_id)";
+//Ids of ingested messages - remembered for the dedup window, beyond the messages themselves
+pub(crate) const INGESTED: &str =
+    "CREATE TABLE IF NOT EXISTS ingested (actor_id TEXT, msg_id TEXT, ingested_at INTEGER, PRIMARY KEY (actor_id, msg_id))";
+pub(crate) const INGESTED_AT: &str =
+    "CREATE INDEX IF NOT EXISTS ingested_at ON ingested (ingested_at)";
 
 pub(crate) const ACTOR_DEF_INSERT: &str =
-    "INSERT INTO actors (actor_id, actor_name, actor_def) VALUES (:actor_id, :actor_name, :actor_def)";
+    "INSERT INTO actors (actor_id, actor_name, actor_def, supervision, parent_id, snapshot_seq, addr, parent_addr) VALUES (:actor_id, :actor_name, :actor_def, :supervision, :parent_id, :snapshot_seq, :addr, :parent_addr)";
+//Sequences continue from the processed ones - whose messages may have been compacted away
 pub(crate) const INSERT_INTO_MESSAGES: &str =
-"INSERT INTO messages (actor_id, msg_id, msg_seq, msg) VALUES(:actor_id, :msg_id,(SELECT IFNULL(MAX(msg_seq), 0) + 1 FROM messages where actor_id = :actor_id), :msg)";
+"INSERT INTO messages (actor_id, msg_id, msg_seq, msg, expires_at, codec, msg_version, stored_at) VALUES(:actor_id, :msg_id, MAX(IFNULL((SELECT MAX(msg_seq) FROM messages WHERE actor_id = :actor_id), 0), IFNULL((SELECT msg_seq FROM actors WHERE actor_id = :actor_id), 0)) + 1, :msg, :expires_at, :codec, :msg_version, CAST(strftime('%s', 'now') AS INTEGER))";
 
 pub(crate) const EVENTS_INSERT: &str = "INSERT INTO events (row_id) VALUES (:row_id)";
+//Whether a message was ingested since the given time - or is still around
+pub(crate) const INGESTED_SEEN: &str = "SELECT EXISTS (SELECT 1 FROM ingested WHERE actor_id = ?1 AND msg_id = ?2 AND ingested_at >= ?3) OR EXISTS (SELECT 1 FROM messages WHERE actor_id = ?1 AND msg_id = ?2)";
+pub(crate) const INGESTED_INSERT: &str =
+    "INSERT OR REPLACE INTO ingested (actor_id, msg_id, ingested_at) VALUES (?, ?, ?)";
+pub(crate) const INGESTED_DELETE: &str = "DELETE FROM ingested WHERE ingested_at < ?";
 pub(crate) const DELETE_ACTOR: &str = "DELETE FROM actors WHERE actor_id = ?";
 pub(crate) const ACTOR_ROWID: &str = "SELECT rowid FROM actors WHERE actor_id = ?";
+//Parent of an actor is either an actor or a pool
 pub(crate) const ACTOR_DEF: &str =
-    "SELECT actor_name, actor_def, msg_seq FROM actors WHERE actor_id = ?";
+    "SELECT A.actor_name, A.actor_def, A.msg_seq, A.state, A.supervision, A.parent_id, IFNULL(P.actor_name, G.pool_name), A.parent_addr FROM actors A LEFT JOIN actors P ON A.parent_id = P.actor_id LEFT JOIN pools G ON A.parent_id = G.pool_id WHERE A.actor_id = ?";
+pub(crate) const ACTOR_CHILDREN: &str = "SELECT actor_name, addr FROM actors WHERE parent_id = ?";
+pub(crate) const UPDATE_ACTOR_STATE: &str =
+    "UPDATE actors SET state = ?, snapshot_seq = ? WHERE actor_id = ?";
 
 pub(crate) const EVENTS_SELECT: &str = "SELECT row_id FROM events WHERE status ='N'";
 
@@ -34,3 +115,57 @@ pub(crate) const MIN_MSG_SEQ: &str = "SELECT MIN(msg_seq), M.rowid, E.row_id  FR
 
 pub(crate) const UPDATE_EVENTS: &str = "UPDATE events SET status = 'Y' WHERE row_id = ?";
 pub(crate) const UPDATE_ACTOR_EVENT_SEQ: &str = "UPDATE actors SET msg_seq = ? WHERE actor_id = ?";
+
+pub(crate) const REPLAY_MESSAGES: &str = "SELECT msg_seq, msg, codec, msg_version FROM messages WHERE actor_id = ? AND inbound = 1 AND msg_seq >= ? AND msg_seq < ? ORDER BY msg_seq ASC LIMIT ?";
+pub(crate) const DEAD_LETTER_INSERT: &str = "INSERT INTO dead_letters (actor_id, msg_id, addr, msg, reason, recorded_at, codec, msg_version) VALUES (:actor_id, :msg_id, :addr, :msg, :reason, :recorded_at, :codec, :msg_version)";
+pub(crate) const DEAD_LETTERS_SELECT: &str =
+    "SELECT id, addr, msg, reason, recorded_at, codec, msg_version FROM dead_letters ORDER BY id ASC";
+pub(crate) const DEAD_LETTER_SELECT: &str =
+    "SELECT id, addr, msg, reason, recorded_at, codec, msg_version FROM dead_letters WHERE id = ?";
+pub(crate) const DEAD_LETTER_DELETE: &str = "DELETE FROM dead_letters WHERE id = ?";
+pub(crate) const DEAD_LETTERS_DELETE: &str = "DELETE FROM dead_letters";
+pub(crate) const SCHEDULE_INSERT: &str = "INSERT INTO schedules (id, actor_id, msg, due_at, every, codec, msg_version) VALUES (:id, :actor_id, :msg, :due_at, :every, :codec, :msg_version)";
+pub(crate) const SCHEDULES_DUE: &str =
+    "SELECT id, msg, every, codec, msg_version FROM schedules WHERE due_at <= ? ORDER BY due_at ASC LIMIT ?";
+pub(crate) const SCHEDULE_RESCHEDULE: &str = "UPDATE schedules SET due_at = ? WHERE id = ?";
+pub(crate) const SCHEDULE_DELETE: &str = "DELETE FROM schedules WHERE id = ?";
+pub(crate) const OUTBOX_INSERT: &str = "INSERT INTO outbox (host, msg_id, msg, codec, msg_version, due_at) VALUES (:host, :msg_id, :msg, :codec, :msg_version, :due_at)";
+//Messages of the hosts whose oldest outbound message has fallen due - a host gets its
+//messages in the order they were recorded
+pub(crate) const OUTBOX_DUE: &str =
+    "SELECT id, msg, codec, msg_version, attempts FROM outbox WHERE host IN (SELECT host FROM outbox AS oldest WHERE id = (SELECT MIN(id) FROM outbox WHERE host = oldest.host) AND due_at <= ?) ORDER BY id ASC LIMIT ?";
+pub(crate) const OUTBOX_RETRY: &str = "UPDATE outbox SET attempts = ?, due_at = ? WHERE id = ?";
+pub(crate) const OUTBOX_ACK: &str = "DELETE FROM outbox WHERE host = ? AND msg_id = ?";
+pub(crate) const EXPIRED_MESSAGES: &str =
+    "SELECT msg, codec, msg_version FROM messages WHERE expires_at <= ? ORDER BY rowid ASC LIMIT ?";
+pub(crate) const MESSAGE_DELETE: &str = "DELETE FROM messages WHERE actor_id = ? AND msg_id = ?";
+//Delivered messages are kept for replay - they must not be swept
+pub(crate) const MESSAGE_CLEAR_EXPIRY: &str =
+    "UPDATE messages SET expires_at = NULL WHERE actor_id = ? AND msg_id = ?";
+pub(crate) const SUBSCRIPTION_INSERT: &str =
+    "INSERT OR IGNORE INTO subscriptions (topic, actor_id, addr) VALUES (?, ?, ?)";
+pub(crate) const SUBSCRIPTION_DELETE: &str =
+    "DELETE FROM subscriptions WHERE topic = ? AND actor_id = ?";
+pub(crate) const SUBSCRIPTIONS_OF_ACTOR_DELETE: &str =
+    "DELETE FROM subscriptions WHERE actor_id = ?";
+pub(crate) const SUBSCRIBERS: &str =
+    "SELECT addr FROM subscriptions WHERE topic = ? ORDER BY rowid ASC";
+//Redefining a pool keeps track of the messages already handed out
+pub(crate) const POOL_UPSERT: &str = "INSERT INTO pools (pool_id, pool_name, producer, distribution, size) VALUES (:pool_id, :pool_name, :producer, :distribution, :size) ON CONFLICT (pool_id) DO UPDATE SET producer = excluded.producer, distribution = excluded.distribution, size = excluded.size";
+pub(crate) const POOL_DEF: &str =
+    "SELECT pool_name, producer, distribution, size, next, msg_seq FROM pools WHERE pool_id = ?";
+pub(crate) const POOL_RESIZE: &str = "UPDATE pools SET size = ? WHERE pool_id = ?";
+pub(crate) const POOL_CURSOR: &str = "UPDATE pools SET next = ?, msg_seq = ? WHERE pool_id = ?";
+pub(crate) const POOL_DELETE: &str = "DELETE FROM pools WHERE pool_id = ?";
+pub(crate) const MAX_MSG_SEQ: &str = "SELECT MAX(IFNULL((SELECT MAX(msg_seq) FROM messages WHERE actor_id = ?1), 0), IFNULL((SELECT msg_seq FROM actors WHERE actor_id = ?1), 0))";
+//Processed messages beyond the latest `:keep` per actor and stored before `:stored_before`.
+//Those from the snapshot sequence on are kept for event sourced actors to replay.
+pub(crate) const COMPACT_MESSAGES: &str = "DELETE FROM messages WHERE rowid IN (SELECT M.rowid FROM messages M JOIN actors A ON M.actor_id = A.actor_id WHERE M.inbound = 1 AND M.msg_seq <= A.msg_seq - :keep AND (A.snapshot_seq IS NULL OR M.msg_seq < A.snapshot_seq) AND IFNULL(M.stored_at, 0) <= :stored_before)";
+pub(crate) const COMPACT_ACTOR_MESSAGES: &str = "DELETE FROM messages WHERE rowid IN (SELECT M.rowid FROM messages M JOIN actors A ON M.actor_id = A.actor_id WHERE M.actor_id = :actor_id AND M.inbound = 1 AND M.msg_seq <= A.msg_seq AND (A.snapshot_seq IS NULL OR M.msg_seq < A.snapshot_seq))";
+pub(crate) const COMPACT_EVENTS: &str =
+    "DELETE FROM events WHERE row_id NOT IN (SELECT rowid FROM messages)";
+pub(crate) const AUTO_VACUUM: &str = "PRAGMA auto_vacuum";
+//Value of `auto_vacuum` when set to incremental
+pub(crate) const AUTO_VACUUM_INCREMENTAL: i64 = 2;
+pub(crate) const INCREMENTAL_VACUUM: &str = "PRAGMA incremental_vacuum";
+pub(crate) const VACUUM: &str = "VACUUM";
@@ -1,9 +1,8 @@
 use crate::catalog::{self};
 
-use crate::constants::{EVENTS_INSERT, EVENT_MAX_AGE};
+use crate::constants::{EVENT_MAX_AGE, EXPIRY_SWEEP_INTERVAL, PASSIVATION_INTERVAL, SCHEDULE_TICK};
 use crate::routing::Router;
 use crate::Config;
-use rusqlite::{hooks::Action, Result, ToSql, Transaction};
 use serde::{ser::SerializeTupleStruct, Deserialize, Serialize, Serializer};
 use std::mem;
 use std::time::{Duration, Instant};
@@ -16,13 +15,6 @@ pub(crate) enum Events {
 
 pub(crate) struct DBEvent(pub i64);
 
-impl DBEvent {
-    pub(crate) fn persist(&self, tx: &Transaction<'_>) -> Result<usize> {
-        let DBEvent(row_id) = self;
-        tx.execute(EVENTS_INSERT, &[&row_id as &dyn ToSql])
-    }
-}
-
 impl std::fmt::Debug for DBEvent {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         f.debug_struct("DBEvent").field("row_id", &self.0).finish()
@@ -30,7 +22,7 @@ impl std::fmt::Debug for DBEvent {
 }
 
 impl Serialize for DBEvent {
-    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    fn serialize<S>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error>
     where
         S: Serializer,
     {
@@ -48,17 +40,6 @@ pub(crate) enum DBAction {
     Unknown,
 }
 
-impl From<Action> for DBAction {
-    fn from(action: Action) -> Self {
-        match action {
-            Action::SQLITE_DELETE => DBAction::Delete,
-            Action::SQLITE_INSERT => DBAction::Insert,
-            Action::SQLITE_UPDATE => DBAction::Update,
-            _ => DBAction::Unknown,
-        }
-    }
-}
-
 pub(crate) struct EventBuffer {
     events: Vec<DBEvent>,
     earliest_event_instant: Option<Instant>,
@@ -90,6 +71,7 @@ impl EventBuffer {
         }
     }
     pub(crate) fn flush(&mut self) -> Vec<DBEvent> {
+        self.earliest_event_instant = None;
         mem::take(&mut self.events)
     }
 }
@@ -105,26 +87,92 @@ impl Drop for EventBuffer {
 pub(crate) struct EventTracker {
     buffer: EventBuffer,
     router: Router,
+    last_tick: Instant,
+    last_sweep: Instant,
+    last_passivation: Instant,
+    last_compaction: Instant,
 }
 impl EventTracker {
     pub(crate) fn new() -> Self {
         Self {
             buffer: EventBuffer::new(),
             router: Router::new(num_cpus::get()),
+            last_tick: Instant::now(),
+            last_sweep: Instant::now(),
+            last_passivation: Instant::now(),
+            last_compaction: Instant::now(),
         }
     }
     pub(crate) fn track(&mut self, event: DBEvent) {
         self.buffer.add(event);
         if self.buffer.should_flush() {
-            let persisted_events = catalog::perist_buffered(self.buffer.flush());
-            let persisted_msgs = catalog::load_messages(persisted_events);
-            self.router.route(persisted_msgs);
+            self.flush();
+        }
+    }
+
+    //Route buffered events that have waited long enough
+    pub(crate) fn flush_matured(&mut self) {
+        if self.buffer.has_matured() {
+            self.flush();
+        }
+    }
+
+    //Inject messages of schedules that have fallen due into the messages table - checked
+    //once every tick. Resulting inserts come back as events to be routed.
+    pub(crate) fn fire_schedules(&mut self) {
+        if self.last_tick.elapsed() >= Duration::from_millis(SCHEDULE_TICK) {
+            self.last_tick = Instant::now();
+            catalog::fire_schedules();
+        }
+    }
+
+    //Remove messages whose time-to-live elapsed before they could be dispatched
+    pub(crate) fn sweep_expired(&mut self) {
+        if self.last_sweep.elapsed() >= Duration::from_secs(EXPIRY_SWEEP_INTERVAL) {
+            self.last_sweep = Instant::now();
+            catalog::sweep_expired();
+        }
+    }
+
+    //Drop actors that have been idle for too long from memory
+    pub(crate) fn passivate_idle(&mut self) {
+        if self.last_passivation.elapsed() >= Duration::from_secs(PASSIVATION_INTERVAL) {
+            self.last_passivation = Instant::now();
+            catalog::passivate_idle();
+        }
+    }
+
+    //Delete processed messages as the retention allows - every compaction interval
+    pub(crate) fn compact(&mut self) {
+        let interval = Config::get_shared().compaction_interval();
+        if interval > 0 && self.last_compaction.elapsed() >= Duration::from_secs(interval) {
+            self.last_compaction = Instant::now();
+            catalog::compact_store();
         }
     }
 
+    fn flush(&mut self) {
+        let persisted_events = catalog::perist_buffered(self.buffer.flush());
+        let persisted_msgs = catalog::load_messages(persisted_events);
+        self.router.route(persisted_msgs);
+    }
+
     pub(crate) fn route_past_events(&mut self) {
         let msgs = catalog::past_events();
         println!("Handling past mags. Events = {:?}", msgs.len());
         self.router.route(msgs);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn serialize_db_event_test1() {
+        let db_event = DBEvent(100);
+        let json = serde_json::to_string(&db_event).unwrap();
+        let expected = "[100]";
+        assert_eq!(json, expected);
+    }
+}
@@ -0,0 +1,747 @@
+//!The in-memory backend - for tests and ephemeral systems. Keeps everything the sqlite
+//!backend does in maps of its own, with the same semantics, but nothing survives the
+//!process. Messages are kept as they are - there is nothing to encode them for.
+
+use crate::backend::{self, ActorDef, PoolDef, StorageBackend};
+use crate::catalog::DeadLetter;
+use crate::constants::FETCH_LIMIT;
+use crate::pubsub::Notifier;
+use crate::retention::{self, now_secs};
+use crate::{Addr, Config, Error, Mail, Msg, Result, Retention, RichMail};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::io::ErrorKind;
+
+struct StoredMsg {
+    actor_id: String,
+    msg_seq: i64,
+    msg: Msg,
+    expires_at: Option<i64>,
+    stored_at: i64,
+}
+
+struct StoredActor {
+    name: String,
+    producer: String,
+    msg_seq: i64,
+    state: Option<Vec<u8>>,
+    snapshot_seq: Option<i64>,
+    supervision: String,
+    addr: Addr,
+    parent: Option<Addr>,
+}
+
+struct Schedule {
+    msg: Msg,
+    due_at: i64,
+    every: i64,
+}
+
+struct Outbound {
+    host: String,
+    msg: Msg,
+    attempts: i64,
+    due_at: i64,
+}
+
+#[derive(Default)]
+pub(crate) struct MemoryBackend {
+    //By row id
+    messages: BTreeMap<i64, StoredMsg>,
+    //Row ids by actor and sequence
+    inboxes: HashMap<String, BTreeMap<i64, i64>>,
+    //Row ids by actor and message id
+    msg_ids: HashMap<(String, String), i64>,
+    //Ingestion time by actor and message id - kept beyond the messages for the dedup window
+    ingested: HashMap<(String, String), i64>,
+    last_rowid: i64,
+    events: BTreeSet<i64>,
+    actors: BTreeMap<String, StoredActor>,
+    dead_letters: BTreeMap<i64, DeadLetter>,
+    last_dead_letter: i64,
+    schedules: HashMap<String, Schedule>,
+    //In the order recorded
+    outbox: BTreeMap<i64, Outbound>,
+    last_outbound: i64,
+    //Topic, actor id and address - in the order of subscription
+    subscriptions: Vec<(String, String, Addr)>,
+    pools: HashMap<String, PoolDef>,
+    notifier: Option<Notifier>,
+}
+
+fn conflict(what: &str) -> Error {
+    Error::Other(Box::new(std::io::Error::new(
+        ErrorKind::AlreadyExists,
+        what.to_string(),
+    )))
+}
+
+impl MemoryBackend {
+    pub(crate) fn new() -> Self {
+        Self::default()
+    }
+
+    fn insert(&mut self, msg: Msg) -> Result<i64> {
+        let actor_id = msg.get_to_id().to_string();
+        let key = (actor_id.clone(), msg.id_as_string());
+        if self.msg_ids.contains_key(&key) {
+            return Err(conflict("Message already stored"));
+        }
+        let msg_seq = self.max_seq(&actor_id) + 1;
+        self.last_rowid += 1;
+        let rowid = self.last_rowid;
+        self.msg_ids.insert(key, rowid);
+        self.inboxes
+            .entry(actor_id.clone())
+            .or_default()
+            .insert(msg_seq, rowid);
+        let expires_at = msg.expires_at();
+        self.messages.insert(
+            rowid,
+            StoredMsg {
+                actor_id,
+                msg_seq,
+                msg,
+                expires_at,
+                stored_at: now_secs(),
+            },
+        );
+        Ok(rowid)
+    }
+
+    fn remove(&mut self, rowid: i64) -> Option<StoredMsg> {
+        let stored = self.messages.remove(&rowid)?;
+        let key = (stored.actor_id.clone(), stored.msg.id_as_string());
+        self.msg_ids.remove(&key);
+        if let Some(inbox) = self.inboxes.get_mut(&stored.actor_id) {
+            inbox.remove(&stored.msg_seq);
+        }
+        self.events.remove(&rowid);
+        Some(stored)
+    }
+
+    fn max_seq(&self, actor_id: &str) -> i64 {
+        let stored = self
+            .inboxes
+            .get(actor_id)
+            .and_then(|inbox| inbox.keys().next_back().copied())
+            .unwrap_or(0);
+        let processed = self.actors.get(actor_id).map_or(0, |actor| actor.msg_seq);
+        stored.max(processed)
+    }
+
+    fn rowid_of(&self, msg: &Msg) -> Option<i64> {
+        let key = (msg.get_to_id().to_string(), msg.id_as_string());
+        self.msg_ids.get(&key).copied()
+    }
+
+    //Processed messages of an actor beyond the latest `keep` and stored before
+    //`stored_before` - sparing those after the latest snapshot
+    fn compactable(&self, actor_id: &str, keep: i64, stored_before: i64) -> Vec<i64> {
+        let (actor, inbox) = match (self.actors.get(actor_id), self.inboxes.get(actor_id)) {
+            (Some(actor), Some(inbox)) => (actor, inbox),
+            _ => return Vec::new(),
+        };
+        inbox
+            .range(..=actor.msg_seq - keep)
+            .filter(|(msg_seq, _)| actor.snapshot_seq.is_none_or(|seq| **msg_seq < seq))
+            .map(|(_, rowid)| *rowid)
+            .filter(|rowid| self.messages[rowid].stored_at <= stored_before)
+            .collect()
+    }
+}
+
+impl StorageBackend for MemoryBackend {
+    fn setup(&mut self) -> Result<()> {
+        println!("Set up in-memory store");
+        Ok(())
+    }
+
+    fn watch(&mut self, notifier: Notifier) {
+        self.notifier = Some(notifier);
+    }
+
+    fn insert_messages(&mut self, msgs: Vec<Msg>) -> Result<Vec<u64>> {
+        let now = now_secs();
+        let since = now - Config::get_shared().dedup_window() as i64;
+        let mut duplicates = Vec::new();
+        for msg in msgs {
+            let key = (msg.get_to_id().to_string(), msg.id_as_string());
+            if matches!(self.ingested.get(&key), Some(at) if *at >= since)
+                || self.msg_ids.contains_key(&key)
+            {
+                duplicates.push(*msg.get_id());
+                continue;
+            }
+            let rowid = self.insert(msg)?;
+            self.ingested.insert(key, now);
+            if let Some(ref notifier) = self.notifier {
+                notifier.stored(rowid);
+            }
+        }
+        Ok(duplicates)
+    }
+
+    fn forget_ingested(&mut self, before: i64) -> Result<usize> {
+        let count = self.ingested.len();
+        self.ingested.retain(|_, at| *at >= before);
+        Ok(count - self.ingested.len())
+    }
+
+    fn from_messages(&mut self, rowids: Vec<i64>) -> Result<Vec<RichMail>> {
+        let rowids: BTreeSet<_> = rowids.into_iter().collect();
+        Ok(rowids
+            .iter()
+            .filter_map(|rowid| self.messages.get(rowid))
+            .map(|stored| {
+                let to = stored.msg.get_to().clone();
+                RichMail::RichContent(
+                    Mail::Trade(stored.msg.clone()),
+                    true,
+                    stored.msg_seq,
+                    None,
+                    to,
+                )
+            })
+            .collect())
+    }
+
+    fn replay_messages(
+        &mut self,
+        actor_id: &str,
+        from_seq: i64,
+        until_seq: i64,
+    ) -> Result<Vec<(i64, Msg)>> {
+        let inbox = match self.inboxes.get(actor_id) {
+            Some(inbox) if from_seq < until_seq => inbox,
+            _ => return Ok(Vec::new()),
+        };
+        Ok(inbox
+            .range(from_seq..until_seq)
+            .take(FETCH_LIMIT)
+            .map(|(msg_seq, rowid)| (*msg_seq, self.messages[rowid].msg.clone()))
+            .collect())
+    }
+
+    fn max_msg_seq(&mut self, actor_id: &str) -> Result<i64> {
+        Ok(self.max_seq(actor_id))
+    }
+
+    fn delete_message(&mut self, msg: &Msg) -> Result<usize> {
+        let removed = self.rowid_of(msg).and_then(|rowid| self.remove(rowid));
+        Ok(removed.is_some() as usize)
+    }
+
+    fn clear_expiry(&mut self, msg: &Msg) -> Result<usize> {
+        let stored = self
+            .rowid_of(msg)
+            .and_then(|rowid| self.messages.get_mut(&rowid));
+        Ok(match stored {
+            Some(stored) => {
+                stored.expires_at = None;
+                1
+            }
+            None => 0,
+        })
+    }
+
+    fn take_expired_messages(&mut self, now: i64) -> Result<Vec<Msg>> {
+        let expired: Vec<_> = self
+            .messages
+            .iter()
+            .filter(|(_, stored)| matches!(stored.expires_at, Some(at) if at <= now))
+            .map(|(rowid, _)| *rowid)
+            .take(FETCH_LIMIT)
+            .collect();
+        Ok(expired
+            .into_iter()
+            .filter_map(|rowid| self.remove(rowid))
+            .map(|stored| stored.msg)
+            .collect())
+    }
+
+    fn persist_events(&mut self, rowids: Vec<i64>) -> Result<Vec<i64>> {
+        self.events.extend(rowids.iter().copied());
+        Ok(rowids)
+    }
+
+    fn read_events(&mut self) -> Result<Vec<i64>> {
+        Ok(self.events.iter().copied().collect())
+    }
+
+    fn save_producer(
+        &mut self,
+        actor_id: &str,
+        addr: Addr,
+        actor_def: &str,
+        supervision: &str,
+        parent: Option<&Addr>,
+        event_sourced: bool,
+    ) -> Result<()> {
+        if self.actors.contains_key(actor_id) {
+            return Err(conflict("Actor already defined"));
+        }
+        self.actors.insert(
+            actor_id.to_string(),
+            StoredActor {
+                name: addr.get_name().to_string(),
+                producer: actor_def.to_string(),
+                msg_seq: 0,
+                state: None,
+                snapshot_seq: event_sourced.then_some(0),
+                supervision: supervision.to_string(),
+                addr,
+                parent: parent.cloned(),
+            },
+        );
+        Ok(())
+    }
+
+    fn remove_actor(&mut self, actor_id: &str) -> Result<usize> {
+        Ok(self.actors.remove(actor_id).is_some() as usize)
+    }
+
+    fn retrieve_actor_def(&mut self, actor_id: &str) -> Result<Option<ActorDef>> {
+        Ok(self.actors.get(actor_id).map(|actor| ActorDef {
+            name: actor.name.clone(),
+            producer: actor.producer.clone(),
+            msg_seq: actor.msg_seq,
+            state: actor.state.clone(),
+            supervision: Some(actor.supervision.clone()),
+            parent: actor
+                .parent
+                .as_ref()
+                .map(|parent| (parent.get_id().to_string(), parent.clone())),
+        }))
+    }
+
+    fn children_of(&mut self, actor_id: &str) -> Result<Vec<Addr>> {
+        Ok(self
+            .actors
+            .values()
+            .filter(|actor| {
+                let parent_id = actor
+                    .parent
+                    .as_ref()
+                    .map(|parent| parent.get_id().to_string());
+                parent_id.as_deref() == Some(actor_id)
+            })
+            .map(|actor| actor.addr.clone())
+            .collect())
+    }
+
+    fn update_actor_event_seq(&mut self, actor_id: &str, msg_seq: i64) -> Result<()> {
+        if let Some(actor) = self.actors.get_mut(actor_id) {
+            actor.msg_seq = msg_seq;
+        }
+        Ok(())
+    }
+
+    fn save_state(&mut self, actor_id: &str, seq: i64, state: &[u8]) -> Result<()> {
+        if let Some(actor) = self.actors.get_mut(actor_id) {
+            actor.state = Some(state.to_vec());
+            actor.snapshot_seq = Some(seq);
+        }
+        Ok(())
+    }
+
+    fn record_dead_letter(&mut self, msg: &Msg, reason: &str, recorded_at: i64) -> Result<()> {
+        self.last_dead_letter += 1;
+        let id = self.last_dead_letter;
+        let addr = msg.get_to().clone().unwrap_or_default();
+        let dead_letter = DeadLetter::new(id, addr, msg.clone(), reason.to_string(), recorded_at);
+        self.dead_letters.insert(id, dead_letter);
+        Ok(())
+    }
+
+    fn dead_letters(&mut self) -> Result<Vec<DeadLetter>> {
+        Ok(self.dead_letters.values().cloned().collect())
+    }
+
+    fn dead_letter(&mut self, id: i64) -> Result<Option<DeadLetter>> {
+        Ok(self.dead_letters.get(&id).cloned())
+    }
+
+    fn delete_dead_letters(&mut self, id: Option<i64>) -> Result<usize> {
+        match id {
+            Some(id) => Ok(self.dead_letters.remove(&id).is_some() as usize),
+            None => {
+                let deleted = self.dead_letters.len();
+                self.dead_letters.clear();
+                Ok(deleted)
+            }
+        }
+    }
+
+    fn save_schedule(&mut self, id: &str, msg: &Msg, due_at: i64, every: i64) -> Result<()> {
+        if self.schedules.contains_key(id) {
+            return Err(conflict("Schedule already exists"));
+        }
+        let msg = msg.clone();
+        let schedule = Schedule { msg, due_at, every };
+        self.schedules.insert(id.to_string(), schedule);
+        Ok(())
+    }
+
+    fn delete_schedule(&mut self, id: &str) -> Result<usize> {
+        Ok(self.schedules.remove(id).is_some() as usize)
+    }
+
+    fn take_due_schedules(&mut self, now: i64) -> Result<Vec<Msg>> {
+        let mut due: Vec<_> = self
+            .schedules
+            .iter()
+            .filter(|(_, schedule)| schedule.due_at <= now)
+            .map(|(id, schedule)| (schedule.due_at, id.clone()))
+            .collect();
+        due.sort();
+        due.truncate(FETCH_LIMIT);
+        let mut msgs = Vec::with_capacity(due.len());
+        for (_, id) in due {
+            let schedule = self.schedules.get_mut(&id).expect("Due schedule");
+            msgs.push(schedule.msg.clone());
+            if schedule.every > 0 {
+                schedule.due_at = now + schedule.every;
+            } else {
+                self.schedules.remove(&id);
+            }
+        }
+        Ok(msgs)
+    }
+
+    fn save_outbound(&mut self, msgs: &[Msg], now: i64) -> Result<()> {
+        for msg in msgs {
+            if let Some(host) = backend::host_of(msg) {
+                self.last_outbound += 1;
+                let outbound = Outbound {
+                    host,
+                    msg: msg.clone(),
+                    attempts: 0,
+                    due_at: now,
+                };
+                self.outbox.insert(self.last_outbound, outbound);
+            }
+        }
+        Ok(())
+    }
+
+    fn take_due_outbound(&mut self, now: i64) -> Result<Vec<Msg>> {
+        //Hosts go by their oldest message
+        let mut hosts: HashMap<&str, bool> = HashMap::new();
+        for outbound in self.outbox.values() {
+            hosts
+                .entry(&outbound.host)
+                .or_insert(outbound.due_at <= now);
+        }
+        let due: Vec<i64> = self
+            .outbox
+            .iter()
+            .filter(|(_, outbound)| hosts[outbound.host.as_str()])
+            .map(|(id, _)| *id)
+            .take(FETCH_LIMIT)
+            .collect();
+        let mut msgs = Vec::with_capacity(due.len());
+        for id in due {
+            let outbound = self.outbox.get_mut(&id).expect("Due outbound message");
+            outbound.due_at = backend::redelivery_at(now, outbound.attempts);
+            outbound.attempts += 1;
+            msgs.push(outbound.msg.clone());
+        }
+        Ok(msgs)
+    }
+
+    fn ack_outbound(&mut self, host: &str, msg_ids: &[u64]) -> Result<usize> {
+        let before = self.outbox.len();
+        self.outbox.retain(|_, outbound| {
+            outbound.host != host || !msg_ids.contains(outbound.msg.get_id())
+        });
+        Ok(before - self.outbox.len())
+    }
+
+    fn subscribe(&mut self, topic: &str, addr: &Addr) -> Result<usize> {
+        let actor_id = addr.get_id().to_string();
+        if self
+            .subscriptions
+            .iter()
+            .any(|(t, id, _)| t == topic && *id == actor_id)
+        {
+            return Ok(0);
+        }
+        self.subscriptions
+            .push((topic.to_string(), actor_id, addr.clone()));
+        Ok(1)
+    }
+
+    fn unsubscribe(&mut self, topic: &str, actor_id: &str) -> Result<usize> {
+        let count = self.subscriptions.len();
+        self.subscriptions
+            .retain(|(t, id, _)| !(t == topic && id == actor_id));
+        Ok(count - self.subscriptions.len())
+    }
+
+    fn drop_subscriptions(&mut self, actor_id: &str) -> Result<usize> {
+        let count = self.subscriptions.len();
+        self.subscriptions.retain(|(_, id, _)| id != actor_id);
+        Ok(count - self.subscriptions.len())
+    }
+
+    fn subscribers(&mut self, topic: &str) -> Result<Vec<Addr>> {
+        Ok(self
+            .subscriptions
+            .iter()
+            .filter(|(t, _, _)| t == topic)
+            .map(|(_, _, addr)| addr.clone())
+            .collect())
+    }
+
+    fn save_pool(
+        &mut self,
+        pool_id: &str,
+        name: &str,
+        producer: &str,
+        distribution: &str,
+        size: usize,
+    ) -> Result<()> {
+        let pool = self
+            .pools
+            .entry(pool_id.to_string())
+            .or_insert_with(|| PoolDef {
+                name: name.to_string(),
+                producer: String::new(),
+                distribution: String::new(),
+                size: 0,
+                next: 0,
+                msg_seq: 0,
+            });
+        pool.producer = producer.to_string();
+        pool.distribution = distribution.to_string();
+        pool.size = size;
+        Ok(())
+    }
+
+    fn pool_def(&mut self, pool_id: &str) -> Result<Option<PoolDef>> {
+        Ok(self.pools.get(pool_id).map(|pool| PoolDef {
+            name: pool.name.clone(),
+            producer: pool.producer.clone(),
+            distribution: pool.distribution.clone(),
+            size: pool.size,
+            next: pool.next,
+            msg_seq: pool.msg_seq,
+        }))
+    }
+
+    fn resize_pool(&mut self, pool_id: &str, size: usize) -> Result<usize> {
+        Ok(match self.pools.get_mut(pool_id) {
+            Some(pool) => {
+                pool.size = size;
+                1
+            }
+            None => 0,
+        })
+    }
+
+    fn save_pool_cursor(&mut self, pool_id: &str, next: usize, msg_seq: i64) -> Result<()> {
+        if let Some(pool) = self.pools.get_mut(pool_id) {
+            pool.next = next;
+            pool.msg_seq = msg_seq;
+        }
+        Ok(())
+    }
+
+    fn delete_pool(&mut self, pool_id: &str) -> Result<usize> {
+        Ok(self.pools.remove(pool_id).is_some() as usize)
+    }
+
+    fn compact(&mut self, retention: Retention, _vacuum: bool) -> Result<usize> {
+        let (keep, stored_before) = match retention::bounds(retention) {
+            Some(bounds) => bounds,
+            None => return Ok(0),
+        };
+        let rowids: Vec<_> = self
+            .actors
+            .keys()
+            .flat_map(|actor_id| self.compactable(actor_id, keep, stored_before))
+            .collect();
+        for rowid in &rowids {
+            self.remove(*rowid);
+        }
+        Ok(rowids.len())
+    }
+
+    fn compact_actor(&mut self, actor_id: &str) -> Result<usize> {
+        let rowids = self.compactable(actor_id, 0, i64::MAX);
+        for rowid in &rowids {
+            self.remove(*rowid);
+        }
+        Ok(rowids.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stored(backend: &mut MemoryBackend, actor: &str) -> Vec<(i64, String)> {
+        let actor_id = Addr::new(actor).get_id().to_string();
+        backend
+            .replay_messages(&actor_id, 1, i64::MAX)
+            .unwrap()
+            .into_iter()
+            .map(|(seq, msg)| (seq, msg.as_text().unwrap_or_default().to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn memory_backend_test_1() {
+        let mut backend = MemoryBackend::new();
+        let addr = Addr::new("memory");
+        let actor_id = addr.get_id().to_string();
+        backend
+            .save_producer(&actor_id, addr.clone(), "{}", "", None, false)
+            .unwrap();
+        assert!(backend
+            .save_producer(&actor_id, addr, "{}", "", None, false)
+            .is_err());
+        let msgs: Vec<_> = ["one", "two", "three"]
+            .iter()
+            .map(|text| Msg::with_text(text, "from", "memory"))
+            .collect();
+        backend.insert_messages(msgs.clone()).unwrap();
+        //Duplicates are reported rather than failing the batch
+        let duplicates = vec![*msgs[0].get_id()];
+        assert_eq!(
+            backend.insert_messages(vec![msgs[0].clone()]).unwrap(),
+            duplicates
+        );
+        assert_eq!(
+            stored(&mut backend, "memory"),
+            vec![
+                (1, "one".to_string()),
+                (2, "two".to_string()),
+                (3, "three".to_string())
+            ]
+        );
+        assert_eq!(backend.read_events().unwrap(), Vec::<i64>::new());
+        backend.persist_events(vec![3, 1]).unwrap();
+        let events = backend.read_events().unwrap();
+        let mails = backend.from_messages(events).unwrap();
+        assert_eq!(mails.iter().map(RichMail::seq).collect::<Vec<_>>(), [1, 3]);
+
+        //Processed upto two - sequences carry on past compacted messages
+        backend.update_actor_event_seq(&actor_id, 2).unwrap();
+        assert_eq!(backend.compact_actor(&actor_id).unwrap(), 2);
+        assert_eq!(backend.read_events().unwrap(), vec![3]);
+        backend.update_actor_event_seq(&actor_id, 3).unwrap();
+        assert_eq!(backend.compact(Retention::Last(1), false).unwrap(), 0);
+        assert_eq!(backend.compact(Retention::Processed, false).unwrap(), 1);
+        backend
+            .insert_messages(vec![Msg::with_text("four", "from", "memory")])
+            .unwrap();
+        assert_eq!(
+            stored(&mut backend, "memory"),
+            vec![(4, "four".to_string())]
+        );
+        assert_eq!(backend.max_msg_seq(&actor_id).unwrap(), 4);
+        //Compacted ones are still duplicates - until forgotten
+        assert_eq!(
+            backend.insert_messages(vec![msgs[0].clone()]).unwrap(),
+            duplicates
+        );
+        assert_eq!(backend.forget_ingested(i64::MAX).unwrap(), 4);
+        assert!(backend
+            .insert_messages(vec![msgs[0].clone()])
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn memory_backend_event_sourced_test_1() {
+        let mut backend = MemoryBackend::new();
+        let addr = Addr::new("memory_journal");
+        let actor_id = addr.get_id().to_string();
+        backend
+            .save_producer(&actor_id, addr, "{}", "", None, true)
+            .unwrap();
+        let msgs: Vec<_> = ["one", "two", "three"]
+            .iter()
+            .map(|text| Msg::with_text(text, "from", "memory_journal"))
+            .collect();
+        backend.insert_messages(msgs).unwrap();
+        backend.update_actor_event_seq(&actor_id, 3).unwrap();
+        //No snapshot yet - every processed message is needed for replay
+        assert_eq!(backend.compact(Retention::Processed, false).unwrap(), 0);
+        assert_eq!(backend.compact_actor(&actor_id).unwrap(), 0);
+        assert_eq!(stored(&mut backend, "memory_journal").len(), 3);
+        //Those before the snapshot go once it is taken
+        backend.save_state(&actor_id, 2, &[0]).unwrap();
+        assert_eq!(backend.compact(Retention::Processed, false).unwrap(), 1);
+        assert_eq!(backend.replay_messages(&actor_id, 2, 4).unwrap().len(), 2);
+    }
+
+    #[test]
+    fn memory_backend_dedup_test_1() {
+        let mut backend = MemoryBackend::new();
+        let addr = Addr::new("dedup");
+        let actor_id = addr.get_id().to_string();
+        backend
+            .save_producer(&actor_id, addr, "{}", "", None, false)
+            .unwrap();
+        let msg = Msg::with_text("once", "from", "dedup");
+        assert!(backend
+            .insert_messages(vec![msg.clone()])
+            .unwrap()
+            .is_empty());
+        backend.update_actor_event_seq(&actor_id, 1).unwrap();
+        assert_eq!(backend.compact_actor(&actor_id).unwrap(), 1);
+        //Compacted away - remembered for as long as the dedup window lasts
+        let window = Config::get_shared().dedup_window() as i64;
+        let key = (actor_id, msg.id_as_string());
+        let duplicates = vec![*msg.get_id()];
+        backend
+            .ingested
+            .insert(key.clone(), now_secs() - window + 60);
+        assert_eq!(
+            backend.insert_messages(vec![msg.clone()]).unwrap(),
+            duplicates
+        );
+        backend.ingested.insert(key, now_secs() - window - 60);
+        assert!(backend.insert_messages(vec![msg]).unwrap().is_empty());
+        assert_eq!(stored(&mut backend, "dedup"), vec![(2, "once".to_string())]);
+    }
+
+    #[test]
+    fn memory_backend_outbox_test_1() {
+        let mut backend = MemoryBackend::new();
+        let to = |text: &str, host: &str| {
+            let mut msg = Msg::from_text(text);
+            msg.set_recipient_addr(&Addr::remote("remote", host));
+            msg
+        };
+        let (host_a, host_b) = ("10.10.10.10:7171", "11.11.11.11:7171");
+        let msgs = vec![to("a1", host_a), to("b1", host_b), to("a2", host_a)];
+        backend.save_outbound(&msgs, 0).unwrap();
+        let texts = |msgs: Vec<Msg>| -> Vec<String> {
+            msgs.iter()
+                .map(|msg| msg.as_text().unwrap_or_default().to_string())
+                .collect()
+        };
+        assert_eq!(
+            texts(backend.take_due_outbound(0).unwrap()),
+            ["a1", "b1", "a2"]
+        );
+        //Claimed ones wait out the backoff - later messages to a host queue up behind them
+        backend.save_outbound(&[to("a3", host_a)], 0).unwrap();
+        assert!(backend.take_due_outbound(0).unwrap().is_empty());
+        let acked = [*msgs[0].get_id(), *msgs[2].get_id()];
+        assert_eq!(backend.ack_outbound(host_a, &acked).unwrap(), 2);
+        assert_eq!(texts(backend.take_due_outbound(0).unwrap()), ["a3"]);
+        let retry_at = backend::redelivery_at(0, 0);
+        assert_eq!(
+            texts(backend.take_due_outbound(retry_at).unwrap()),
+            ["b1", "a3"]
+        );
+        //Backoff doubles with each attempt
+        let retry_at = backend::redelivery_at(retry_at, 1);
+        assert!(backend.take_due_outbound(retry_at - 1).unwrap().is_empty());
+        assert_eq!(backend.take_due_outbound(retry_at).unwrap().len(), 2);
+    }
+}
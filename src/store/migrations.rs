@@ -0,0 +1,343 @@
+//!Ordered migrations of the backing store schema. The version a database is at gets
+//!recorded in the `schema_version` table - each migration step newer than that is applied
+//!at startup, in its own transaction. Databases created before versioning start out at
+//!version zero - steps tolerate the tables and columns they add being already there.
+//!
+//!New steps go at the end of `MIGRATIONS` - released steps must never change. Databases
+//!written by released builds are kept under `fixtures` - the tests migrate copies of them.
+
+use crate::constants::*;
+use crate::{Error, Result};
+use rusqlite::{params, Connection, Transaction};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+//A migration step - brings the schema from the previous version to its own
+struct Migration {
+    version: u32,
+    description: &'static str,
+    apply: fn(&Transaction<'_>) -> rusqlite::Result<()>,
+}
+
+const MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        description: "Messages, actors and events",
+        apply: |tx| {
+            tx.execute(MESSAGES, [])?;
+            tx.execute(ACTORS, [])?;
+            tx.execute(EVENTS, [])?;
+            Ok(())
+        },
+    },
+    Migration {
+        version: 2,
+        description: "Message expiry, dead letters and schedules",
+        apply: |tx| {
+            add_column(tx, "messages", "expires_at", MESSAGES_ADD_EXPIRES_AT)?;
+            tx.execute(DEAD_LETTERS, [])?;
+            tx.execute(SCHEDULES, [])?;
+            tx.execute(SCHEDULES_DUE_AT, [])?;
+            Ok(())
+        },
+    },
+    Migration {
+        version: 3,
+        description: "Actor supervision and hierarchy",
+        apply: |tx| {
+            add_column(tx, "actors", "supervision", ACTORS_ADD_SUPERVISION)?;
+            add_column(tx, "actors", "parent_id", ACTORS_ADD_PARENT)?;
+            Ok(())
+        },
+    },
+    Migration {
+        version: 4,
+        description: "Topic subscriptions and actor pools",
+        apply: |tx| {
+            tx.execute(SUBSCRIPTIONS, [])?;
+            tx.execute(POOLS, [])?;
+            Ok(())
+        },
+    },
+    Migration {
+        version: 5,
+        description: "Message codecs",
+        apply: |tx| {
+            add_column(tx, "messages", "codec", MESSAGES_ADD_CODEC)?;
+            add_column(tx, "schedules", "codec", SCHEDULES_ADD_CODEC)?;
+            add_column(tx, "dead_letters", "codec", DEAD_LETTERS_ADD_CODEC)?;
+            Ok(())
+        },
+    },
+    Migration {
+        version: 6,
+        description: "Message layout versions",
+        apply: |tx| {
+            add_column(tx, "messages", "msg_version", MESSAGES_ADD_MSG_VERSION)?;
+            add_column(tx, "schedules", "msg_version", SCHEDULES_ADD_MSG_VERSION)?;
+            add_column(
+                tx,
+                "dead_letters",
+                "msg_version",
+                DEAD_LETTERS_ADD_MSG_VERSION,
+            )?;
+            Ok(())
+        },
+    },
+    Migration {
+        version: 7,
+        description: "Message retention",
+        apply: |tx| {
+            add_column(tx, "messages", "stored_at", MESSAGES_ADD_STORED_AT)?;
+            add_column(tx, "actors", "snapshot_seq", ACTORS_ADD_SNAPSHOT_SEQ)?;
+            Ok(())
+        },
+    },
+    Migration {
+        version: 8,
+        description: "Outbox of messages to other nodes",
+        apply: |tx| {
+            tx.execute(OUTBOX, [])?;
+            tx.execute(OUTBOX_HOST, [])?;
+            Ok(())
+        },
+    },
+    Migration {
+        version: 9,
+        description: "Message deduplication",
+        apply: |tx| {
+            tx.execute(INGESTED, [])?;
+            tx.execute(INGESTED_AT, [])?;
+            Ok(())
+        },
+    },
+    Migration {
+        version: 10,
+        description: "Actor and parent addresses",
+        apply: |tx| {
+            add_column(tx, "actors", "addr", ACTORS_ADD_ADDR)?;
+            add_column(tx, "actors", "parent_addr", ACTORS_ADD_PARENT_ADDR)?;
+            Ok(())
+        },
+    },
+];
+
+///Latest schema version - the one this build migrates databases to
+pub(crate) const SCHEMA_VERSION: u32 = MIGRATIONS.len() as u32;
+
+//Add a column unless a database predating versioning already has it
+fn add_column(
+    tx: &Transaction<'_>,
+    table: &str,
+    column: &str,
+    alter: &str,
+) -> rusqlite::Result<()> {
+    let count: i64 = tx.query_row(COLUMN_EXISTS, params![table, column], |row| row.get(0))?;
+    if count == 0 {
+        tx.execute(alter, [])?;
+    }
+    Ok(())
+}
+
+fn sql_err(err: rusqlite::Error) -> Error {
+    Error::Other(Box::new(err))
+}
+
+//Version of the schema as recorded in the database
+pub(crate) fn version(conn: &Connection) -> Result<u32> {
+    conn.execute(SCHEMA_VERSION_TABLE, []).map_err(sql_err)?;
+    conn.query_row(SCHEMA_VERSION_SELECT, [], |row| row.get(0))
+        .map_err(sql_err)
+}
+
+//Bring the schema up to the latest version. Fails without touching the database if it has
+//been migrated by a newer build.
+pub(crate) fn migrate(conn: &mut Connection) -> Result<u32> {
+    migrate_to(conn, SCHEMA_VERSION)
+}
+
+fn migrate_to(conn: &mut Connection, target: u32) -> Result<u32> {
+    let found = version(conn)?;
+    if found > SCHEMA_VERSION {
+        return Err(Error::SchemaTooNew(found, SCHEMA_VERSION));
+    }
+    let mut current = found;
+    for migration in MIGRATIONS
+        .iter()
+        .filter(|migration| migration.version > found && migration.version <= target)
+    {
+        let tx = conn.transaction().map_err(sql_err)?;
+        (migration.apply)(&tx).map_err(sql_err)?;
+        let applied_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |elapsed| elapsed.as_secs() as i64);
+        tx.execute(
+            SCHEMA_VERSION_INSERT,
+            params![migration.version, migration.description, applied_at],
+        )
+        .map_err(sql_err)?;
+        tx.commit().map_err(sql_err)?;
+        println!(
+            "Migrated schema to version {} - {}",
+            migration.version, migration.description
+        );
+        current = migration.version;
+    }
+    Ok(current)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sqlite::value_to_msg;
+    use crate::{Addr, Producer, ProducerDeserializer};
+    use std::fs;
+    use uuid::Uuid;
+
+    //Database written by the released baseline - messages, actors and events, the first
+    //message of `fixture_actor` processed
+    const BASELINE: &str = concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/src/store/fixtures/baseline.db"
+    );
+
+    //Columns each step introduces
+    const COLUMNS: &[(u32, &str, &str)] = &[
+        (1, "messages", "msg"),
+        (1, "actors", "actor_def"),
+        (1, "events", "status"),
+        (2, "messages", "expires_at"),
+        (2, "dead_letters", "reason"),
+        (2, "schedules", "due_at"),
+        (3, "actors", "supervision"),
+        (3, "actors", "parent_id"),
+        (4, "subscriptions", "topic"),
+        (4, "pools", "distribution"),
+        (5, "messages", "codec"),
+        (5, "schedules", "codec"),
+        (5, "dead_letters", "codec"),
+        (6, "messages", "msg_version"),
+        (6, "schedules", "msg_version"),
+        (6, "dead_letters", "msg_version"),
+        (7, "messages", "stored_at"),
+        (7, "actors", "snapshot_seq"),
+        (8, "outbox", "due_at"),
+        (9, "ingested", "ingested_at"),
+        (10, "actors", "addr"),
+        (10, "actors", "parent_addr"),
+    ];
+
+    fn has_column(conn: &Connection, table: &str, column: &str) -> bool {
+        conn.query_row(COLUMN_EXISTS, params![table, column], |row| {
+            row.get::<_, i64>(0)
+        })
+        .unwrap()
+            == 1
+    }
+
+    fn message_count(conn: &Connection) -> i64 {
+        conn.query_row("SELECT count(1) FROM messages", [], |row| row.get(0))
+            .unwrap()
+    }
+
+    //Each step applied to a database at the previous version - holding a message
+    #[test]
+    fn migrations_step_test_1() {
+        for step in MIGRATIONS {
+            let mut conn = Connection::open_in_memory().unwrap();
+            migrate_to(&mut conn, step.version - 1).unwrap();
+            if step.version > 1 {
+                conn.execute(
+                    "INSERT INTO messages (actor_id, msg_id, msg_seq, msg) VALUES ('1', '1', 1, x'00')",
+                    [],
+                )
+                .unwrap();
+            }
+            assert_eq!(migrate_to(&mut conn, step.version).unwrap(), step.version);
+            for (version, table, column) in COLUMNS {
+                assert_eq!(has_column(&conn, table, column), *version <= step.version);
+            }
+            assert_eq!(message_count(&conn), (step.version > 1) as i64);
+        }
+    }
+
+    //Migrate a copy of a fixture - the checked in one stays as released
+    fn migrated_copy(fixture: &str) -> (Connection, std::path::PathBuf) {
+        let path = std::env::temp_dir().join(format!("arrows-fixture-{}.db", Uuid::new_v4()));
+        fs::copy(fixture, &path).unwrap();
+        let mut conn = Connection::open(&path).unwrap();
+        assert_eq!(version(&conn).unwrap(), 0);
+        assert_eq!(migrate(&mut conn).unwrap(), SCHEMA_VERSION);
+        (conn, path)
+    }
+
+    //Database created before versioning - as written by the released baseline
+    #[test]
+    fn migrations_baseline_test_1() {
+        let (mut conn, path) = migrated_copy(BASELINE);
+        for (_, table, column) in COLUMNS {
+            assert!(has_column(&conn, table, column));
+        }
+        let texts: Vec<String> = conn
+            .prepare("SELECT msg, codec, msg_version FROM messages ORDER BY rowid")
+            .unwrap()
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))
+            .unwrap()
+            .map(|row| {
+                let (msg, codec, msg_version) = row.unwrap();
+                let msg = value_to_msg(msg, codec, msg_version).unwrap();
+                msg.as_text().unwrap().to_string()
+            })
+            .collect();
+        assert_eq!(texts, ["first", "second", "third", "other"]);
+        let pending: Vec<i64> = conn
+            .prepare(EVENTS_SELECT)
+            .unwrap()
+            .query_map([], |row| row.get(0))
+            .unwrap()
+            .collect::<rusqlite::Result<_>>()
+            .unwrap();
+        assert_eq!(pending, [2, 3, 4]);
+        let actor_id = Addr::new("fixture_actor").get_id().to_string();
+        let (name, producer, msg_seq, supervision, parent_id): (
+            String,
+            String,
+            i64,
+            Option<String>,
+            Option<String>,
+        ) = conn
+            .query_row(ACTOR_DEF, [&actor_id], |row| {
+                Ok((
+                    row.get(0)?,
+                    row.get(1)?,
+                    row.get(2)?,
+                    row.get(4)?,
+                    row.get(5)?,
+                ))
+            })
+            .unwrap();
+        assert_eq!((name.as_str(), msg_seq), ("fixture_actor", 1));
+        assert!(ProducerDeserializer.from_string(producer).is_ok());
+        assert_eq!((supervision, parent_id), (None, None));
+        //Migrating again is a no-op
+        assert_eq!(migrate(&mut conn).unwrap(), SCHEMA_VERSION);
+        drop(conn);
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn migrations_newer_database_test_1() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        migrate(&mut conn).unwrap();
+        conn.execute(
+            SCHEMA_VERSION_INSERT,
+            params![SCHEMA_VERSION + 1, "Future", 0],
+        )
+        .unwrap();
+        match migrate(&mut conn) {
+            Err(Error::SchemaTooNew(found, supported)) => {
+                assert_eq!((found, supported), (SCHEMA_VERSION + 1, SCHEMA_VERSION))
+            }
+            other => panic!("Unexpected {:?}", other),
+        }
+    }
+}
@@ -1,5 +1,10 @@
 pub(crate) mod apis;
+pub(crate) mod backend;
 pub(crate) mod constants;
 pub(crate) mod dbconnection;
 pub(crate) mod events;
+pub(crate) mod memory;
+pub(crate) mod migrations;
 pub(crate) mod pubsub;
+pub(crate) mod retention;
+pub(crate) mod sqlite;
@@ -1,9 +1,10 @@
-use crate::constants::TABLE_MESSAGES;
-use crate::dbconnection::DBConnection;
+use crate::backend::StorageBackend;
+use crate::catalog;
+use crate::constants::SCHEDULE_TICK;
 use crate::events::{DBEvent, EventTracker, Events};
-use rusqlite::hooks::Action;
-use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError, Sender};
 use std::thread::JoinHandle;
+use std::time::Duration;
 
 pub(crate) struct Publisher {
     publisher: Sender<Events>,
@@ -19,20 +20,8 @@ impl Publisher {
             subscriber: None,
         }
     }
-    pub fn start(&mut self, conn: &mut DBConnection) {
-        conn.inner.update_hook(None::<fn(Action, &str, &str, i64)>);
-        let publisher = self.publisher.clone();
-        conn.inner
-            .update_hook(Some(move |action: Action, _db: &str, tbl: &str, row_id| {
-                let tbl_of_interest = tbl.starts_with(TABLE_MESSAGES);
-                if action == Action::SQLITE_INSERT && tbl_of_interest {
-                    let event = DBEvent(row_id);
-                    match publisher.send(Events::DbUpdate(event)) {
-                        Ok(_) => (),
-                        Err(err) => eprintln!("Error publishing event {}", err),
-                    }
-                }
-            }));
+    pub fn start(&mut self, backend: &mut dyn StorageBackend) {
+        backend.watch(Notifier(self.publisher.clone()));
         let receiver = self.receiver.take();
         let mut subscriber = Subscriber::new(receiver);
         subscriber.start();
@@ -44,6 +33,18 @@ impl Publisher {
     }
 }
 
+//Handed to the backend - announces stored messages to the subscriber
+#[derive(Clone)]
+pub(crate) struct Notifier(Sender<Events>);
+
+impl Notifier {
+    pub(crate) fn stored(&self, row_id: i64) {
+        if let Err(err) = self.0.send(Events::DbUpdate(DBEvent(row_id))) {
+            eprintln!("Error publishing event {}", err);
+        }
+    }
+}
+
 pub(crate) struct Subscriber {
     receiver: Option<Receiver<Events>>,
     pub join_handle: Option<JoinHandle<()>>,
@@ -62,14 +63,23 @@ impl Subscriber {
             let receiver = receiver.as_ref().expect("Inner receiver");
             let mut tracker = EventTracker::new();
             tracker.route_past_events();
+            catalog::deliver_outbox();
             loop {
-                let event = receiver.recv().expect("Expected event");
-                match event {
-                    Events::Stop => break,
-                    Events::DbUpdate(evt) => {
+                //Wake up periodically so that buffered events do not wait for the next
+                //event to arrive, schedules get fired on time, expired messages get swept, idle
+                //actors get passivated and processed messages get compacted
+                match receiver.recv_timeout(Duration::from_millis(SCHEDULE_TICK)) {
+                    Ok(Events::Stop) => break,
+                    Ok(Events::DbUpdate(evt)) => {
                         tracker.track(evt);
                     }
+                    Err(RecvTimeoutError::Timeout) => tracker.flush_matured(),
+                    Err(RecvTimeoutError::Disconnected) => break,
                 }
+                tracker.fire_schedules();
+                tracker.sweep_expired();
+                tracker.passivate_idle();
+                tracker.compact();
             }
         });
         self.join_handle = Some(join_handle);
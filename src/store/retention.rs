@@ -0,0 +1,134 @@
+//!Compaction of the backing store - deletes processed messages as the configured
+//![Retention](crate::Retention) allows, along with their events. A message counts as
+//!processed once the sequence of its actor has advanced past it. Event sourced actors
+//!replay messages from their latest snapshot, or from the start until they take one -
+//!those are kept regardless.
+
+use crate::constants::*;
+use crate::Retention;
+use rusqlite::{named_params, Connection, Result};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+pub(crate) fn now_secs() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs() as i64)
+}
+
+//Number of processed messages kept per actor and the time before which messages may go -
+//None if all are kept
+pub(crate) fn bounds(retention: Retention) -> Option<(i64, i64)> {
+    match retention {
+        Retention::All => None,
+        Retention::Processed => Some((0, i64::MAX)),
+        Retention::Last(count) => Some((count as i64, i64::MAX)),
+        Retention::For(secs) => Some((0, now_secs() - secs as i64)),
+    }
+}
+
+//Apply the retention to all actors and optionally reclaim the space freed up. Returns the
+//number of messages deleted.
+pub(crate) fn compact(conn: &mut Connection, retention: Retention, vacuum: bool) -> Result<usize> {
+    let (keep, stored_before) = match bounds(retention) {
+        Some(bounds) => bounds,
+        None => return Ok(0),
+    };
+    let tx = conn.transaction()?;
+    let deleted = tx.execute(
+        COMPACT_MESSAGES,
+        named_params! {":keep": keep, ":stored_before": stored_before},
+    )?;
+    tx.execute(COMPACT_EVENTS, [])?;
+    tx.commit()?;
+    if vacuum && deleted > 0 {
+        let auto_vacuum: i64 = conn.query_row(AUTO_VACUUM, [], |row| row.get(0))?;
+        if auto_vacuum == AUTO_VACUUM_INCREMENTAL {
+            conn.execute_batch(INCREMENTAL_VACUUM)?;
+        } else {
+            conn.execute_batch(VACUUM)?;
+        }
+    }
+    Ok(deleted)
+}
+
+//Delete the processed messages of an actor right away - as the `Processed` retention asks
+pub(crate) fn compact_actor(conn: &Connection, actor_id: &str) -> Result<usize> {
+    let mut stmt = conn.prepare_cached(COMPACT_ACTOR_MESSAGES)?;
+    stmt.execute(named_params! {":actor_id": actor_id})
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::migrations;
+    use rusqlite::params;
+
+    //Actor 1 has processed 3 of its 4 messages, actor 2 is event sourced with a snapshot
+    //taken at message 2 of 3 and actor 3 is event sourced without one - all processed
+    fn store() -> Connection {
+        let mut conn = Connection::open_in_memory().unwrap();
+        migrations::migrate(&mut conn).unwrap();
+        conn.execute_batch(
+            "INSERT INTO actors (actor_id, actor_def, msg_seq) VALUES ('1', x'00', 3);
+             INSERT INTO actors (actor_id, actor_def, msg_seq, state, snapshot_seq)
+                VALUES ('2', x'00', 3, x'00', 2);
+             INSERT INTO actors (actor_id, actor_def, msg_seq, snapshot_seq)
+                VALUES ('3', x'00', 2, 0);",
+        )
+        .unwrap();
+        for (actor_id, count) in [("1", 4), ("2", 3), ("3", 2)] {
+            for seq in 1..=count {
+                conn.execute(
+                    "INSERT INTO messages (actor_id, msg_id, msg_seq, msg, stored_at)
+                        VALUES (?, ?, ?, x'00', ?)",
+                    params![actor_id, format!("{}-{}", actor_id, seq), seq, seq * 100],
+                )
+                .unwrap();
+                conn.execute(EVENTS_INSERT, [conn.last_insert_rowid()])
+                    .unwrap();
+            }
+        }
+        conn
+    }
+
+    fn remaining(conn: &Connection, table: &str) -> i64 {
+        conn.query_row(&format!("SELECT count(1) FROM {}", table), [], |row| {
+            row.get(0)
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn retention_compact_test_1() {
+        let mut conn = store();
+        assert_eq!(compact(&mut conn, Retention::All, false).unwrap(), 0);
+        //Actor 2 keeps the messages from its snapshot on, actor 3 all of them
+        assert_eq!(compact(&mut conn, Retention::Last(1), false).unwrap(), 3);
+        assert_eq!(compact(&mut conn, Retention::Processed, true).unwrap(), 1);
+        assert_eq!(
+            (remaining(&conn, "messages"), remaining(&conn, "events")),
+            (5, 5)
+        );
+        let replayable: i64 = conn
+            .query_row(
+                "SELECT count(1) FROM messages WHERE actor_id = '3'",
+                [],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(replayable, 2);
+
+        let mut conn = store();
+        //Stored a while ago - but not before the unix epoch
+        assert_eq!(
+            compact(&mut conn, Retention::For(now_secs() as u64), false).unwrap(),
+            0
+        );
+        assert_eq!(compact(&mut conn, Retention::For(0), false).unwrap(), 4);
+        let mut conn = store();
+        assert_eq!(compact_actor(&conn, "1").unwrap(), 3);
+        assert_eq!(compact_actor(&conn, "2").unwrap(), 1);
+        assert_eq!(compact_actor(&conn, "3").unwrap(), 0);
+        assert_eq!(compact(&mut conn, Retention::Processed, false).unwrap(), 0);
+    }
+}
@@ -0,0 +1,1019 @@
+#![allow(clippy::wrong_self_convention)]
+//!The sqlite backend - the default. Messages, actors and the rest are kept in tables of an
+//!sqlite database under the configured db path. Stored messages are announced via the
+//!update hook of the connection.
+
+use crate::backend::{self, ActorDef, PoolDef, StorageBackend};
+use crate::catalog::DeadLetter;
+use crate::common::codec::{self, Codec};
+use crate::common::envelope::{self, MSG_VERSION};
+use crate::common::utils::from_bytes;
+use crate::constants::*;
+use crate::dbconnection::DBConnection;
+use crate::events::DBAction;
+use crate::migrations;
+use crate::pubsub::Notifier;
+use crate::retention;
+use crate::{Addr, Config, Mail, Msg, Result, Retention, RichMail};
+use fallible_streaming_iterator::FallibleStreamingIterator;
+use rusqlite::{
+    hooks::Action,
+    named_params, params,
+    types::{Type, Value},
+    Error::InvalidQuery,
+    ToSql,
+};
+use std::collections::HashMap;
+use std::io::Error;
+
+pub(crate) struct SqliteBackend {
+    conn: DBConnection,
+    message_insert_stmt: Option<String>,
+    inbox_select_stmts: HashMap<String, String>,
+    actor_create_stmts: HashMap<String, String>,
+}
+impl std::fmt::Debug for SqliteBackend {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SqliteBackend")
+            .field("message_insert_stmt", &self.message_insert_stmt)
+            .field("inbox_select_stmts", &self.inbox_select_stmts)
+            .field("actor_create_stmts", &self.actor_create_stmts)
+            .finish()
+    }
+}
+impl SqliteBackend {
+    pub(crate) fn new() -> Self {
+        Self {
+            conn: DBConnection::new(),
+            message_insert_stmt: None,
+            inbox_select_stmts: HashMap::new(),
+            actor_create_stmts: HashMap::new(),
+        }
+    }
+
+    pub(crate) fn all_actors(&mut self) -> rusqlite::Result<Vec<String>> {
+        let mut stmt = self
+            .conn
+            .inner
+            .prepare_cached("SELECT actor_id FROM actors")
+            .ok();
+        let mut actors = Vec::with_capacity(FETCH_LIMIT);
+        match stmt {
+            Some(ref mut s) => {
+                let rows = s.query_map([], |row| row.get(0))?;
+                for row in rows {
+                    let value: String = row?;
+                    actors.push(value);
+                }
+            }
+            None => panic!("Error retrieving actors!"),
+        }
+        Ok(actors)
+    }
+
+    pub(crate) fn purge_inbox_of(&mut self, actor_id: &str) -> rusqlite::Result<()> {
+        let stmt = format!(
+            "SELECT count(1) FROM sqlite_master WHERE type='table' AND name='inbox_{}'",
+            actor_id
+        );
+        let mut stmt = self.conn.inner.prepare(&stmt)?;
+        let mut rows = stmt.query([])?;
+        if let Some(row) = rows.next()? {
+            let value: usize = row.get(0)?;
+            if value == 1 {
+                let stmt = format!("DELETE FROM inbox_{}", actor_id);
+                match self.conn.inner.execute(&stmt, []) {
+                    Ok(deleted) => println!("Rows deleted: {}", deleted),
+                    Err(err) => println!("Error occured: {}", err),
+                }
+            } else {
+                println!("Table does not exist");
+            }
+        }
+        Ok(())
+    }
+
+    pub(crate) fn delete_actor_messages(
+        &mut self,
+        actor_id: &str,
+        msg_ids: Vec<&str>,
+    ) -> std::io::Result<()> {
+        let msg_ids: String = msg_ids
+            .into_iter()
+            .map(|id| {
+                let mut s = String::from("'");
+                s += id;
+                s += "'";
+                s
+            })
+            .collect::<Vec<_>>()
+            .join(",");
+        let stmt = format!(
+            "DELETE FROM messages WHERE actor_id = '{}' AND msg_id in ({})",
+            actor_id, msg_ids
+        );
+        let _rs = self.conn.inner.execute_batch(TX_BEGIN).map_err(sql_to_io);
+        let mut stmt = self.conn.inner.prepare(&stmt).map_err(sql_to_io)?;
+        let _rs = stmt.execute(params![]).map_err(sql_to_io);
+        let _rs = self.conn.inner.execute_batch(TX_COMMIT).map_err(sql_to_io);
+        Ok(())
+    }
+
+    pub(crate) fn actor_is_present(&mut self, actor_id: &str) -> rusqlite::Result<()> {
+        let mut stmt = self.conn.inner.prepare_cached(ACTOR_ROWID)?;
+        let status = stmt
+            .query(rusqlite::params![actor_id])?
+            .count()
+            .and_then(|c| if c == 1 { Ok(()) } else { Err(InvalidQuery) });
+        status
+    }
+
+    pub(crate) fn min_msg_seq(
+        &mut self,
+        actor_id: &str,
+    ) -> rusqlite::Result<Option<(i64, i64, i64)>> {
+        let mut stmt = self.conn.inner.prepare_cached(MIN_MSG_SEQ)?;
+        let mut rows = stmt.query(rusqlite::params![actor_id])?;
+        if let Some(row) = rows.next()? {
+            let seq: i64 = row.get(0)?;
+            let rowid: i64 = row.get(1)?;
+            let row_id: i64 = row.get(2)?;
+            return Ok(Some((seq, rowid, row_id)));
+        }
+        Ok(None)
+    }
+
+    pub(crate) fn update_events(&mut self, row_id: i64) -> rusqlite::Result<()> {
+        let mut stmt = self.conn.inner.prepare_cached("UPDATE_EVENTS")?;
+        stmt.execute(params![row_id])?;
+        Ok(())
+    }
+
+    pub(crate) fn into_inbox(&mut self, msg: Msg) -> rusqlite::Result<()> {
+        let stmt = Self::message_insert_stmt(&mut self.message_insert_stmt);
+        let mut stmt = self.conn.inner.prepare_cached(stmt).ok();
+        let msg_id = msg.id_as_string();
+        let actor_id = msg.get_to_id().to_string();
+        let codec = Config::get_shared().codec();
+        let bytes = msg.encode(codec).map_err(encode_failure)?;
+        match stmt {
+            Some(ref mut s) => s.execute(
+                named_params! {":actor_id": &actor_id as &dyn ToSql, ":msg_id": &msg_id as &dyn ToSql, ":actor_id": &actor_id as &dyn ToSql, ":msg": &bytes as &dyn ToSql, ":expires_at": &msg.expires_at() as &dyn ToSql, ":codec": &codec as &dyn ToSql, ":msg_version": &MSG_VERSION as &dyn ToSql },
+            )?,
+            None => panic!(),
+        };
+        Ok(())
+    }
+
+    pub(crate) fn actor_messages(&mut self, actor_id: &str) -> rusqlite::Result<Vec<Msg>> {
+        let stmt = self
+            .inbox_select_stmts
+            .entry(actor_id.to_string())
+            .or_insert_with(|| {
+                format!(
+                    "SELECT msg, codec, msg_version FROM messages where actor_id = {} ORDER BY rowid ASC LIMIT {}",
+                    actor_id, FETCH_LIMIT
+                )
+            });
+
+        let mut stmt = self.conn.inner.prepare_cached(stmt).ok();
+        let mut msgs = Vec::with_capacity(FETCH_LIMIT);
+        match stmt {
+            Some(ref mut s) => {
+                //let rows = s.query_and_then([], |row| row.get::<_, Msg>(0))?;
+                let rows = s.query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))?;
+                for row in rows {
+                    let (value, codec, version): (Value, u8, u8) = row?;
+                    msgs.push(value_to_msg(value, codec, version).map_err(decode_failure)?);
+                }
+            }
+            None => {
+                panic!("Error draining inbox - CachedStatement not found")
+            }
+        }
+        Ok(msgs)
+    }
+
+    pub(crate) fn rowids_of(&mut self, actor_id: &str) -> rusqlite::Result<Vec<i64>> {
+        let stmt = format!(
+            "SELECT rowid FROM messages WHERE actor_id = '{}' ORDER BY rowid ASC",
+            actor_id
+        );
+        let mut stmt = self.conn.inner.prepare_cached(&stmt)?;
+        let mut rows = stmt.query([])?;
+        let mut rowids = Vec::new();
+        while let Some(row) = rows.next()? {
+            rowids.push(row.get(0)?);
+        }
+        Ok(rowids)
+    }
+
+    pub(crate) fn messages_from(
+        &mut self,
+        actor_id: &str,
+        start_at: i64,
+    ) -> rusqlite::Result<Vec<Msg>> {
+        let stmt =format!("SELECT msg FROM inbox WHERE actor_id = '{}' and rowid >= {} ORDER BY rowid ASC LIMIT {}", actor_id, start_at, FETCH_LIMIT);
+        let mut stmt = self.conn.inner.prepare_cached(&stmt).ok();
+        let mut msgs = Vec::with_capacity(FETCH_LIMIT);
+        match stmt {
+            Some(ref mut s) => {
+                let rows = s.query_map([], |row| row.get(0))?;
+                for row in rows {
+                    let value: Value = row?;
+                    msgs.push(value_to_msg(value, codec::Bincode::ID, 0).map_err(decode_failure)?);
+                }
+            }
+            None => panic!("Error reading inbox!"),
+        }
+        Ok(msgs)
+    }
+
+    pub(crate) fn into_inbox_batch(
+        &mut self,
+        msgs: impl Iterator<Item = Msg>,
+    ) -> rusqlite::Result<()> {
+        self.conn.inner.execute_batch(TX_BEGIN)?;
+        let stmt = Self::message_insert_stmt(&mut self.message_insert_stmt);
+        let mut stmt = self.conn.inner.prepare_cached(stmt).ok();
+        let codec = Config::get_shared().codec();
+        match stmt {
+            Some(ref mut s) => {
+                for msg in msgs {
+                    let bytes = msg.encode(codec).map_err(encode_failure)?;
+                    let actor_id = msg.get_to_id().to_string();
+                    let _status = s.execute(named_params! { ":actor_id": &actor_id as &dyn ToSql, ":msg_id": &msg.id_as_string() as &dyn ToSql, ":actor_id": &actor_id as &dyn ToSql, ":msg": &bytes as &dyn ToSql, ":expires_at": &msg.expires_at() as &dyn ToSql, ":codec": &codec as &dyn ToSql, ":msg_version": &MSG_VERSION as &dyn ToSql })?;
+                }
+            }
+            None => panic!(),
+        };
+        self.conn.inner.execute_batch(TX_COMMIT)?;
+        Ok(())
+    }
+
+    fn message_insert_stmt(stmt: &mut Option<String>) -> &str {
+        match stmt {
+            Some(ref s) => s,
+            None => {
+                *stmt = Some(INSERT_INTO_MESSAGES.to_string());
+                INSERT_INTO_MESSAGES
+            }
+        }
+    }
+}
+
+impl StorageBackend for SqliteBackend {
+    //Brings the schema up to date - refuses databases migrated by a newer build
+    fn setup(&mut self) -> Result<()> {
+        let version = migrations::migrate(&mut self.conn.inner)?;
+        println!("Set up arrows schema - version {}", version);
+        Ok(())
+    }
+
+    fn watch(&mut self, notifier: Notifier) {
+        self.conn
+            .inner
+            .update_hook(None::<fn(Action, &str, &str, i64)>);
+        self.conn
+            .inner
+            .update_hook(Some(move |action: Action, _db: &str, tbl: &str, row_id| {
+                let tbl_of_interest = tbl.starts_with(TABLE_MESSAGES);
+                if action == Action::SQLITE_INSERT && tbl_of_interest {
+                    notifier.stored(row_id);
+                }
+            }));
+    }
+
+    fn insert_messages(&mut self, msgs: Vec<Msg>) -> Result<Vec<u64>> {
+        //Commit any active tx to avoid nested transaction issue
+        match self.conn.inner.execute_batch(TX_COMMIT) {
+            Ok(_any_tx) => (),
+            //Err(err) => println!("{}", err),
+            Err(_err) => (),
+        }
+        let stmt = Self::message_insert_stmt(&mut self.message_insert_stmt);
+        //Rolled back on drop - unless committed
+        let tx = self.conn.inner.transaction()?;
+        let codec = Config::get_shared().codec();
+        let now = retention::now_secs();
+        let since = now - Config::get_shared().dedup_window() as i64;
+        let mut duplicates = Vec::new();
+        {
+            let mut insert = tx.prepare_cached(stmt)?;
+            let mut seen = tx.prepare_cached(INGESTED_SEEN)?;
+            let mut ingested = tx.prepare_cached(INGESTED_INSERT)?;
+            for msg in msgs {
+                let actor_id = msg.get_to_id().to_string();
+                let msg_id = msg.id_as_string();
+                if seen.query_row(params![actor_id, msg_id, since], |row| row.get(0))? {
+                    duplicates.push(*msg.get_id());
+                    continue;
+                }
+                let bytes = msg.encode(codec)?;
+                insert.execute(named_params! {
+                    ":actor_id": &actor_id as &dyn ToSql,
+                    ":msg_id": &msg_id as &dyn ToSql,
+                    ":msg": &bytes as &dyn ToSql,
+                    ":expires_at": &msg.expires_at() as &dyn ToSql,
+                    ":codec": &codec as &dyn ToSql,
+                    ":msg_version": &MSG_VERSION as &dyn ToSql,
+                })?;
+                ingested.execute(params![actor_id, msg_id, now])?;
+            }
+        }
+        tx.commit()?;
+        Ok(duplicates)
+    }
+
+    fn forget_ingested(&mut self, before: i64) -> Result<usize> {
+        Ok(self.conn.inner.execute(INGESTED_DELETE, params![before])?)
+    }
+
+    fn from_messages(&mut self, rowids: Vec<i64>) -> Result<Vec<RichMail>> {
+        let rowids = rowids
+            .iter()
+            .map(|id| id.to_string())
+            .collect::<Vec<_>>()
+            .join(",");
+        let stmt = format!(
+            "SELECT msg, inbound, msg_seq, codec, msg_version FROM messages WHERE rowid IN ({})",
+            rowids
+        );
+        let mut stmt = self.conn.inner.prepare(&stmt)?;
+        let mut rows = stmt.query([])?;
+        let mut msgs = Vec::new();
+        while let Some(row) = rows.next()? {
+            let value: Value = row.get(0)?;
+            let inbound: i64 = row.get(1)?;
+            let msg_seq: i64 = row.get(2)?;
+            let msg = value_to_msg(value, row.get(3)?, row.get(4)?)?;
+            let to = msg.get_to().clone();
+            msgs.push(RichMail::RichContent(
+                Mail::Trade(msg),
+                inbound == 1,
+                msg_seq,
+                None,
+                to,
+            ));
+        }
+        Ok(msgs)
+    }
+
+    fn replay_messages(
+        &mut self,
+        actor_id: &str,
+        from_seq: i64,
+        until_seq: i64,
+    ) -> Result<Vec<(i64, Msg)>> {
+        let mut stmt = self.conn.inner.prepare_cached(REPLAY_MESSAGES)?;
+        let rows = stmt.query_map(
+            params![actor_id, from_seq, until_seq, FETCH_LIMIT as i64],
+            |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?)),
+        )?;
+        let mut msgs = Vec::new();
+        for row in rows {
+            let (msg_seq, value, codec, version): (i64, Value, u8, u8) = row?;
+            msgs.push((msg_seq, value_to_msg(value, codec, version)?));
+        }
+        Ok(msgs)
+    }
+
+    fn max_msg_seq(&mut self, actor_id: &str) -> Result<i64> {
+        let mut stmt = self.conn.inner.prepare_cached(MAX_MSG_SEQ)?;
+        Ok(stmt.query_row(params![actor_id], |row| row.get(0))?)
+    }
+
+    fn delete_message(&mut self, msg: &Msg) -> Result<usize> {
+        let mut stmt = self.conn.inner.prepare_cached(MESSAGE_DELETE)?;
+        Ok(stmt.execute(params![msg.get_to_id().to_string(), msg.id_as_string()])?)
+    }
+
+    fn clear_expiry(&mut self, msg: &Msg) -> Result<usize> {
+        let mut stmt = self.conn.inner.prepare_cached(MESSAGE_CLEAR_EXPIRY)?;
+        Ok(stmt.execute(params![msg.get_to_id().to_string(), msg.id_as_string()])?)
+    }
+
+    fn take_expired_messages(&mut self, now: i64) -> Result<Vec<Msg>> {
+        let tx = self.conn.inner.transaction()?;
+        let mut expired = Vec::new();
+        {
+            let mut stmt = tx.prepare_cached(EXPIRED_MESSAGES)?;
+            let rows = stmt.query_map(params![now, FETCH_LIMIT as i64], |row| {
+                Ok((row.get(0)?, row.get(1)?, row.get(2)?))
+            })?;
+            for row in rows {
+                let (value, codec, version) = row?;
+                expired.push(value_to_msg(value, codec, version)?);
+            }
+        }
+        for msg in &expired {
+            tx.execute(
+                MESSAGE_DELETE,
+                params![msg.get_to_id().to_string(), msg.id_as_string()],
+            )?;
+        }
+        tx.commit()?;
+        Ok(expired)
+    }
+
+    fn persist_events(&mut self, rowids: Vec<i64>) -> Result<Vec<i64>> {
+        let tx = self.conn.inner.transaction()?;
+        for row_id in &rowids {
+            tx.execute(EVENTS_INSERT, params![row_id])?;
+        }
+        tx.commit()?;
+        Ok(rowids)
+    }
+
+    fn read_events(&mut self) -> Result<Vec<i64>> {
+        let mut stmt = self.conn.inner.prepare_cached(EVENTS_SELECT)?;
+        let mut rows = stmt.query([])?;
+        let mut events = Vec::new();
+        while let Some(row) = rows.next()? {
+            events.push(row.get(0)?);
+        }
+        Ok(events)
+    }
+
+    fn save_producer(
+        &mut self,
+        identity: &str,
+        addr: Addr,
+        actor_def: &str,
+        supervision: &str,
+        parent: Option<&Addr>,
+        event_sourced: bool,
+    ) -> Result<()> {
+        let snapshot_seq = event_sourced.then_some(0);
+        let parent_id = parent.map(|parent| parent.get_id().to_string());
+        let parent_addr = parent.map(Addr::as_bytes);
+        let mut stmt = self.conn.inner.prepare_cached(ACTOR_DEF_INSERT).ok();
+        match stmt {
+            Some(ref mut s) => s.execute(
+                named_params! { ":actor_id": &identity as &dyn ToSql,":actor_name": addr.get_name() as &dyn ToSql, ":actor_def": &actor_def as &dyn ToSql, ":supervision": &supervision as &dyn ToSql, ":parent_id": &parent_id as &dyn ToSql, ":snapshot_seq": &snapshot_seq as &dyn ToSql, ":addr": &addr.as_bytes() as &dyn ToSql, ":parent_addr": &parent_addr as &dyn ToSql },
+            )?,
+            None => panic!(),
+        };
+        Ok(())
+    }
+
+    fn remove_actor(&mut self, actor_id: &str) -> Result<usize> {
+        let mut stmt = self.conn.inner.prepare_cached(DELETE_ACTOR)?;
+        Ok(stmt.execute(params![actor_id])?)
+    }
+
+    fn retrieve_actor_def(&mut self, actor_id: &str) -> Result<Option<ActorDef>> {
+        let mut stmt = self.conn.inner.prepare_cached(ACTOR_DEF)?;
+        let mut rows = stmt.query(rusqlite::params![actor_id])?;
+        if let Some(row) = rows.next()? {
+            let parent_id: Option<String> = row.get(5)?;
+            let parent_name: Option<String> = row.get(6)?;
+            let parent_addr: Option<Vec<u8>> = row.get(7)?;
+            let parent = parent_id.zip(parent_name).map(|(parent_id, parent_name)| {
+                (parent_id, stored_addr(parent_addr, &parent_name))
+            });
+            return Ok(Some(ActorDef {
+                name: row.get(0)?,
+                producer: row.get(1)?,
+                msg_seq: row.get(2)?,
+                state: row.get(3)?,
+                supervision: row.get(4)?,
+                parent,
+            }));
+        }
+        Ok(None)
+    }
+
+    fn children_of(&mut self, actor_id: &str) -> Result<Vec<Addr>> {
+        let mut stmt = self.conn.inner.prepare_cached(ACTOR_CHILDREN)?;
+        let rows = stmt.query_map(params![actor_id], |row| {
+            let name: String = row.get(0)?;
+            Ok(stored_addr(row.get(1)?, &name))
+        })?;
+        Ok(rows.collect::<rusqlite::Result<_>>()?)
+    }
+
+    fn update_actor_event_seq(&mut self, actor_id: &str, msg_seq: i64) -> Result<()> {
+        let mut stmt = self.conn.inner.prepare_cached(UPDATE_ACTOR_EVENT_SEQ)?;
+        stmt.execute(params![msg_seq, actor_id])?;
+        Ok(())
+    }
+
+    fn save_state(&mut self, actor_id: &str, seq: i64, state: &[u8]) -> Result<()> {
+        let mut stmt = self.conn.inner.prepare_cached(UPDATE_ACTOR_STATE)?;
+        stmt.execute(params![state, seq, actor_id])?;
+        Ok(())
+    }
+
+    fn record_dead_letter(&mut self, msg: &Msg, reason: &str, recorded_at: i64) -> Result<()> {
+        let addr = msg.get_to().clone().unwrap_or_default();
+        let codec = Config::get_shared().codec();
+        let mut stmt = self.conn.inner.prepare_cached(DEAD_LETTER_INSERT)?;
+        stmt.execute(named_params! {
+            ":actor_id": &addr.get_id().to_string() as &dyn ToSql,
+            ":msg_id": &msg.id_as_string() as &dyn ToSql,
+            ":addr": &addr.as_bytes() as &dyn ToSql,
+            ":msg": &msg.encode(codec)? as &dyn ToSql,
+            ":reason": &reason as &dyn ToSql,
+            ":recorded_at": &recorded_at as &dyn ToSql,
+            ":codec": &codec as &dyn ToSql,
+            ":msg_version": &MSG_VERSION as &dyn ToSql,
+        })?;
+        Ok(())
+    }
+
+    fn dead_letters(&mut self) -> Result<Vec<DeadLetter>> {
+        let mut stmt = self.conn.inner.prepare_cached(DEAD_LETTERS_SELECT)?;
+        let rows = stmt.query_map([], row_to_dead_letter)?;
+        Ok(rows.collect::<rusqlite::Result<_>>()?)
+    }
+
+    fn dead_letter(&mut self, id: i64) -> Result<Option<DeadLetter>> {
+        let mut stmt = self.conn.inner.prepare_cached(DEAD_LETTER_SELECT)?;
+        let mut rows = stmt.query_map(params![id], row_to_dead_letter)?;
+        Ok(rows.next().transpose()?)
+    }
+
+    fn delete_dead_letters(&mut self, id: Option<i64>) -> Result<usize> {
+        let deleted = match id {
+            Some(id) => self.conn.inner.execute(DEAD_LETTER_DELETE, params![id])?,
+            None => self.conn.inner.execute(DEAD_LETTERS_DELETE, [])?,
+        };
+        Ok(deleted)
+    }
+
+    fn save_schedule(&mut self, id: &str, msg: &Msg, due_at: i64, every: i64) -> Result<()> {
+        let codec = Config::get_shared().codec();
+        let mut stmt = self.conn.inner.prepare_cached(SCHEDULE_INSERT)?;
+        stmt.execute(named_params! {
+            ":id": &id as &dyn ToSql,
+            ":actor_id": &msg.get_to_id().to_string() as &dyn ToSql,
+            ":msg": &msg.encode(codec)? as &dyn ToSql,
+            ":due_at": &due_at as &dyn ToSql,
+            ":every": &every as &dyn ToSql,
+            ":codec": &codec as &dyn ToSql,
+            ":msg_version": &MSG_VERSION as &dyn ToSql,
+        })?;
+        Ok(())
+    }
+
+    fn delete_schedule(&mut self, id: &str) -> Result<usize> {
+        Ok(self.conn.inner.execute(SCHEDULE_DELETE, params![id])?)
+    }
+
+    fn take_due_schedules(&mut self, now: i64) -> Result<Vec<Msg>> {
+        let tx = self.conn.inner.transaction()?;
+        let mut due = Vec::new();
+        {
+            let mut stmt = tx.prepare_cached(SCHEDULES_DUE)?;
+            let rows = stmt.query_map(params![now, FETCH_LIMIT as i64], |row| {
+                Ok((
+                    row.get(0)?,
+                    row.get(1)?,
+                    row.get(2)?,
+                    row.get(3)?,
+                    row.get(4)?,
+                ))
+            })?;
+            for row in rows {
+                let (id, value, every, codec, version): (String, Value, i64, u8, u8) = row?;
+                due.push((id, value_to_msg(value, codec, version)?, every));
+            }
+        }
+        for (id, _, every) in &due {
+            if *every > 0 {
+                tx.execute(SCHEDULE_RESCHEDULE, params![now + every, id])?;
+            } else {
+                tx.execute(SCHEDULE_DELETE, params![id])?;
+            }
+        }
+        tx.commit()?;
+        Ok(due.into_iter().map(|(_, msg, _)| msg).collect())
+    }
+
+    fn save_outbound(&mut self, msgs: &[Msg], now: i64) -> Result<()> {
+        let codec = Config::get_shared().codec();
+        let tx = self.conn.inner.transaction()?;
+        {
+            let mut stmt = tx.prepare_cached(OUTBOX_INSERT)?;
+            for msg in msgs {
+                let host = match backend::host_of(msg) {
+                    Some(host) => host,
+                    None => continue,
+                };
+                stmt.execute(named_params! {
+                    ":host": &host as &dyn ToSql,
+                    ":msg_id": &msg.id_as_string() as &dyn ToSql,
+                    ":msg": &msg.encode(codec)? as &dyn ToSql,
+                    ":codec": &codec as &dyn ToSql,
+                    ":msg_version": &MSG_VERSION as &dyn ToSql,
+                    ":due_at": &now as &dyn ToSql,
+                })?;
+            }
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    fn take_due_outbound(&mut self, now: i64) -> Result<Vec<Msg>> {
+        let tx = self.conn.inner.transaction()?;
+        let mut due = Vec::new();
+        {
+            let mut stmt = tx.prepare_cached(OUTBOX_DUE)?;
+            let rows = stmt.query_map(params![now, FETCH_LIMIT as i64], |row| {
+                Ok((
+                    row.get(0)?,
+                    row.get(1)?,
+                    row.get(2)?,
+                    row.get(3)?,
+                    row.get(4)?,
+                ))
+            })?;
+            for row in rows {
+                let (id, value, codec, version, attempts): (i64, Value, u8, u8, i64) = row?;
+                due.push((id, value_to_msg(value, codec, version)?, attempts));
+            }
+        }
+        for (id, _, attempts) in &due {
+            let due_at = backend::redelivery_at(now, *attempts);
+            tx.execute(OUTBOX_RETRY, params![attempts + 1, due_at, id])?;
+        }
+        tx.commit()?;
+        Ok(due.into_iter().map(|(_, msg, _)| msg).collect())
+    }
+
+    fn ack_outbound(&mut self, host: &str, msg_ids: &[u64]) -> Result<usize> {
+        let tx = self.conn.inner.transaction()?;
+        let mut acked = 0;
+        {
+            let mut stmt = tx.prepare_cached(OUTBOX_ACK)?;
+            for msg_id in msg_ids {
+                acked += stmt.execute(params![host, msg_id.to_string()])?;
+            }
+        }
+        tx.commit()?;
+        Ok(acked)
+    }
+
+    fn subscribe(&mut self, topic: &str, addr: &Addr) -> Result<usize> {
+        let mut stmt = self.conn.inner.prepare_cached(SUBSCRIPTION_INSERT)?;
+        Ok(stmt.execute(params![topic, addr.get_id().to_string(), addr.as_bytes()])?)
+    }
+
+    fn unsubscribe(&mut self, topic: &str, actor_id: &str) -> Result<usize> {
+        let mut stmt = self.conn.inner.prepare_cached(SUBSCRIPTION_DELETE)?;
+        Ok(stmt.execute(params![topic, actor_id])?)
+    }
+
+    fn drop_subscriptions(&mut self, actor_id: &str) -> Result<usize> {
+        let mut stmt = self
+            .conn
+            .inner
+            .prepare_cached(SUBSCRIPTIONS_OF_ACTOR_DELETE)?;
+        Ok(stmt.execute(params![actor_id])?)
+    }
+
+    fn subscribers(&mut self, topic: &str) -> Result<Vec<Addr>> {
+        let mut stmt = self.conn.inner.prepare_cached(SUBSCRIBERS)?;
+        let rows = stmt.query_map(params![topic], |row| row.get(0))?;
+        let mut subscribers = Vec::new();
+        for row in rows {
+            subscribers.push(value_to_addr(row?));
+        }
+        Ok(subscribers)
+    }
+
+    fn save_pool(
+        &mut self,
+        pool_id: &str,
+        name: &str,
+        producer: &str,
+        distribution: &str,
+        size: usize,
+    ) -> Result<()> {
+        let mut stmt = self.conn.inner.prepare_cached(POOL_UPSERT)?;
+        stmt.execute(named_params! {
+            ":pool_id": &pool_id as &dyn ToSql,
+            ":pool_name": &name as &dyn ToSql,
+            ":producer": &producer as &dyn ToSql,
+            ":distribution": &distribution as &dyn ToSql,
+            ":size": &(size as i64) as &dyn ToSql,
+        })?;
+        Ok(())
+    }
+
+    fn pool_def(&mut self, pool_id: &str) -> Result<Option<PoolDef>> {
+        let mut stmt = self.conn.inner.prepare_cached(POOL_DEF)?;
+        let mut rows = stmt.query(params![pool_id])?;
+        match rows.next()? {
+            Some(row) => Ok(Some(PoolDef {
+                name: row.get(0)?,
+                producer: row.get(1)?,
+                distribution: row.get(2)?,
+                size: row.get::<_, i64>(3)? as usize,
+                next: row.get::<_, i64>(4)? as usize,
+                msg_seq: row.get(5)?,
+            })),
+            None => Ok(None),
+        }
+    }
+
+    fn resize_pool(&mut self, pool_id: &str, size: usize) -> Result<usize> {
+        let mut stmt = self.conn.inner.prepare_cached(POOL_RESIZE)?;
+        Ok(stmt.execute(params![size as i64, pool_id])?)
+    }
+
+    fn save_pool_cursor(&mut self, pool_id: &str, next: usize, msg_seq: i64) -> Result<()> {
+        let mut stmt = self.conn.inner.prepare_cached(POOL_CURSOR)?;
+        stmt.execute(params![next as i64, msg_seq, pool_id])?;
+        Ok(())
+    }
+
+    fn delete_pool(&mut self, pool_id: &str) -> Result<usize> {
+        Ok(self.conn.inner.execute(POOL_DELETE, params![pool_id])?)
+    }
+
+    fn compact(&mut self, retention: Retention, vacuum: bool) -> Result<usize> {
+        Ok(retention::compact(&mut self.conn.inner, retention, vacuum)?)
+    }
+
+    fn compact_actor(&mut self, actor_id: &str) -> Result<usize> {
+        Ok(retention::compact_actor(&self.conn.inner, actor_id)?)
+    }
+}
+
+impl From<Action> for DBAction {
+    fn from(action: Action) -> Self {
+        match action {
+            Action::SQLITE_DELETE => DBAction::Delete,
+            Action::SQLITE_INSERT => DBAction::Insert,
+            Action::SQLITE_UPDATE => DBAction::Update,
+            _ => DBAction::Unknown,
+        }
+    }
+}
+
+fn sql_to_io(err: rusqlite::Error) -> std::io::Error {
+    eprintln!("rusqlite::Error has occured: {:?}", err);
+    Error::other("rusqlite error")
+}
+
+//Decode a stored message - with the codec and in the layout version it was recorded with
+pub(crate) fn value_to_msg(v: Value, codec: u8, version: u8) -> crate::Result<Msg> {
+    match v {
+        Value::Blob(bytes) => envelope::decode_msg(codec, version, &bytes),
+        _ => Err(crate::Error::InvalidData),
+    }
+}
+
+//Messages that can not be encoded/decoded surface as rusqlite conversion errors
+fn encode_failure(err: crate::Error) -> rusqlite::Error {
+    rusqlite::Error::ToSqlConversionFailure(err.to_string().into())
+}
+
+fn decode_failure(err: crate::Error) -> rusqlite::Error {
+    rusqlite::Error::FromSqlConversionFailure(0, Type::Blob, err.to_string().into())
+}
+
+fn row_to_dead_letter(row: &rusqlite::Row<'_>) -> rusqlite::Result<DeadLetter> {
+    Ok(DeadLetter::new(
+        row.get(0)?,
+        value_to_addr(row.get(1)?),
+        value_to_msg(row.get(2)?, row.get(5)?, row.get(6)?).map_err(decode_failure)?,
+        row.get(3)?,
+        row.get(4)?,
+    ))
+}
+
+//Address of an actor as persisted - actors defined before addresses were recorded are local
+fn stored_addr(bytes: Option<Vec<u8>>, name: &str) -> Addr {
+    bytes
+        .and_then(|bytes| from_bytes::<'_, Addr>(&bytes).ok())
+        .unwrap_or_else(|| Addr::new(name))
+}
+
+pub(crate) fn value_to_addr(v: Value) -> Addr {
+    if let Value::Blob(bytes) = v {
+        return from_bytes::<'_, Addr>(&bytes).unwrap_or_default();
+    }
+    Addr::default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::mail::Msg;
+    use crate::Addr;
+    use rand::{thread_rng, Rng};
+    use std::iter::repeat_n;
+
+    fn store_messages(actor_name: &str) -> (String, String) {
+        //Add randomness to the message text
+        let mut rng = thread_rng();
+        let random: u64 = rng.gen();
+        let message = format!("Actor message-{}", random);
+        //Generate as many messages as required to flush buffer
+        let messages = repeat_n(&message, Config::get_shared().db_buff_size());
+        let messages: Vec<_> = messages
+            .map(|msg| Msg::with_text(msg, "from", actor_name))
+            .collect();
+
+        let mut store = SqliteBackend::new();
+        let _ = store.setup();
+        //Persist messages
+        let rs = store.insert_messages(messages);
+        assert!(rs.is_ok());
+        let actor_id = Addr::new("actor").get_id().to_string();
+        //Return random generated message and actor_id for actor_name
+        (message.to_string(), actor_id)
+    }
+    #[test]
+    fn select_from_inbox_test() -> Result<()> {
+        let (message, actor_id) = store_messages("actor");
+
+        let mut store = SqliteBackend::new();
+        let _ = store.setup();
+
+        let rowids = store.rowids_of(&actor_id).unwrap();
+        let msgs = store.from_messages(rowids).unwrap();
+        let count = msgs
+            .iter()
+            .filter(|msg| msg.mail().message().as_text() == Some(&message))
+            .count();
+        assert!(count == Config::get_shared().db_buff_size());
+        Ok(())
+    }
+
+    #[test]
+    fn read_message_from_test() {
+        let (message, actor_id) = store_messages("actor");
+        let mut store = SqliteBackend::new();
+        let _ = store.setup();
+        let mut rowids = store.rowids_of(&actor_id).unwrap();
+        let last = rowids.pop().unwrap();
+        let msgs = store.messages_from(&actor_id, last).unwrap();
+
+        assert!(msgs[0].as_text() == Some(&message));
+    }
+
+    #[test]
+    fn into_inbox_no_batch_test_1() {
+        let _num = 100;
+        //InvalidParameterCount
+        //Err(SqliteFailure(
+        //Err(ToSqlConversionFailure(TryFromIntError
+        //Err(SqliteFailure(Error { code: ReadOnly, extended_code: 1032 }
+        //Err(SqliteFailure(Error { code: TypeMismatch
+        //Err(SqliteFailure(Error { code: ConstraintViolation, extended_code: 1555 },
+        // Some("UNIQUE constraint failed: actors.actor_id")
+        //let _status = into_inbox_no_batch_func(num);
+    }
+
+    fn in_memory() -> SqliteBackend {
+        let inner = rusqlite::Connection::open_in_memory().unwrap();
+        let mut store = SqliteBackend {
+            conn: DBConnection { inner },
+            message_insert_stmt: None,
+            inbox_select_stmts: HashMap::new(),
+            actor_create_stmts: HashMap::new(),
+        };
+        store.setup().unwrap();
+        store
+    }
+
+    #[test]
+    fn insert_messages_dedup_test_1() {
+        let mut store = in_memory();
+        let msg = Msg::with_text("once", "from", "dedup");
+        assert!(store.insert_messages(vec![msg.clone()]).unwrap().is_empty());
+        let duplicates = vec![*msg.get_id()];
+        assert_eq!(
+            store.insert_messages(vec![msg.clone()]).unwrap(),
+            duplicates
+        );
+        //Compacted away - remembered for as long as the dedup window lasts
+        store
+            .conn
+            .inner
+            .execute("DELETE FROM messages", [])
+            .unwrap();
+        let window = Config::get_shared().dedup_window() as i64;
+        let age = |store: &mut SqliteBackend, secs: i64| {
+            let at = retention::now_secs() - secs;
+            store
+                .conn
+                .inner
+                .execute("UPDATE ingested SET ingested_at = ?", [at])
+                .unwrap();
+        };
+        age(&mut store, window - 60);
+        assert_eq!(
+            store.insert_messages(vec![msg.clone()]).unwrap(),
+            duplicates
+        );
+        age(&mut store, window + 60);
+        assert!(store.insert_messages(vec![msg.clone()]).unwrap().is_empty());
+
+        //A failed batch is rolled back as a whole
+        store.conn.inner.execute("DROP TABLE ingested", []).unwrap();
+        let fresh = Msg::with_text("fresh", "from", "dedup");
+        assert!(store.insert_messages(vec![fresh]).is_err());
+        assert!(store.conn.inner.is_autocommit());
+        let count: i64 = store
+            .conn
+            .inner
+            .query_row("SELECT count(1) FROM messages", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(count, 1);
+    }
+
+    #[test]
+    fn undecodable_message_test_1() {
+        let mut store = in_memory();
+        let msg = Msg::with_text("garbled", "from", "undecodable");
+        store.insert_messages(vec![msg.clone()]).unwrap();
+        let actor_id = msg.get_to_id().to_string();
+        let rowids = store.rowids_of(&actor_id).unwrap();
+        assert_eq!(store.from_messages(rowids.clone()).unwrap().len(), 1);
+        store
+            .conn
+            .inner
+            .execute("UPDATE messages SET codec = 99", [])
+            .unwrap();
+        assert!(matches!(
+            store.from_messages(rowids),
+            Err(crate::Error::UnknownCodec(99))
+        ));
+        assert!(store.actor_messages(&actor_id).is_err());
+    }
+
+    #[test]
+    fn children_of_test_1() {
+        let mut store = in_memory();
+        let parent = Addr::remote("remote_parent", "10.0.0.1:7272");
+        let child = Addr::remote("remote_child", "10.0.0.1:7272");
+        let (parent_id, child_id) = (parent.get_id().to_string(), child.get_id().to_string());
+        store
+            .save_producer(&parent_id, parent.clone(), "{}", "", None, false)
+            .unwrap();
+        store
+            .save_producer(&child_id, child.clone(), "{}", "", Some(&parent), false)
+            .unwrap();
+        let def = store.retrieve_actor_def(&child_id).unwrap().unwrap();
+        assert_eq!(def.parent, Some((parent_id.clone(), parent)));
+        assert_eq!(store.children_of(&parent_id).unwrap(), [child]);
+        //Actors defined before addresses were recorded are local
+        store
+            .conn
+            .inner
+            .execute("UPDATE actors SET addr = NULL, parent_addr = NULL", [])
+            .unwrap();
+        let def = store.retrieve_actor_def(&child_id).unwrap().unwrap();
+        assert_eq!(
+            def.parent.map(|(_, addr)| addr),
+            Some(Addr::new("remote_parent"))
+        );
+        assert_eq!(
+            store.children_of(&parent_id).unwrap(),
+            [Addr::new("remote_child")]
+        );
+    }
+
+    #[test]
+    fn save_producer_1001() -> Result<()> {
+        let mut store = SqliteBackend::new();
+        let _ = store.setup();
+        let addr = Addr::new("1001");
+        let identity = "1001";
+        let insert = store.save_producer(
+            identity,
+            addr,
+            r#"{"new_actor_builder":null}"#,
+            "",
+            None,
+            false,
+        );
+        assert!(insert.is_ok());
+        Ok(())
+    }
+
+    #[test]
+    fn actor_is_present_1001_test_1() -> Result<()> {
+        let mut store = SqliteBackend::new();
+        let _ = store.setup();
+        let actor_id = "1001";
+        let present = store.actor_is_present(actor_id);
+        assert!(present.is_ok());
+        Ok(())
+    }
+
+    #[test]
+    fn retrieve_actor_def_1001_test_1() -> Result<()> {
+        let mut store = SqliteBackend::new();
+        let _ = store.setup();
+        let actor_id = "1001";
+        let actor_def = store.retrieve_actor_def(actor_id);
+        assert!(actor_def.is_ok());
+        Ok(())
+    }
+}